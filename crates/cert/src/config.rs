@@ -78,6 +78,27 @@ pub fn gen_send_config(
     Ok(quinn::ClientConfig::new(Arc::new(rustls_config)))
 }
 
+/// Generate a config for outgoing connections to a server, only accepting the
+/// permitted keys.
+///
+/// If no keys are permitted, any key is accepted.
+#[inline]
+pub fn gen_pinned_send_config(
+    public_address: IpAddr,
+    key_pair: &KeyPair,
+    permitted_keys: &[PublicKey],
+) -> Result<quinn::ClientConfig> {
+    let (cert, key) = gen_cert(public_address, key_pair)?;
+
+    let rustls_config = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()?
+        .with_custom_certificate_verifier(Arc::new(CertVerifier::with_keys(permitted_keys)))
+        .with_single_cert(vec![cert], key)?;
+    Ok(quinn::ClientConfig::new(Arc::new(rustls_config)))
+}
+
 /// Generate a config for incoming connections.
 #[inline]
 pub fn gen_recv_config(
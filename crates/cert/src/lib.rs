@@ -16,6 +16,6 @@ mod config;
 mod error;
 mod verify;
 
-pub use config::{gen_configs, gen_recv_config, gen_send_config};
+pub use config::{gen_configs, gen_pinned_send_config, gen_recv_config, gen_send_config};
 pub use error::{Error, Result};
 pub use verify::get_key_unchecked;
@@ -12,11 +12,16 @@ use x509_parser::{
     validate::{Validator, X509StructureValidator},
 };
 
-pub(crate) struct CertVerifier(Option<PublicKey>);
+/// Verifies certificates, only accepting the permitted keys if there are any.
+pub(crate) struct CertVerifier(Vec<PublicKey>);
 
 impl CertVerifier {
     pub(crate) fn new(permitted_key: Option<PublicKey>) -> Self {
-        Self(permitted_key)
+        Self(permitted_key.into_iter().collect())
+    }
+
+    pub(crate) fn with_keys(permitted_keys: &[PublicKey]) -> Self {
+        Self(permitted_keys.to_vec())
     }
 
     fn verify_cert(
@@ -41,10 +46,10 @@ impl CertVerifier {
 
         cert.verify_signature(Some(cert.public_key()))?;
 
-        if let Some(permitted_key) = self.0 {
+        if !self.0.is_empty() {
             match PublicKey::try_from(cert.public_key().subject_public_key.data) {
                 Ok(client_key) => {
-                    if client_key != permitted_key {
+                    if !self.0.contains(&client_key) {
                         return Err(Error::KeyNotPermitted);
                     }
                 }
@@ -161,6 +166,25 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn cert_verifier_pinned_keys() {
+        let ip_addr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let key_pair = KeyPair::from_entropy();
+        let (cert, _) = crate::config::gen_cert(ip_addr, &key_pair).unwrap();
+
+        let cert_verifier =
+            CertVerifier::with_keys(&[KeyPair::from_entropy().public, key_pair.public]);
+        cert_verifier
+            .verify_client_cert(&cert, &[], std::time::SystemTime::now())
+            .unwrap();
+
+        let cert_verifier = CertVerifier::with_keys(&[KeyPair::from_entropy().public]);
+        assert!(matches!(
+            cert_verifier.verify_client_cert(&cert, &[], std::time::SystemTime::now()),
+            Err(rustls::Error::InvalidCertificateSignature)
+        ));
+    }
+
     #[test]
     fn cert_verifier_invalid_unwanted() {
         let cert_verifier = CertVerifier::new(None);
@@ -1,8 +1,9 @@
 #![allow(deprecated)]
 
-use std::{net::IpAddr, path::PathBuf};
+use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
+use memorage_client::{fs::restore::ConflictPolicy, net::ServerAddress};
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -44,11 +45,16 @@ pub enum Command {
         data: Option<PathBuf>,
         /// Use the specified coordination server
         ///
-        /// The address can be IPv4 or IPv6.
+        /// The address can be an IPv4 or IPv6 address or a hostname, optionally
+        /// followed by a port.
         #[clap(short, long)]
-        server: Option<IpAddr>,
+        server: Option<ServerAddress>,
     },
     Backup {
+        /// Show the changes the backup would make without changing anything
+        /// on the peer
+        #[clap(long)]
+        dry_run: bool,
         /// Use the specified configuration file
         #[clap(short, long)]
         config: Option<PathBuf>,
@@ -57,9 +63,10 @@ pub enum Command {
         data: Option<PathBuf>,
         /// Use the specified coordination server
         ///
-        /// The address can be IPv4 or IPv6.
+        /// The address can be an IPv4 or IPv6 address or a hostname, optionally
+        /// followed by a port.
         #[clap(short, long)]
-        server: Option<IpAddr>,
+        server: Option<ServerAddress>,
     },
     Check {
         /// Use the specified configuration file
@@ -70,15 +77,45 @@ pub enum Command {
         data: Option<PathBuf>,
         /// Use the specified coordination server
         ///
-        /// The address can be IPv4 or IPv6.
+        /// The address can be an IPv4 or IPv6 address or a hostname, optionally
+        /// followed by a port.
         #[clap(short, long)]
-        server: Option<IpAddr>,
+        server: Option<ServerAddress>,
     },
     /// Retrieve stored files
     Retrieve {
         /// Place retrieved files in the specified directory
         #[clap(short, long)]
         output: Option<PathBuf>,
+        /// Restore files into the backup path
+        #[clap(long, conflicts_with = "output")]
+        in_place: bool,
+        /// What to do with files that already exist locally
+        ///
+        /// One of skip, overwrite, keep-both or if-changed. if-changed only
+        /// overwrites files whose contents differ from the backup.
+        #[clap(long, default_value = "skip")]
+        conflict: ConflictPolicy,
+        /// Use the specified configuration file
+        #[clap(short, long)]
+        config: Option<PathBuf>,
+        /// Use the specified data file
+        #[clap(short, long)]
+        data: Option<PathBuf>,
+        /// Use the specified coordination server
+        ///
+        /// The address can be an IPv4 or IPv6 address or a hostname, optionally
+        /// followed by a port.
+        #[clap(short, long)]
+        server: Option<ServerAddress>,
+    },
+    /// Rotate the master key used to encrypt stored files
+    ///
+    /// Generates a new master key phrase and wraps the keys of all stored files
+    /// under it. Files are not re-uploaded, and the identity and peer are
+    /// unchanged. An interrupted rotation is resumed by running the command
+    /// again.
+    RotateKey {
         /// Use the specified configuration file
         #[clap(short, long)]
         config: Option<PathBuf>,
@@ -87,9 +124,19 @@ pub enum Command {
         data: Option<PathBuf>,
         /// Use the specified coordination server
         ///
-        /// The address can be IPv4 or IPv6.
+        /// The address can be an IPv4 or IPv6 address or a hostname, optionally
+        /// followed by a port.
+        #[clap(short, long)]
+        server: Option<ServerAddress>,
+    },
+    /// Show past and scheduled backups
+    Status {
+        /// Use the specified configuration file
         #[clap(short, long)]
-        server: Option<IpAddr>,
+        config: Option<PathBuf>,
+        /// Use the specified data file
+        #[clap(short, long)]
+        data: Option<PathBuf>,
     },
     Daemon {
         #[clap(short, long)]
@@ -99,8 +146,9 @@ pub enum Command {
         data: Option<PathBuf>,
         /// Use the specified coordination server
         ///
-        /// The address can be IPv4 or IPv6.
+        /// The address can be an IPv4 or IPv6 address or a hostname, optionally
+        /// followed by a port.
         #[clap(short, long)]
-        server: Option<IpAddr>,
+        server: Option<ServerAddress>,
     },
 }
@@ -1,68 +1,132 @@
-use std::{net::IpAddr, path::PathBuf};
+use std::path::PathBuf;
 
 use memorage_client::{
-    fs::index::Index,
+    fs::index::{Index, IndexDifference},
     net::{
-        peer::{sleep_till, OutgoingConnection},
-        Client,
+        peer::{sleep_till, BackupPlan, OutgoingConnection, TransferStats},
+        Client, ServerAddress,
+    },
+    persistent::{
+        config::Config,
+        data::Data,
+        history::{Direction, History, Run},
+        Persistent,
     },
-    persistent::{config::Config, data::Data, Persistent},
     Result,
 };
+use memorage_core::time::OffsetDateTime;
 
 use tracing::{debug, trace};
 
 pub async fn backup(
+    dry_run: bool,
     config: Option<PathBuf>,
-    data: Option<PathBuf>,
-    server: Option<IpAddr>,
+    data_path: Option<PathBuf>,
+    server: Option<ServerAddress>,
 ) -> Result<()> {
     let config = Config::from_disk(config).await?;
-    let data = Data::from_disk(data).await?;
+    let data = Data::from_disk(data_path.as_ref()).await?;
     debug!("loaded config and data files");
     if let Some(server) = server {
         let server_address = &mut config.lock().server_address;
         *server_address = vec![server];
     }
+    let history_path = History::path_next_to(data_path);
+    let peer = data.lock().peer;
+
+    // The run is recorded before it starts, so that runs that never finish
+    // still appear in the history.
+    let start = OffsetDateTime::now_utc();
+    if !dry_run {
+        let run = Run::in_progress(Direction::Outgoing, start, peer);
+        History::update(history_path.as_ref(), |h| h.push(run)).await?;
+    }
 
-    let client = Client::new(data, config.clone()).await?;
+    let result: Result<Option<TransferStats>> = try {
+        let client = Client::new(data, config.clone()).await?;
 
-    let time = client.schedule_outgoing_connection().await?;
+        let time = client.schedule_outgoing_connection().await?;
+        if !dry_run {
+            History::update(history_path.as_ref(), |h| h.scheduled_outgoing = Some(time)).await?;
+        }
 
-    let backup_path_clone = config.lock().backup_path.clone();
-    let new_index_handle = tokio::spawn(async move {
-        // TODO: Race conditions?
-        Index::from_directory(backup_path_clone).await
-    });
+        let backup_path_clone = config.lock().backup_path.clone();
+        let new_index_handle = tokio::spawn(async move {
+            // TODO: Race conditions?
+            Index::from_directory(backup_path_clone).await
+        });
 
-    sleep_till(time).await?;
-    let mut outgoing_connection = client.create_outgoing_connection().await?;
+        sleep_till(time).await?;
+        let mut outgoing_connection = client.create_outgoing_connection().await?;
 
-    async fn indefinite_ping(connection: &mut OutgoingConnection) -> ! {
-        loop {
-            // TODO: The select statement could drop indefinite_ping during the
-            // ping, which may result in a write error on the peer if
-            // indefinite_ping gets dropped after transmitting a ping but before
-            // receiving a response.
-            let result = connection.ping().await;
+        async fn indefinite_ping(connection: &mut OutgoingConnection) -> ! {
+            loop {
+                // TODO: The select statement could drop indefinite_ping during the
+                // ping, which may result in a write error on the peer if
+                // indefinite_ping gets dropped after transmitting a ping but before
+                // receiving a response.
+                let result = connection.ping().await;
 
-            trace!(?result, "pinged peer");
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                trace!(?result, "pinged peer");
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            }
+        }
+        let new_index = tokio::select! {
+            // Biased mode first checks if the new index has already been created
+            // before beginning to ping.
+            biased;
+            new_index = new_index_handle => new_index??,
+            // indefinite_ping will keep pinging the peer to keep the connection
+            // open until the local index has been created. Index::new() is just
+            // there to satisfy the type checker.
+            _ = indefinite_ping(&mut outgoing_connection) => Index::new(),
+        };
+        debug!("new index created");
+
+        if dry_run {
+            let plan = outgoing_connection.plan_backup(&new_index).await?;
+            print_plan(&plan);
+            None
+        } else {
+            Some(outgoing_connection.backup(&new_index).await?)
         }
-    }
-    let new_index = tokio::select! {
-        // Biased mode first checks if the new index has already been created
-        // before beginning to ping.
-        biased;
-        new_index = new_index_handle => new_index??,
-        // indefinite_ping will keep pinging the peer to keep the connection
-        // open until the local index has been created. Index::new() is just
-        // there to satisfy the type checker.
-        _ = indefinite_ping(&mut outgoing_connection) => Index::new(),
     };
-    debug!("new index created");
 
-    outgoing_connection.backup(&new_index).await?;
+    let result = match result {
+        // Dry runs aren't recorded in the history, whether or not they fail.
+        Ok(None) => return Ok(()),
+        Err(e) if dry_run => return Err(e),
+        Ok(Some(stats)) => Ok(stats),
+        Err(e) => Err(e),
+    };
+    let run = Run::new(Direction::Outgoing, start, peer, &result);
+    History::update(history_path, |h| h.push(run)).await?;
+    result.map(|_| ())
+}
 
-    Ok(())
+fn print_plan(plan: &BackupPlan) {
+    let (mut writes, mut renames, mut deletes) = (0, 0, 0);
+    for difference in &plan.difference {
+        match difference {
+            IndexDifference::Write(path) => {
+                writes += 1;
+                println!("write  {}", path.display());
+            }
+            IndexDifference::Rename { from, to } => {
+                renames += 1;
+                println!("rename {} -> {}", from.display(), to.display());
+            }
+            IndexDifference::Delete(path) => {
+                deletes += 1;
+                println!("delete {}", path.display());
+            }
+        }
+    }
+    if plan.migrate_path_hashing {
+        println!("stored file names would be migrated to keyed hashes");
+    }
+    println!(
+        "{writes} writes ({} bytes), {renames} renames, {deletes} deletes",
+        plan.write_len
+    );
 }
@@ -1,33 +1,56 @@
-use std::{net::IpAddr, path::PathBuf};
+use std::path::PathBuf;
 
 use memorage_client::{
-    net::{peer::sleep_till, Client},
-    persistent::{config::Config, data::Data, Persistent},
+    net::{peer::sleep_till, Client, ServerAddress},
+    persistent::{
+        config::Config,
+        data::Data,
+        history::{Direction, History, Run},
+        Persistent,
+    },
     Result,
 };
+use memorage_core::time::OffsetDateTime;
 use tracing::debug;
 
 pub async fn check(
     config: Option<PathBuf>,
-    data: Option<PathBuf>,
-    server: Option<IpAddr>,
+    data_path: Option<PathBuf>,
+    server: Option<ServerAddress>,
 ) -> Result<()> {
     let config = Config::from_disk(config).await?;
-    let data = Data::from_disk(data).await?;
+    let data = Data::from_disk(data_path.as_ref()).await?;
     debug!("loaded config and data files");
     if let Some(server) = server {
         let server_address = &mut config.lock().server_address;
         *server_address = vec![server];
     }
+    let history_path = History::path_next_to(data_path);
+    let peer = data.lock().peer;
 
     let client = Client::new(data, config).await?;
     let time = match client.check_incoming_connection().await? {
         Some(t) => t,
         None => return Ok(()),
     };
-    sleep_till(time).await?;
-    let incoming_connection = client.receive_incoming_connection().await?;
-    incoming_connection.handle().await?;
 
-    Ok(())
+    // Incoming runs start once the peer has requested a connection, and are
+    // recorded straight away so that runs that never finish still appear in the
+    // history.
+    let start = OffsetDateTime::now_utc();
+    let run = Run::in_progress(Direction::Incoming, start, peer);
+    History::update(history_path.as_ref(), |h| {
+        h.scheduled_incoming = Some(time);
+        h.push(run);
+    })
+    .await?;
+
+    let result = try {
+        sleep_till(time).await?;
+        let incoming_connection = client.receive_incoming_connection().await?;
+        incoming_connection.handle().await?
+    };
+    let run = Run::new(Direction::Incoming, start, peer, &result);
+    History::update(history_path, |h| h.push(run)).await?;
+    result.map(|_| ())
 }
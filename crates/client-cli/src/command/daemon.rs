@@ -1,31 +1,42 @@
-use std::{net::IpAddr, path::PathBuf};
+use std::path::PathBuf;
 
 use memorage_client::{
     fs::index::Index,
     net::{
-        peer::{sleep_till, OutgoingConnection},
-        Client,
+        peer::{sleep_till, OutgoingConnection, TransferStats},
+        Client, ServerAddress,
     },
-    persistent::{config::Config, data::Data, Persistent},
+    persistent::{
+        config::Config,
+        data::Data,
+        history::{Direction, History, Run},
+        Persistent,
+    },
+    schedule::Schedule,
     Result,
 };
-use memorage_core::time::OffsetDateTime;
+use memorage_core::{
+    time::{OffsetDateTime, UtcOffset},
+    PublicKey,
+};
 
 use tokio::sync::mpsc::channel;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
 pub async fn daemon(
     config: Option<PathBuf>,
-    data: Option<PathBuf>,
-    server: Option<IpAddr>,
+    data_path: Option<PathBuf>,
+    server: Option<ServerAddress>,
 ) -> Result<!> {
     let config = Config::from_disk(config).await?;
-    let data = Data::from_disk(data).await?;
+    let data = Data::from_disk(data_path.as_ref()).await?;
     debug!("loaded config and data files");
     if let Some(server) = server {
         let server_address = &mut config.lock().server_address;
         *server_address = vec![server];
     }
+    let history_path = History::path_next_to(data_path);
+    let peer = data.lock().peer;
 
     let (incoming_tx, mut incoming_rx) = channel::<IncomingEvent>(10);
     let incoming_config = config.clone();
@@ -34,8 +45,9 @@ pub async fn daemon(
     let _incoming = tokio::spawn(async move {
         let config = incoming_config;
         let data = incoming_data;
+        let mut failures = 0;
         loop {
-            let result: Result<()> = try {
+            let result: Result<Option<TransferStats>> = try {
                 let client = Client::new(data.clone(), config.clone()).await?;
                 match client.check_incoming_connection().await? {
                     Some(time) => {
@@ -44,25 +56,39 @@ pub async fn daemon(
                         let _ = incoming_tx.send(IncomingEvent::Connecting).await;
                         let conn = client.receive_incoming_connection().await?;
                         let _ = incoming_tx.send(IncomingEvent::Connected).await;
-                        conn.handle().await?;
+                        Some(conn.handle().await?)
                     }
                     None => {
                         let _ = incoming_tx.send(IncomingEvent::Checked).await;
+                        None
                     }
                 }
             };
 
             match result {
-                Ok(_) => {
-                    let _ = incoming_tx.send(IncomingEvent::Complete).await;
+                Ok(None) => failures = 0,
+                Ok(Some(stats)) => {
+                    failures = 0;
+                    let _ = incoming_tx.send(IncomingEvent::Complete(stats)).await;
                 }
                 Err(e) => {
+                    failures += 1;
                     let _ = incoming_tx.send(IncomingEvent::Error(e)).await;
                 }
             };
 
-            let check_incoming_interval = config.lock().check_incoming_interval;
-            tokio::time::sleep(check_incoming_interval).await;
+            let (check_incoming_interval, error_retry) = {
+                let config = config.lock();
+                (config.check_incoming_interval, config.error_retry)
+            };
+            let delay = match error_retry.retry_delay(failures) {
+                Some(delay) => delay,
+                None => {
+                    failures = 0;
+                    check_incoming_interval
+                }
+            };
+            tokio::time::sleep(delay).await;
         }
     });
 
@@ -73,10 +99,42 @@ pub async fn daemon(
     let _outgoing = tokio::spawn(async move {
         let config = outgoing_config;
         let data = outgoing_data;
+        let mut failures = 0;
         loop {
-            let result: Result<()> = try {
+            let (schedule, outgoing_schedule_delay) = {
+                let config = config.lock();
+                (
+                    config.outgoing_schedule.clone(),
+                    config.outgoing_schedule_delay,
+                )
+            };
+            // The time of the scheduled run. Retries after failures don't wait
+            // for the schedule.
+            let mut run_at = None;
+            if let (Some(schedule), 0) = (schedule, failures) {
+                let now = OffsetDateTime::now_utc();
+                let next = next_run(&schedule, now);
+                let _ = outgoing_tx.send(OutgoingEvent::NextRun(next)).await;
+                match next {
+                    // The connection is requested in advance, so that the peer
+                    // has time to notice the request before the scheduled run.
+                    // The conversion only fails if the request is in the past.
+                    Some(next) => {
+                        let request_at = next - outgoing_schedule_delay;
+                        tokio::time::sleep((request_at - now).try_into().unwrap_or_default()).await;
+                        run_at = Some(next);
+                    }
+                    None => std::future::pending().await,
+                }
+            }
+
+            let _ = outgoing_tx.send(OutgoingEvent::Started).await;
+            let result: Result<TransferStats> = try {
                 let client = Client::new(data.clone(), config.clone()).await?;
-                let time = client.schedule_outgoing_connection().await?;
+                let time = match run_at {
+                    Some(time) => client.schedule_outgoing_connection_at(time).await?,
+                    None => client.schedule_outgoing_connection().await?,
+                };
                 let _ = outgoing_tx.send(OutgoingEvent::Scheduled(time)).await;
 
                 let backup_path_clone = config.lock().backup_path.clone();
@@ -114,68 +172,181 @@ pub async fn daemon(
                 debug!("new index created");
 
                 let _ = outgoing_tx.send(OutgoingEvent::Connected).await;
-                conn.backup(&new_index).await?;
-                let _ = outgoing_tx.send(OutgoingEvent::Complete).await;
+                conn.backup(&new_index).await?
             };
 
             match result {
-                Ok(_) => {
-                    let _ = outgoing_tx.send(OutgoingEvent::Complete).await;
+                Ok(stats) => {
+                    failures = 0;
+                    let _ = outgoing_tx.send(OutgoingEvent::Complete(stats)).await;
                 }
                 Err(e) => {
+                    failures += 1;
                     let _ = outgoing_tx.send(OutgoingEvent::Error(e)).await;
                 }
             };
 
-            let schedule_outgoing_interval = config.lock().schedule_outgoing_interval;
-            tokio::time::sleep(schedule_outgoing_interval).await;
+            let error_retry = config.lock().error_retry;
+            if let Some(delay) = error_retry.retry_delay(failures) {
+                let next = OffsetDateTime::now_utc() + delay;
+                let _ = outgoing_tx.send(OutgoingEvent::NextRun(Some(next))).await;
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            failures = 0;
+
+            if config.lock().outgoing_schedule.is_none() {
+                let schedule_outgoing_interval = config.lock().schedule_outgoing_interval;
+                let next = OffsetDateTime::now_utc() + schedule_outgoing_interval;
+                let _ = outgoing_tx.send(OutgoingEvent::NextRun(Some(next))).await;
+                tokio::time::sleep(schedule_outgoing_interval).await;
+            }
         }
     });
 
+    // The start times of the runs in progress, which are recorded in the history
+    // as soon as the runs start and updated once they end. Incoming runs start
+    // once the peer has requested a connection, so failed checks for requests
+    // aren't recorded.
+    let mut incoming_start = None;
+    let mut outgoing_start = None;
+
     loop {
-        tokio::select! {
+        let update: Option<HistoryUpdate> = tokio::select! {
             event = incoming_rx.recv() => {
                 let event = event.expect("incoming handler dropped sender");
                 match event {
-                    IncomingEvent::Checked => info!("checked server for connection requests"),
+                    IncomingEvent::Checked => {
+                        info!("checked server for connection requests");
+                        None
+                    }
                     IncomingEvent::Scheduled(time) => {
-                        info!("scheduled to receieve backup from peer at {time}")
+                        info!("scheduled to receieve backup from peer at {time}");
+                        let start = OffsetDateTime::now_utc();
+                        incoming_start = Some(start);
+                        let run = Run::in_progress(Direction::Incoming, start, peer);
+                        Some(Box::new(move |h| {
+                            h.scheduled_incoming = Some(time);
+                            h.push(run);
+                        }))
+                    }
+                    IncomingEvent::Connecting => {
+                        info!("connecting to peer to receive backup");
+                        None
+                    }
+                    IncomingEvent::Connected => {
+                        info!("connected to peer - ready to recieve backup");
+                        None
+                    }
+                    IncomingEvent::Complete(stats) => {
+                        info!("sucessfuly receieved backup from peer");
+                        incoming_start.take().map(|start| record(Direction::Incoming, start, peer, Ok(stats)))
                     }
-                    IncomingEvent::Connecting => info!("connecting to peer to receive backup"),
-                    IncomingEvent::Connected => info!("connected to peer - ready to recieve backup"),
-                    IncomingEvent::Complete => info!("sucessfuly receieved backup from peer"),
                     IncomingEvent::Error(error) => {
-                        error!("error on incoming connection handler: {error}")
+                        error!("error on incoming connection handler: {error}");
+                        incoming_start.take().map(|start| record(Direction::Incoming, start, peer, Err(error)))
                     }
                 }
             },
             event = outgoing_rx.recv() => {
                 let event = event.expect("outgoing handler dropped sender");
                 match event {
-                    OutgoingEvent::Scheduled(time) => info!("scheduled to backup to peer at {time}"),
-                    OutgoingEvent::Connecting => info!("connecting to peer to transfer backup"),
-                    OutgoingEvent::Connected => info!("connected to peer - ready to transfer backup"),
-                    OutgoingEvent::Complete => info!("succesfully transferred backup"),
-                    OutgoingEvent::Error(error) => error!("error on outgoing connection handler: {error}"),
+                    OutgoingEvent::NextRun(Some(time)) => {
+                        info!("next backup to peer at {time}");
+                        None
+                    }
+                    OutgoingEvent::NextRun(None) => {
+                        error!("outgoing schedule never matches - no further backups will run");
+                        None
+                    }
+                    OutgoingEvent::Started => {
+                        info!("starting backup to peer");
+                        let start = OffsetDateTime::now_utc();
+                        outgoing_start = Some(start);
+                        let run = Run::in_progress(Direction::Outgoing, start, peer);
+                        Some(Box::new(move |h| h.push(run)))
+                    }
+                    OutgoingEvent::Scheduled(time) => {
+                        info!("scheduled to backup to peer at {time}");
+                        Some(Box::new(move |h| h.scheduled_outgoing = Some(time)))
+                    }
+                    OutgoingEvent::Connecting => {
+                        info!("connecting to peer to transfer backup");
+                        None
+                    }
+                    OutgoingEvent::Connected => {
+                        info!("connected to peer - ready to transfer backup");
+                        None
+                    }
+                    OutgoingEvent::Complete(stats) => {
+                        info!("succesfully transferred backup");
+                        outgoing_start.take().map(|start| record(Direction::Outgoing, start, peer, Ok(stats)))
+                    }
+                    OutgoingEvent::Error(error) => {
+                        error!("error on outgoing connection handler: {error}");
+                        outgoing_start.take().map(|start| record(Direction::Outgoing, start, peer, Err(error)))
+                    }
                 }
             }
+        };
+
+        if let Some(update) = update {
+            if let Err(e) = History::update(history_path.as_ref(), update).await {
+                error!("error updating history: {e}");
+            }
         }
     }
 }
 
+/// Returns the next time after `now` that matches the schedule in the local
+/// time zone.
+///
+/// The local offset is determined at the time of the next run, so that runs
+/// follow daylight saving time changes.
+fn next_run(schedule: &Schedule, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    let local_offset = |time| {
+        UtcOffset::local_offset_at(time).unwrap_or_else(|_| {
+            warn!("couldn't determine local UTC offset - interpreting schedule in UTC");
+            UtcOffset::UTC
+        })
+    };
+    let offset = local_offset(now);
+    let next = schedule.next_after(now, offset)?;
+    match local_offset(next) {
+        // The offset changes before the next run.
+        next_offset if next_offset != offset => schedule.next_after(now, next_offset),
+        _ => Some(next),
+    }
+}
+
+type HistoryUpdate = Box<dyn FnOnce(&mut History) + Send>;
+
+fn record(
+    direction: Direction,
+    start: OffsetDateTime,
+    peer: PublicKey,
+    result: Result<TransferStats>,
+) -> HistoryUpdate {
+    let run = Run::new(direction, start, peer, &result);
+    Box::new(move |h| h.push(run))
+}
+
 enum IncomingEvent {
     Checked,
     Scheduled(OffsetDateTime),
     Connecting,
     Connected,
-    Complete,
+    Complete(TransferStats),
     Error(memorage_client::Error),
 }
 
 enum OutgoingEvent {
+    /// The time of the next run, or [`None`] if the schedule never matches.
+    NextRun(Option<OffsetDateTime>),
+    Started,
     Scheduled(OffsetDateTime),
     Connecting,
     Connected,
-    Complete,
+    Complete(TransferStats),
     Error(memorage_client::Error),
 }
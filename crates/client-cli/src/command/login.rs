@@ -3,6 +3,7 @@ use crate::io;
 use std::path::PathBuf;
 
 use memorage_client::{
+    crypto::MasterKey,
     mnemonic::MnemonicPhrase,
     persistent::{
         config::Config,
@@ -19,21 +20,36 @@ pub async fn login(config_output: Option<PathBuf>, data_output: Option<PathBuf>)
     let password = Option::from(io::securely_prompt("Password: ")?).filter(|s| s.as_str() != "");
     let key_pair: KeyPair = MnemonicPhrase::new(words, password)?.into();
 
+    let words_input =
+        io::prompt("Master key phrase (empty if the master key was never rotated): ")?;
+    let master_key: Option<MasterKey> = match words_input.as_str() {
+        "" => None,
+        _ => {
+            let words = words_input.split(' ').collect();
+            let password =
+                Option::from(io::securely_prompt("Password: ")?).filter(|s| s.as_str() != "");
+            Some(MnemonicPhrase::new(words, password)?.into())
+        }
+    };
+
     let config = Config::from_disk(config_output.as_ref()).await;
     let data = Data::from_disk(data_output.as_ref()).await;
 
     if let Ok(data) = data {
-        if data.lock().key_pair == key_pair {
+        let mut data = data.lock().clone();
+        if data.key_pair == key_pair && data.master_key == master_key {
             println!("User already logged in");
             return Ok(());
         } else {
             io::prompt_continue("Logging in will log out the current user")?;
-            let mut data = data.lock().clone();
             data.key_pair = key_pair;
+            data.master_key = master_key;
+            data.previous_master_key = None;
             data.to_disk(data_output).await?;
         }
     } else {
-        let data = DataWithoutPeer::from_key_pair(key_pair);
+        let mut data = DataWithoutPeer::from_key_pair(key_pair);
+        data.master_key = master_key;
         data.to_disk(data_output).await?;
     }
 
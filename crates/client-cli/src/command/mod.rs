@@ -4,7 +4,9 @@ mod daemon;
 mod login;
 mod pair;
 mod retrieve;
+mod rotate_key;
 mod setup;
+mod status;
 
 pub use backup::backup;
 pub use check::check;
@@ -12,4 +14,6 @@ pub use daemon::daemon;
 pub use login::login;
 pub use pair::pair;
 pub use retrieve::retrieve;
+pub use rotate_key::rotate_key;
 pub use setup::setup;
+pub use status::status;
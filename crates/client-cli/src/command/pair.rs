@@ -1,9 +1,9 @@
 use crate::io;
 
-use std::{net::IpAddr, path::PathBuf};
+use std::path::PathBuf;
 
 use memorage_client::{
-    net::Client,
+    net::{Client, ServerAddress},
     persistent::{config::Config, data::DataWithoutPeer, Persistent},
     Result,
 };
@@ -15,7 +15,7 @@ pub async fn pair(
     code: Option<PairingCode>,
     config: Option<PathBuf>,
     data: Option<PathBuf>,
-    server: Option<IpAddr>,
+    server: Option<ServerAddress>,
 ) -> Result<()> {
     let config = Config::from_disk(config).await?;
     let data = DataWithoutPeer::from_disk(data).await?;
@@ -27,15 +27,15 @@ pub async fn pair(
 
     let client = Client::new(data.clone(), config).await?;
 
-    let key_pair = data.lock().key_pair.clone();
+    let data = data.lock().clone();
     if let Some(code) = code {
         let peer = client.get_key(code).await?;
-        io::verify_peer(key_pair, peer, false).await
+        io::verify_peer(data, peer, false).await
     } else {
         let pairing_code = client.register().await?;
         println!("Pairing code: {}", pairing_code);
 
         let peer = client.register_response().await?;
-        io::verify_peer(key_pair, peer, true).await
+        io::verify_peer(data, peer, true).await
     }
 }
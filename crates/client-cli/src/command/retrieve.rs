@@ -1,7 +1,8 @@
-use std::{net::IpAddr, path::PathBuf};
+use std::path::PathBuf;
 
 use memorage_client::{
-    net::{peer::sleep_till, Client},
+    fs::restore::ConflictPolicy,
+    net::{peer::sleep_till, Client, ServerAddress},
     persistent::{config::Config, data::Data, Persistent},
     Result,
 };
@@ -10,14 +11,12 @@ use tracing::debug;
 
 pub async fn retrieve(
     output: Option<PathBuf>,
+    in_place: bool,
+    conflict: ConflictPolicy,
     config: Option<PathBuf>,
     data: Option<PathBuf>,
-    server: Option<IpAddr>,
+    server: Option<ServerAddress>,
 ) -> Result<()> {
-    let output = match output {
-        Some(p) => p,
-        None => std::env::current_dir()?.join("memorage_backup"),
-    };
     let config = Config::from_disk(config).await?;
     let data = Data::from_disk(data).await?;
     debug!("loaded config and data files");
@@ -26,12 +25,34 @@ pub async fn retrieve(
         *server_address = vec![server];
     }
 
+    let output = match output {
+        Some(p) => p,
+        None if in_place => config.lock().backup_path.clone(),
+        None => std::env::current_dir()?.join("memorage_backup"),
+    };
+
     let client = Client::new(data, config).await?;
     let time = client.schedule_outgoing_connection().await?;
     sleep_till(time).await?;
     let mut outgoing_connection = client.create_outgoing_connection().await?;
-    outgoing_connection.retrieve(&output).await?;
+    let summary = outgoing_connection.retrieve(&output, conflict).await?;
 
     println!("Retrieval succesful");
+    for path in &summary.created {
+        println!("created     {}", path.display());
+    }
+    for path in &summary.overwritten {
+        println!("overwritten {}", path.display());
+    }
+    for (original, path) in &summary.renamed {
+        println!("kept both   {} -> {}", original.display(), path.display());
+    }
+    println!(
+        "{} created, {} overwritten, {} kept both, {} skipped",
+        summary.created.len(),
+        summary.overwritten.len(),
+        summary.renamed.len(),
+        summary.skipped.len()
+    );
     Ok(())
 }
@@ -0,0 +1,59 @@
+use crate::io;
+
+use std::path::PathBuf;
+
+use memorage_client::{
+    crypto::MasterKey,
+    net::{peer::sleep_till, Client, ServerAddress},
+    persistent::{config::Config, data::Data, Persistent},
+    Result,
+};
+
+use tracing::debug;
+
+pub async fn rotate_key(
+    config: Option<PathBuf>,
+    data_path: Option<PathBuf>,
+    server: Option<ServerAddress>,
+) -> Result<()> {
+    let config = Config::from_disk(config).await?;
+    let data = Data::from_disk(data_path.as_ref()).await?;
+    debug!("loaded config and data files");
+    if let Some(server) = server {
+        let server_address = &mut config.lock().server_address;
+        *server_address = vec![server];
+    }
+
+    if data.lock().previous_master_key.is_some() {
+        println!("Resuming interrupted master key rotation");
+    } else {
+        io::prompt_continue(
+            "The new master key phrase is needed alongside your mnemonic phrase to retrieve files",
+        )?;
+        let phrase = io::prompt_new_phrase()?;
+        println!("New master key phrase: {}", phrase);
+        let new_master_key = MasterKey::from(phrase);
+
+        // The new key is saved before the peer's index uses it, and the old key
+        // is kept until it doesn't, so that an interrupted rotation can be
+        // resumed.
+        let mut new_data = data.lock().clone();
+        new_data.previous_master_key = Some(new_data.master_key());
+        new_data.master_key = Some(new_master_key);
+        new_data.to_disk(data_path.as_ref()).await?;
+        *data.lock() = new_data;
+    }
+
+    let client = Client::new(data.clone(), config).await?;
+    let time = client.schedule_outgoing_connection().await?;
+    sleep_till(time).await?;
+    let outgoing_connection = client.create_outgoing_connection().await?;
+    outgoing_connection.rotate_key().await?;
+
+    let mut data = data.lock().clone();
+    data.previous_master_key = None;
+    data.to_disk(data_path).await?;
+
+    println!("Master key rotation successful");
+    Ok(())
+}
@@ -3,7 +3,6 @@ use crate::io;
 use std::path::PathBuf;
 
 use memorage_client::{
-    mnemonic::MnemonicPhrase,
     persistent::{
         data::{Data, DataWithoutPeer},
         Persistent, CONFIG_PATH,
@@ -14,32 +13,7 @@ use memorage_client::{
 use tracing::info;
 
 pub async fn setup(config_output: Option<PathBuf>, data_output: Option<PathBuf>) -> Result<()> {
-    let num_words = loop {
-        match io::prompt("Mnemonic phrase length (18): ")?.as_ref() {
-            "" => break 18,
-            s => match s.parse::<usize>() {
-                Ok(n) => break n,
-                Err(_) => {
-                    eprintln!("Mnemonic phrase length must be a number");
-                }
-            },
-        }
-    };
-
-    let password = io::securely_prompt("Enter password (empty for no password): ")?;
-    let password = match &password[..] {
-        "" => None,
-        _ => Some(password),
-    };
-    if let Some(ref password) = password {
-        let confirmed_password = io::securely_prompt("Confirm password: ")?;
-        if &confirmed_password != password {
-            eprintln!("Passwords didn't match");
-            std::process::exit(1);
-        }
-    }
-
-    let phrase = MnemonicPhrase::generate(num_words, password);
+    let phrase = io::prompt_new_phrase()?;
     println!("Mnemonic phrase: {}", phrase);
 
     let data = DataWithoutPeer::from_key_pair(phrase.into());
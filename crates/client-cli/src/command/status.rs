@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use memorage_client::{
+    crypto::fingerprint,
+    persistent::{
+        config::Config,
+        data::Data,
+        history::{Direction, History, Run},
+        Persistent,
+    },
+    Result,
+};
+use memorage_core::time::OffsetDateTime;
+
+pub async fn status(config: Option<PathBuf>, data_path: Option<PathBuf>) -> Result<()> {
+    let config = Config::from_disk(config).await?;
+    let data = Data::from_disk(data_path.as_ref()).await?;
+    let history = History::from_disk_or_default(History::path_next_to(data_path)).await?;
+
+    println!("Peer: {}", fingerprint(&data.lock().peer));
+    print_last_success(
+        "Last backup to peer",
+        history.last_success(Direction::Outgoing),
+    );
+    print_last_success(
+        "Last backup from peer",
+        history.last_success(Direction::Incoming),
+    );
+    print_scheduled("Next backup to peer", history.scheduled_outgoing);
+    print_scheduled("Next backup from peer", history.scheduled_incoming);
+
+    match history.runs.last() {
+        Some(Run {
+            error: Some(error),
+            end: Some(end),
+            ..
+        }) => println!("Last run failed at {end}: {error}"),
+        Some(Run {
+            start, end: None, ..
+        }) => println!("Last run started at {start} and hasn't finished"),
+        _ => {}
+    }
+
+    let peer_storage_path = config.lock().peer_storage_path.clone();
+    println!(
+        "Storage used by peer: {} bytes",
+        peer_storage_path.size().await?
+    );
+    Ok(())
+}
+
+fn print_last_success(label: &str, run: Option<&Run>) {
+    match run {
+        Some(Run {
+            end: Some(end),
+            files,
+            bytes,
+            ..
+        }) => println!("{label}: {end} ({files} files, {bytes} bytes)"),
+        _ => println!("{label}: never"),
+    }
+}
+
+fn print_scheduled(label: &str, time: Option<OffsetDateTime>) {
+    match time {
+        Some(time) => println!("{label}: scheduled for {time}"),
+        None => println!("{label}: not scheduled"),
+    }
+}
@@ -1,5 +1,6 @@
 use memorage_client::{
-    persistent::{config::Config, data::Data, Persistent},
+    mnemonic::MnemonicPhrase,
+    persistent::{config::Config, data::DataWithoutPeer, Persistent},
     Error, Result,
 };
 
@@ -8,7 +9,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use memorage_core::{KeyPair, PublicKey};
+use memorage_core::PublicKey;
 
 #[inline]
 pub fn prompt<S>(s: S) -> Result<String>
@@ -62,6 +63,36 @@ where
     }
 }
 
+#[inline]
+pub fn prompt_new_phrase() -> Result<MnemonicPhrase<'static>> {
+    let num_words = loop {
+        match prompt("Mnemonic phrase length (18): ")?.as_ref() {
+            "" => break 18,
+            s => match s.parse::<usize>() {
+                Ok(n) => break n,
+                Err(_) => {
+                    eprintln!("Mnemonic phrase length must be a number");
+                }
+            },
+        }
+    };
+
+    let password = securely_prompt("Enter password (empty for no password): ")?;
+    let password = match &password[..] {
+        "" => None,
+        _ => Some(password),
+    };
+    if let Some(ref password) = password {
+        let confirmed_password = securely_prompt("Confirm password: ")?;
+        if &confirmed_password != password {
+            eprintln!("Passwords didn't match");
+            std::process::exit(1);
+        }
+    }
+
+    Ok(MnemonicPhrase::generate(num_words, password))
+}
+
 #[inline]
 pub async fn setup_config() -> Result<Config> {
     let mut config = Config::default();
@@ -154,12 +185,12 @@ pub async fn setup_config() -> Result<Config> {
 }
 
 #[inline]
-pub async fn verify_peer(key_pair: KeyPair, peer: PublicKey, initiator: bool) -> Result<()> {
+pub async fn verify_peer(data: DataWithoutPeer, peer: PublicKey, initiator: bool) -> Result<()> {
     let (key_1, key_2);
     if initiator {
-        (key_1, key_2) = (key_pair.public, peer);
+        (key_1, key_2) = (data.key_pair.public, peer);
     } else {
-        (key_1, key_2) = (peer, key_pair.public);
+        (key_1, key_2) = (peer, data.key_pair.public);
     }
     println!("Key 1: {}", key_1);
     println!("Key 2: {}", key_2);
@@ -169,7 +200,7 @@ pub async fn verify_peer(key_pair: KeyPair, peer: PublicKey, initiator: bool) ->
         .to_lowercase();
 
     if input == "y" || input == "yes" {
-        let data = Data { key_pair, peer };
+        let data = data.with_peer(peer);
         println!("Saving peer");
         data.to_disk(Option::<&Path>::None).await?;
         println!("Pairing successful");
@@ -36,10 +36,11 @@ async fn main() -> memorage_client::Result<()> {
             server,
         } => command::pair(code, config, data, server).await,
         Command::Backup {
+            dry_run,
             config,
             data,
             server,
-        } => command::backup(config, data, server).await,
+        } => command::backup(dry_run, config, data, server).await,
         Command::Check {
             config,
             data,
@@ -47,10 +48,18 @@ async fn main() -> memorage_client::Result<()> {
         } => command::check(config, data, server).await,
         Command::Retrieve {
             output,
+            in_place,
+            conflict,
             config,
             data,
             server,
-        } => command::retrieve(output, config, data, server).await,
+        } => command::retrieve(output, in_place, conflict, config, data, server).await,
+        Command::RotateKey {
+            config,
+            data,
+            server,
+        } => command::rotate_key(config, data, server).await,
+        Command::Status { config, data } => command::status(config, data).await,
         Command::Daemon {
             config,
             data,
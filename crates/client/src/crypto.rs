@@ -7,10 +7,141 @@ use chacha20poly1305::{
     Tag, XChaCha20Poly1305, XNonce,
 };
 use memorage_core::rand::{thread_rng, RngCore};
-use memorage_core::PrivateKey;
+use memorage_core::{PrivateKey, PublicKey};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+/// A key that can be used to encrypt and decrypt data.
+pub trait EncryptionKey: private::Sealed {
+    fn as_key(&self) -> &chacha20poly1305::Key;
+}
+
+impl private::Sealed for PrivateKey {}
+impl EncryptionKey for PrivateKey {
+    fn as_key(&self) -> &chacha20poly1305::Key {
+        chacha20poly1305::Key::from_slice(self.as_ref())
+    }
+}
+
+mod private {
+    #[allow(unreachable_pub)]
+    pub trait Sealed {}
+}
+
+/// The key used to wrap the [`DataKey`] of every file in the
+/// [`Index`](crate::fs::index::Index).
+///
+/// Until it is first rotated, the master key is derived from the user's private
+/// key. Rotated master keys are generated from a separate mnemonic phrase, so
+/// that they can be replaced without changing the user's identity.
+// Keys are deliberately not `Copy` so that copies are explicit.
+#[allow(missing_copy_implementations)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MasterKey([u8; 32]);
+
+impl From<&PrivateKey> for MasterKey {
+    fn from(key: &PrivateKey) -> Self {
+        Self(blake3::derive_key(
+            "memorage 2022-07-01 master key",
+            key.as_ref(),
+        ))
+    }
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+impl private::Sealed for MasterKey {}
+impl EncryptionKey for MasterKey {
+    fn as_key(&self) -> &chacha20poly1305::Key {
+        chacha20poly1305::Key::from_slice(&self.0)
+    }
+}
+
+/// The key used to hash the paths of stored files.
+///
+/// Like the [`MasterKey`], the path key is derived from the user's private key.
+#[allow(missing_copy_implementations)]
+#[derive(Clone, PartialEq, Eq)]
+pub struct PathKey([u8; 32]);
+
+impl PathKey {
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<&PrivateKey> for PathKey {
+    fn from(key: &PrivateKey) -> Self {
+        Self(blake3::derive_key(
+            "memorage 2022-07-01 path key",
+            key.as_ref(),
+        ))
+    }
+}
+
+impl std::fmt::Debug for PathKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PathKey(..)")
+    }
+}
+
+/// A random key used to encrypt the contents of a single file.
+///
+/// Data keys are never stored in plaintext; they are wrapped under the
+/// [`MasterKey`] and stored alongside the file's entry in the index. This
+/// means that the master key can be rotated without re-encrypting any files.
+#[allow(missing_copy_implementations)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataKey([u8; 32]);
+
+impl DataKey {
+    pub fn generate() -> Self {
+        let mut key = [0; 32];
+        thread_rng().fill_bytes(&mut key);
+        Self(key)
+    }
+
+    pub fn wrap(&self, master_key: &MasterKey) -> Result<Encrypted<DataKey>> {
+        Encrypted::encrypt(self, master_key)
+    }
+}
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DataKey(..)")
+    }
+}
+
+impl private::Sealed for DataKey {}
+impl EncryptionKey for DataKey {
+    fn as_key(&self) -> &chacha20poly1305::Key {
+        chacha20poly1305::Key::from_slice(&self.0)
+    }
+}
+
+impl Encrypted<DataKey> {
+    /// Unwraps the data key using the first of the master keys that it was
+    /// wrapped under.
+    pub fn unwrap_key(&self, master_keys: &[MasterKey]) -> Result<DataKey> {
+        for master_key in master_keys {
+            match self.decrypt(master_key) {
+                Err(Error::Decryption) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::Decryption)
+    }
+
+    /// Wrap the data key under a different master key.
+    pub fn rewrap(&self, old: &[MasterKey], new: &MasterKey) -> Result<Self> {
+        self.unwrap_key(old)?.wrap(new)
+    }
+}
+
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Encrypted<T>
@@ -26,9 +157,26 @@ impl<T> Encrypted<T>
 where
     T: Serialize + DeserializeOwned,
 {
-    pub fn encrypt(value: &T, key: &PrivateKey) -> Result<Self> {
-        let data = bincode::serialize(value)?;
-        let aed = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key.as_ref()));
+    pub fn encrypt<K>(value: &T, key: &K) -> Result<Self>
+    where
+        K: EncryptionKey,
+    {
+        Self::encrypt_bytes(&bincode::serialize(value)?, key)
+    }
+
+    pub fn decrypt<K>(&self, key: &K) -> Result<T>
+    where
+        K: EncryptionKey,
+    {
+        bincode::deserialize(&self.decrypt_bytes(key)?).map_err(|e| e.into())
+    }
+
+    /// Encrypts an already serialized value.
+    pub(crate) fn encrypt_bytes<K>(data: &[u8], key: &K) -> Result<Self>
+    where
+        K: EncryptionKey,
+    {
+        let aed = XChaCha20Poly1305::new(key.as_key());
 
         let mut rng = thread_rng();
         let mut nonce = [0; 24];
@@ -36,7 +184,7 @@ where
 
         let xnonce = XNonce::from_slice(&nonce);
 
-        let encrypted = match aed.encrypt(xnonce, data.as_ref()) {
+        let encrypted = match aed.encrypt(xnonce, data) {
             Ok(c) => c,
             Err(_) => return Err(Error::Encryption),
         };
@@ -48,18 +196,29 @@ where
         })
     }
 
-    pub fn decrypt(&self, key: &PrivateKey) -> Result<T> {
-        let aed = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key.as_ref()));
+    /// Decrypts the value without deserializing it.
+    pub(crate) fn decrypt_bytes<K>(&self, key: &K) -> Result<Vec<u8>>
+    where
+        K: EncryptionKey,
+    {
+        let aed = XChaCha20Poly1305::new(key.as_key());
         let nonce = XNonce::from_slice(&self.nonce);
 
-        let decrypted = match aed.decrypt(nonce, self.value.as_ref()) {
-            Ok(c) => c,
-            Err(_) => return Err(Error::Decryption),
-        };
-        bincode::deserialize(&decrypted).map_err(|e| e.into())
+        aed.decrypt(nonce, self.value.as_ref())
+            .map_err(|_| Error::Decryption)
     }
 }
 
+/// Returns a short fingerprint of the public key, suitable for comparing keys
+/// by eye.
+pub fn fingerprint(key: &PublicKey) -> String {
+    blake3::hash(key.as_ref()).as_bytes()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
 /// Splits a slice containing a nonce, data, and tag into three individual
 /// slices corresponding to the noce, data, and tag respectively.
 pub fn split_encrypted_buf(buf: &'_ mut [u8]) -> (&'_ mut [u8], &'_ mut [u8], &'_ mut [u8]) {
@@ -76,8 +235,11 @@ pub fn split_encrypted_buf(buf: &'_ mut [u8]) -> (&'_ mut [u8], &'_ mut [u8], &'
 
 /// Encrypts a slice returning the nonce used to encrypt it and the tag
 /// generated.
-pub fn encrypt_in_place(buf: &mut [u8], key: &PrivateKey) -> Result<([u8; 24], [u8; 16])> {
-    let aed = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key.as_ref()));
+pub fn encrypt_in_place<K>(buf: &mut [u8], key: &K) -> Result<([u8; 24], [u8; 16])>
+where
+    K: EncryptionKey,
+{
+    let aed = XChaCha20Poly1305::new(key.as_key());
 
     let mut rng = thread_rng();
     let mut nonce = [0; 24];
@@ -92,8 +254,11 @@ pub fn encrypt_in_place(buf: &mut [u8], key: &PrivateKey) -> Result<([u8; 24], [
 
 /// Decrypts a slice containing a nonce, data, and tag, returning a subslice of
 /// `buf` with the decrypted data.
-pub fn decrypt_in_place<'a, 'b>(key: &'a PrivateKey, buf: &'b mut [u8]) -> Result<&'b [u8]> {
-    let aed = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key.as_ref()));
+pub fn decrypt_in_place<'a, 'b, K>(key: &'a K, buf: &'b mut [u8]) -> Result<&'b [u8]>
+where
+    K: EncryptionKey,
+{
+    let aed = XChaCha20Poly1305::new(key.as_key());
 
     let (nonce, data, tag) = split_encrypted_buf(buf);
 
@@ -132,4 +297,21 @@ mod tests {
         let decrypted = encrypted.decrypt(&incorrect_key);
         assert!(matches!(decrypted, Err(Error::Decryption)));
     }
+
+    #[test]
+    fn rewrap_data_key() {
+        let old = MasterKey::from(&KeyPair::from_entropy().private);
+        let new = MasterKey::from(&KeyPair::from_entropy().private);
+        let data_key = DataKey::generate();
+
+        let wrapped = data_key.wrap(&old).unwrap();
+        let rewrapped = wrapped.rewrap(&[new.clone(), old.clone()], &new).unwrap();
+
+        assert!(matches!(rewrapped.decrypt(&old), Err(Error::Decryption)));
+        assert_eq!(rewrapped.decrypt(&new).unwrap(), data_key);
+        assert!(matches!(
+            rewrapped.unwrap_key(&[old]),
+            Err(Error::Decryption)
+        ));
+    }
 }
@@ -14,6 +14,8 @@ pub enum Error {
     Encryption,
     #[error("error decrypting file")]
     Decryption,
+    #[error("unsupported index version {0}")]
+    UnsupportedIndexVersion(u32),
     #[error("UTF8 error")]
     Utf8(#[from] std::string::FromUtf8Error),
     /// An error that occurs during serialization or deserialization.
@@ -37,10 +39,12 @@ pub enum Error {
     ConfigRead(#[from] toml::de::Error),
     #[error("error writing config")]
     ConfigWrite(#[from] toml::ser::Error),
+    #[error("no coordination servers configured")]
+    NoServers,
+    #[error("coordination server didn't respond")]
+    ServerTimeout,
     #[error("peer didn't respond to connection request")]
     PeerNoResponse,
-    #[error("unauthorised connection request")]
-    UnauthorisedConnectionRequest,
     #[error("error occured while traversing directory")]
     Jwalk(#[from] jwalk::Error),
     #[error("peer encountered error")]
@@ -63,6 +67,10 @@ pub enum Error {
     TooLarge,
     #[error("frame too short")]
     FrameTooShort,
+    #[error("file length header exceeds stream length")]
+    InvalidLengthHeader,
+    #[error("unsupported encrypted file version {0}")]
+    UnsupportedStreamVersion(u8),
     #[error("join error")]
     Join(#[from] tokio::task::JoinError),
     #[error("mnemonic contains invalid words")]
@@ -1,14 +1,52 @@
-use crate::{crypto::Encrypted, fs::hash, Error, Result};
+use crate::{
+    crypto::{DataKey, Encrypted, MasterKey, PathKey},
+    fs::{hash, HashedPath},
+    Error, Result,
+};
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use memorage_core::PrivateKey;
 
 use bimap::BiMap;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use tokio::{fs::File, io::AsyncWriteExt};
 
+/// Prefixes serialized indexes, followed by the version of the index format.
+///
+/// Indexes written before the format was versioned start with their number of
+/// entries, which is never `u64::MAX`.
+const INDEX_MARKER: [u8; 8] = [0xff; 8];
+const INDEX_VERSION: u32 = 1;
+
 #[derive(Clone, Default, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub struct Index(BiMap<PathBuf, [u8; 32]>);
+pub struct Index {
+    entries: BiMap<PathBuf, [u8; 32]>,
+    /// The data key of each file, wrapped under the master key and keyed by
+    /// the hash of the file.
+    ///
+    /// Files backed up before data keys were introduced have no data key, and
+    /// are encrypted directly under the user's private key instead.
+    keys: HashMap<[u8; 32], Encrypted<DataKey>>,
+    /// How the names of the files stored on the peer were derived.
+    path_hashing: PathHashing,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PathHashing {
+    /// Paths were hashed without a key, using [`HashedPath::unkeyed`].
+    Unkeyed,
+    /// Paths were hashed using [`HashedPath::new`].
+    #[default]
+    Keyed,
+    /// Files are being renamed from unkeyed to keyed hashes, so each file may
+    /// be stored under either name.
+    Migrating,
+}
 
 impl Index {
     pub fn new() -> Self {
@@ -52,7 +90,7 @@ impl Index {
             let (path, hash) = result?;
             // TODO: Is unwrap safe?
             index
-                .0
+                .entries
                 .insert(path.strip_prefix(&index_path).unwrap().to_path_buf(), hash);
         }
 
@@ -63,8 +101,11 @@ impl Index {
     pub fn difference(&self, other: &Index) -> Vec<IndexDifference> {
         let mut diff = Vec::new();
 
-        for (path, hash) in &self.0 {
-            match (other.0.get_by_left(path), other.0.get_by_right(hash)) {
+        for (path, hash) in &self.entries {
+            match (
+                other.entries.get_by_left(path),
+                other.entries.get_by_right(hash),
+            ) {
                 (Some(_), Some(_)) => {}
                 (None, Some(old_path)) => diff.push(IndexDifference::Rename {
                     from: old_path.clone(),
@@ -74,8 +115,8 @@ impl Index {
             }
         }
 
-        for (path, hash) in &other.0 {
-            if !self.0.contains_left(path) && !self.0.contains_right(hash) {
+        for (path, hash) in &other.entries {
+            if !self.entries.contains_left(path) && !self.entries.contains_right(hash) {
                 diff.push(IndexDifference::Delete(path.clone()))
             }
         }
@@ -83,9 +124,105 @@ impl Index {
         // TODO: Shuffle diff to not reveal any info on fs to peer?
         diff
     }
+
+    /// Returns the hash of the file at the given path.
+    pub fn hash<P>(&self, path: P) -> Option<&[u8; 32]>
+    where
+        P: AsRef<Path>,
+    {
+        self.entries.get_by_left(path.as_ref())
+    }
+
+    /// Returns the wrapped data key of the file with the given hash.
+    pub fn key(&self, hash: &[u8; 32]) -> Option<&Encrypted<DataKey>> {
+        self.keys.get(hash)
+    }
+
+    pub fn set_key(&mut self, hash: [u8; 32], key: Encrypted<DataKey>) {
+        self.keys.insert(hash, key);
+    }
+
+    /// Copies the data keys of any files in `other` that are also in `self`.
+    ///
+    /// Unchanged and renamed files keep their hash, and so this carries their
+    /// keys over from the old index to the new index.
+    pub fn inherit_keys(&mut self, other: &Index) {
+        for hash in self.entries.right_values() {
+            if let Some(key) = other.keys.get(hash) {
+                self.keys.insert(*hash, key.clone());
+            }
+        }
+    }
+
+    /// Wraps every data key in the index under a new master key, unwrapping
+    /// each key with whichever of the `old` master keys it was wrapped under.
+    ///
+    /// Files without a data key are still encrypted under the private key, and
+    /// are unaffected until they are next written.
+    pub fn rewrap_keys(&mut self, old: &[MasterKey], new: &MasterKey) -> Result<()> {
+        for key in self.keys.values_mut() {
+            *key = key.rewrap(old, new)?;
+        }
+        Ok(())
+    }
+
+    pub fn path_hashing(&self) -> PathHashing {
+        self.path_hashing
+    }
+
+    /// Returns the name under which the file at the given path is stored on
+    /// the peer.
+    pub fn hashed_path<P>(&self, path: P, key: &PathKey) -> HashedPath
+    where
+        P: AsRef<Path>,
+    {
+        match self.path_hashing {
+            PathHashing::Unkeyed => HashedPath::unkeyed(path),
+            PathHashing::Keyed | PathHashing::Migrating => HashedPath::new(path, key),
+        }
+    }
+
+    pub(crate) fn set_path_hashing(&mut self, path_hashing: PathHashing) {
+        self.path_hashing = path_hashing;
+    }
+
+    /// Returns whether both indexes contain the same files, ignoring data
+    /// keys.
+    pub(crate) fn same_entries(&self, other: &Index) -> bool {
+        self.entries == other.entries
+    }
 }
 
 impl Encrypted<Index> {
+    /// Encrypts the index in the current, versioned index format.
+    pub fn encrypt_index(index: &Index, key: &PrivateKey) -> Result<Self> {
+        let mut buf = INDEX_MARKER.to_vec();
+        buf.extend(INDEX_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut buf, index)?;
+        Self::encrypt_bytes(&buf, key)
+    }
+
+    /// Decrypts the index, falling back to the unversioned format, which only
+    /// contained the paths and hashes of files.
+    pub fn decrypt_index(&self, key: &PrivateKey) -> Result<Index> {
+        let buf = self.decrypt_bytes(key)?;
+        match buf.strip_prefix(&INDEX_MARKER) {
+            Some(versioned) => {
+                let (version, index) = versioned.split_at(versioned.len().min(4));
+                match version.try_into().map(u32::from_le_bytes) {
+                    Ok(INDEX_VERSION) => Ok(bincode::deserialize(index)?),
+                    Ok(version) => Err(Error::UnsupportedIndexVersion(version)),
+                    Err(_) => Err(Error::UnexpectedEof),
+                }
+            }
+            None => Ok(Index {
+                entries: bincode::deserialize(&buf)?,
+                keys: HashMap::new(),
+                path_hashing: PathHashing::Unkeyed,
+            }),
+        }
+    }
+
     pub async fn from_disk<P>(path: P) -> Result<Option<Self>>
     where
         P: AsRef<Path>,
@@ -114,7 +251,7 @@ impl<'a> IntoIterator for &'a Index {
     type IntoIter = Iter<'a>;
 
     fn into_iter(self) -> Iter<'a> {
-        self.0.iter()
+        self.entries.iter()
     }
 }
 
@@ -126,3 +263,70 @@ pub enum IndexDifference {
     Rename { from: PathBuf, to: PathBuf },
     Delete(PathBuf),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memorage_core::KeyPair;
+
+    /// An index written before the format was versioned, containing the file
+    /// `a` with a hash of all zeros.
+    const UNVERSIONED_INDEX: [u8; 49] = [
+        1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    #[test]
+    fn decrypt_unversioned_index() {
+        let private = KeyPair::from_entropy().private;
+        let encrypted = Encrypted::<Index>::encrypt_bytes(&UNVERSIONED_INDEX, &private).unwrap();
+
+        let index = encrypted.decrypt_index(&private).unwrap();
+        assert_eq!(index.path_hashing(), PathHashing::Unkeyed);
+        assert_eq!(index.hash("a"), Some(&[0; 32]));
+        assert_eq!(index.key(&[0; 32]), None);
+    }
+
+    #[test]
+    fn decrypt_versioned_index() {
+        let private = KeyPair::from_entropy().private;
+        let mut index = Index::new();
+        index.entries.insert(PathBuf::from("a"), [0; 32]);
+        index.set_key(
+            [0; 32],
+            DataKey::generate()
+                .wrap(&MasterKey::from(&private))
+                .unwrap(),
+        );
+
+        let encrypted = Encrypted::encrypt_index(&index, &private).unwrap();
+        assert_eq!(encrypted.decrypt_index(&private).unwrap(), index);
+
+        let mut buf = INDEX_MARKER.to_vec();
+        buf.extend((INDEX_VERSION + 1).to_le_bytes());
+        let encrypted = Encrypted::<Index>::encrypt_bytes(&buf, &private).unwrap();
+        assert!(matches!(
+            encrypted.decrypt_index(&private),
+            Err(Error::UnsupportedIndexVersion(_))
+        ));
+    }
+
+    #[test]
+    fn migrating_path_hashing() {
+        let private = KeyPair::from_entropy().private;
+        let key = PathKey::from(&private);
+        let mut index = Encrypted::<Index>::encrypt_bytes(&UNVERSIONED_INDEX, &private)
+            .unwrap()
+            .decrypt_index(&private)
+            .unwrap();
+        assert_eq!(index.hashed_path("a", &key), HashedPath::unkeyed("a"));
+
+        index.set_path_hashing(PathHashing::Migrating);
+        let index = Encrypted::encrypt_index(&index, &private)
+            .unwrap()
+            .decrypt_index(&private)
+            .unwrap();
+        assert_eq!(index.path_hashing(), PathHashing::Migrating);
+        assert_eq!(index.hashed_path("a", &key), HashedPath::new("a", &key));
+    }
+}
@@ -2,6 +2,7 @@ mod path;
 mod root;
 
 pub mod index;
+pub mod restore;
 
 pub use path::HashedPath;
 pub use root::RootDirectory;
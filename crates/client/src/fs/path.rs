@@ -1,4 +1,7 @@
+use crate::crypto::PathKey;
+
 use std::{
+    borrow::Cow,
     fmt::Write,
     path::{Path, PathBuf},
 };
@@ -9,11 +12,29 @@ use serde::{Deserialize, Serialize};
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct HashedPath(PathBuf);
 
-impl From<&Path> for HashedPath {
-    fn from(path: &Path) -> Self {
+impl HashedPath {
+    /// Hashes the path using a keyed hash so that the peer cannot confirm
+    /// guesses of the original path.
+    pub fn new<P>(path: P, key: &PathKey) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_hash(blake3::keyed_hash(key.as_bytes(), &path_bytes(path.as_ref())).into())
+    }
+
+    /// Hashes the path the way it was hashed before paths were keyed.
+    ///
+    /// This should only be used to locate files stored by older versions of
+    /// Memorage so that they can be migrated.
+    pub fn unkeyed<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_hash(blake3::hash(path.as_ref().to_string_lossy().as_bytes()).into())
+    }
+
+    fn from_hash(hash: [u8; 32]) -> Self {
         let mut result = String::new();
-        // TODO: to_string_lossy?
-        let hash: [u8; 32] = blake3::hash(path.to_string_lossy().as_bytes()).into();
         for x in hash {
             let _ = write!(result, "{:02x?}", x);
         }
@@ -21,11 +42,32 @@ impl From<&Path> for HashedPath {
     }
 }
 
-// TODO: Specialisation
+/// Returns the raw bytes of the path, so that distinct non-UTF-8 paths don't
+/// collide.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Returns the raw bytes of the path, so that distinct non-UTF-16 paths don't
+/// collide.
+#[cfg(windows)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::windows::ffi::OsStrExt;
+    Cow::Owned(
+        path.as_os_str()
+            .encode_wide()
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+    )
+}
 
-impl From<PathBuf> for HashedPath {
-    fn from(path: PathBuf) -> Self {
-        Self::from(path.as_path())
+#[cfg(not(any(unix, windows)))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
     }
 }
 
@@ -34,3 +76,33 @@ impl AsRef<Path> for HashedPath {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memorage_core::KeyPair;
+
+    #[test]
+    fn keyed_hash() {
+        let key_1 = PathKey::from(&KeyPair::from_entropy().private);
+        let key_2 = PathKey::from(&KeyPair::from_entropy().private);
+        let path = Path::new(".ssh/id_ed25519");
+
+        assert_eq!(HashedPath::new(path, &key_1), HashedPath::new(path, &key_1));
+        assert_ne!(HashedPath::new(path, &key_1), HashedPath::new(path, &key_2));
+        assert_ne!(HashedPath::new(path, &key_1), HashedPath::unkeyed(path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let key = PathKey::from(&KeyPair::from_entropy().private);
+        let path_1 = Path::new(OsStr::from_bytes(b"foo\xff"));
+        let path_2 = Path::new(OsStr::from_bytes(b"foo\xfe"));
+
+        assert_eq!(HashedPath::unkeyed(path_1), HashedPath::unkeyed(path_2));
+        assert_ne!(HashedPath::new(path_1, &key), HashedPath::new(path_2, &key));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::Result;
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// What to do when a retrieved file already exists locally.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Leave the local file untouched.
+    #[default]
+    Skip,
+    /// Replace the local file.
+    Overwrite,
+    /// Keep the local file and write the retrieved file next to it with a
+    /// suffix.
+    KeepBoth,
+    /// Replace the local file only if its hash differs from the indexed hash.
+    IfChanged,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = ConflictPolicyError;
+
+    /// This implementation is only here for clap
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            "keep-both" => Ok(Self::KeepBoth),
+            "if-changed" => Ok(Self::IfChanged),
+            _ => Err(ConflictPolicyError),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ConflictPolicyError;
+
+impl std::fmt::Display for ConflictPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "expected one of skip, overwrite, keep-both or if-changed"
+        )
+    }
+}
+
+impl std::error::Error for ConflictPolicyError {}
+
+/// Where a retrieved file should be written.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Destination {
+    /// No file existed at the path.
+    New(PathBuf),
+    /// The file at the path will be replaced.
+    Overwrite(PathBuf),
+    /// A file existed at the original path, so the file will be written to a
+    /// different path.
+    Renamed(PathBuf),
+    /// The file will not be written.
+    Skip,
+}
+
+impl ConflictPolicy {
+    /// Determines where the file that should be at `path` with hash `hash`
+    /// should be written.
+    pub async fn destination(self, path: PathBuf, hash: [u8; 32]) -> Result<Destination> {
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(Destination::New(path));
+        }
+
+        Ok(match self {
+            Self::Skip => Destination::Skip,
+            Self::Overwrite => Destination::Overwrite(path),
+            Self::KeepBoth => Destination::Renamed(available_path(&path).await?),
+            Self::IfChanged => {
+                let (path, local_hash) = tokio::task::spawn_blocking(move || -> Result<_> {
+                    let local_hash = crate::fs::hash(std::fs::File::open(&path)?)?;
+                    Ok((path, local_hash))
+                })
+                .await??;
+
+                if local_hash == hash {
+                    Destination::Skip
+                } else {
+                    Destination::Overwrite(path)
+                }
+            }
+        })
+    }
+}
+
+/// Returns the first path of the form `name (restored N).ext` that doesn't
+/// exist.
+async fn available_path(path: &Path) -> Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default();
+    let mut n = 1;
+    loop {
+        let mut file_name = OsString::from(stem);
+        if n == 1 {
+            file_name.push(" (restored)");
+        } else {
+            file_name.push(format!(" (restored {n})"));
+        }
+        if let Some(extension) = path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+
+        let candidate = path.with_file_name(file_name);
+        if !tokio::fs::try_exists(&candidate).await? {
+            return Ok(candidate);
+        }
+        n += 1;
+    }
+}
+
+/// The changes made by a retrieval.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RestoreSummary {
+    /// Files that didn't exist locally.
+    pub created: Vec<PathBuf>,
+    /// Files that replaced a local file.
+    pub overwritten: Vec<PathBuf>,
+    /// Files written next to an existing local file, as `(original, written)`.
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// Files that weren't written.
+    pub skipped: Vec<PathBuf>,
+}
+
+impl RestoreSummary {
+    /// Records that the file at `path` was written to `destination`.
+    pub fn record(&mut self, path: PathBuf, destination: Destination) {
+        match destination {
+            Destination::New(p) => self.created.push(p),
+            Destination::Overwrite(p) => self.overwritten.push(p),
+            Destination::Renamed(p) => self.renamed.push((path, p)),
+            Destination::Skip => self.skipped.push(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::hash;
+
+    #[tokio::test]
+    async fn conflict_destination() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        let existing = dir.join("notes.txt");
+        tokio::fs::write(&existing, b"contents").await.unwrap();
+        let existing_hash = hash(&b"contents"[..]).unwrap();
+        let missing = dir.join("missing.txt");
+
+        for policy in [
+            ConflictPolicy::Skip,
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::KeepBoth,
+            ConflictPolicy::IfChanged,
+        ] {
+            assert_eq!(
+                policy.destination(missing.clone(), [0; 32]).await.unwrap(),
+                Destination::New(missing.clone())
+            );
+        }
+
+        assert_eq!(
+            ConflictPolicy::Skip
+                .destination(existing.clone(), [0; 32])
+                .await
+                .unwrap(),
+            Destination::Skip
+        );
+        assert_eq!(
+            ConflictPolicy::Overwrite
+                .destination(existing.clone(), existing_hash)
+                .await
+                .unwrap(),
+            Destination::Overwrite(existing.clone())
+        );
+        assert_eq!(
+            ConflictPolicy::KeepBoth
+                .destination(existing.clone(), [0; 32])
+                .await
+                .unwrap(),
+            Destination::Renamed(dir.join("notes (restored).txt"))
+        );
+        assert_eq!(
+            ConflictPolicy::IfChanged
+                .destination(existing.clone(), existing_hash)
+                .await
+                .unwrap(),
+            Destination::Skip
+        );
+        assert_eq!(
+            ConflictPolicy::IfChanged
+                .destination(existing.clone(), [0; 32])
+                .await
+                .unwrap(),
+            Destination::Overwrite(existing.clone())
+        );
+
+        tokio::fs::write(dir.join("notes (restored).txt"), b"")
+            .await
+            .unwrap();
+        assert_eq!(
+            ConflictPolicy::KeepBoth
+                .destination(existing, [0; 32])
+                .await
+                .unwrap(),
+            Destination::Renamed(dir.join("notes (restored 2).txt"))
+        );
+    }
+}
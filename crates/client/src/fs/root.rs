@@ -55,6 +55,45 @@ impl RootDirectory {
         tracing::error!("peer sent malicious file name");
         Err(Error::MaliciousFileName)
     }
+
+    /// Returns the total size of the files in the directory.
+    ///
+    /// Returns 0 if the directory doesn't exist.
+    pub async fn size(&self) -> Result<u64> {
+        let mut entries = match tokio::fs::read_dir(&self.0).await.map_err(Error::from) {
+            Ok(entries) => entries,
+            Err(Error::NotFound { .. }) => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut size = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+        Ok(size)
+    }
+
+    /// Renames the file `from` to `to`.
+    ///
+    /// Renaming succeeds if `from` was already renamed to `to`, so that an
+    /// interrupted series of renames can be repeated.
+    pub async fn rename<P, Q>(&self, from: P, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let to = self.file_path(to)?;
+        match tokio::fs::rename(self.file_path(from)?, &to)
+            .await
+            .map_err(Error::from)
+        {
+            Err(Error::NotFound { .. }) if tokio::fs::metadata(&to).await.is_ok() => Ok(()),
+            result => result,
+        }
+    }
 }
 
 impl From<PathBuf> for RootDirectory {
@@ -131,4 +170,26 @@ mod tests {
             Err(Error::MaliciousFileName)
         ));
     }
+
+    #[tokio::test]
+    async fn repeated_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let root: RootDirectory = dir.path().into();
+        tokio::fs::write(root.file_path("foo").unwrap(), b"foo")
+            .await
+            .unwrap();
+
+        root.rename("foo", "bar").await.unwrap();
+        root.rename("foo", "bar").await.unwrap();
+        assert!(matches!(
+            root.rename("foo", "baz").await,
+            Err(Error::NotFound { .. })
+        ));
+        assert_eq!(
+            tokio::fs::read(root.file_path("bar").unwrap())
+                .await
+                .unwrap(),
+            b"foo"
+        );
+    }
 }
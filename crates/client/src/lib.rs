@@ -24,3 +24,4 @@ pub mod fs;
 pub mod mnemonic;
 pub mod net;
 pub mod persistent;
+pub mod schedule;
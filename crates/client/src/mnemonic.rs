@@ -1,6 +1,6 @@
 use memorage_core::{rand::seq::SliceRandom, KeyPair, PrivateKey};
 
-use crate::{Error, Result};
+use crate::{crypto::MasterKey, Error, Result};
 
 lazy_static::lazy_static! {
     static ref ARGON2: argon2::Argon2<'static> = {
@@ -67,6 +67,14 @@ impl<'a> std::fmt::Display for MnemonicPhrase<'a> {
     }
 }
 
+impl From<MnemonicPhrase<'_>> for MasterKey {
+    /// Convert a `MnemonicPhrase` into a [`MasterKey`], by deriving it from the
+    /// private key of the [`KeyPair`] that the phrase converts into.
+    fn from(phrase: MnemonicPhrase<'_>) -> Self {
+        MasterKey::from(&KeyPair::from(phrase).private)
+    }
+}
+
 impl From<MnemonicPhrase<'_>> for KeyPair {
     /// Convert a `MnemonicPhrase` into a [`KeyPair`].
     ///
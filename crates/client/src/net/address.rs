@@ -0,0 +1,185 @@
+use crate::Result;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The address of a coordination server.
+///
+/// The address can be an IP address or a hostname, optionally followed by a
+/// port. If no port is given, [`memorage_core::PORT`] is used. IPv6 addresses
+/// with a port must be enclosed in square brackets.
+///
+/// # Examples
+/// ```
+/// # use memorage_client::net::ServerAddress;
+/// let ip: ServerAddress = "45.79.238.170".parse().unwrap();
+/// let ipv6_with_port: ServerAddress = "[2001:db8::1]:4000".parse().unwrap();
+/// let hostname: ServerAddress = "memorage.example.org:4000".parse().unwrap();
+/// assert_eq!(hostname.port(), 4000);
+/// assert_eq!(ip.port(), memorage_core::PORT);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ServerAddress {
+    host: Host,
+    port: u16,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Host {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+impl ServerAddress {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Resolves the address to socket addresses, performing a DNS lookup if
+    /// the address is a hostname.
+    pub async fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        match self.host {
+            Host::Ip(ip) => Ok(vec![SocketAddr::new(ip, self.port)]),
+            Host::Domain(ref domain) => Ok(tokio::net::lookup_host((domain.as_str(), self.port))
+                .await?
+                .collect()),
+        }
+    }
+}
+
+impl From<IpAddr> for ServerAddress {
+    fn from(ip: IpAddr) -> Self {
+        Self {
+            host: Host::Ip(ip),
+            port: memorage_core::PORT,
+        }
+    }
+}
+
+impl From<SocketAddr> for ServerAddress {
+    fn from(address: SocketAddr) -> Self {
+        Self {
+            host: Host::Ip(address.ip()),
+            port: address.port(),
+        }
+    }
+}
+
+impl FromStr for ServerAddress {
+    type Err = ServerAddressError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(address) = s.parse::<SocketAddr>() {
+            return Ok(address.into());
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(ip.into());
+        }
+
+        let (domain, port) = match s.rsplit_once(':') {
+            Some((domain, port)) => (domain, port.parse().map_err(|_| ServerAddressError)?),
+            None => (s, memorage_core::PORT),
+        };
+
+        let valid = !domain.is_empty()
+            && domain
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if valid {
+            Ok(Self {
+                host: Host::Domain(domain.to_owned()),
+                port,
+            })
+        } else {
+            Err(ServerAddressError)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ServerAddressError;
+
+impl std::fmt::Display for ServerAddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid server address")
+    }
+}
+
+impl std::error::Error for ServerAddressError {}
+
+impl std::fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The port is omitted if it is the default, so that addresses written
+        // before ports were supported round trip.
+        match (&self.host, self.port == memorage_core::PORT) {
+            (Host::Ip(ip), true) => write!(f, "{ip}"),
+            (Host::Ip(ip), false) => write!(f, "{}", SocketAddr::new(*ip, self.port)),
+            (Host::Domain(domain), true) => write!(f, "{domain}"),
+            (Host::Domain(domain), false) => write!(f, "{domain}:{}", self.port),
+        }
+    }
+}
+
+impl Serialize for ServerAddress {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerAddress {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_server_address() {
+        for (s, port) in [
+            ("45.79.238.170", memorage_core::PORT),
+            ("45.79.238.170:4000", 4000),
+            ("2001:db8::1", memorage_core::PORT),
+            ("[2001:db8::1]:4000", 4000),
+            ("memorage.example.org", memorage_core::PORT),
+            ("memorage.example.org:4000", 4000),
+            ("localhost", memorage_core::PORT),
+        ] {
+            let address = s.parse::<ServerAddress>().unwrap();
+            assert_eq!(address.port(), port);
+            assert_eq!(address.to_string(), s);
+        }
+
+        for s in [
+            "",
+            ":4000",
+            "example.org:",
+            "example.org:70000",
+            "exa mple.org",
+        ] {
+            assert!(s.parse::<ServerAddress>().is_err(), "{s}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_ip() {
+        let address = "[::1]:4000".parse::<ServerAddress>().unwrap();
+        assert_eq!(
+            address.resolve().await.unwrap(),
+            vec!["[::1]:4000".parse().unwrap()]
+        );
+    }
+}
@@ -1,5 +1,8 @@
 use crate::{
-    net::peer::{IncomingConnection, OutgoingConnection},
+    net::{
+        peer::{IncomingConnection, OutgoingConnection},
+        ServerAddress,
+    },
     persistent::{
         config::Config,
         data::{Data, KeyPairData},
@@ -7,19 +10,47 @@ use crate::{
     Error, Result,
 };
 
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
 
-use memorage_core::{time::OffsetDateTime, Mutex, PublicKey};
+use memorage_core::{time::OffsetDateTime, Mutex, NatType, PublicKey};
 use memorage_cs::{
     request::{self, Request},
-    PairingCode,
+    response::{ConnectionRequest, Strategy},
+    Candidate, CandidateKind, PairingCode,
 };
+use memorage_stun::{Consensus, Retransmission};
 
-use futures_util::StreamExt;
+use futures_util::{stream::FuturesUnordered, StreamExt};
 use quinn::{Endpoint, EndpointConfig, Incoming, NewConnection};
+use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::UdpSocket;
 use tracing::{debug, info, trace, warn};
 
+/// How long to wait for a coordination server before trying the next one.
+const SERVER_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long to wait for a STUN server before trying the next one.
+const STUN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// How long to wait for a connection to a peer, either directly or through a
+/// relay.
+const PEER_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How many times to send the relay token, in case some are lost.
+const RELAY_TOKEN_SENDS: usize = 3;
+
+/// How many ports following the peer's port are punched when its NAT
+/// allocates a new port for each destination.
+const PREDICTED_PORTS: u16 = 16;
+
+lazy_static::lazy_static! {
+    /// The coordination server that last accepted a connection.
+    static ref LAST_WORKING_SERVER: Mutex<Option<ServerAddress>> = Mutex::new(None);
+}
+
 #[derive(Debug)]
 pub struct Client<T>
 where
@@ -28,7 +59,6 @@ where
     data: Arc<Mutex<T>>,
     config: Arc<Mutex<Config>>,
     send_config: quinn::ClientConfig,
-    public_address: IpAddr,
     endpoint: Endpoint,
     incoming: Incoming,
     socket: UdpSocket,
@@ -39,17 +69,19 @@ where
     T: KeyPairData,
 {
     pub async fn new(data: Arc<Mutex<T>>, config: Arc<Mutex<Config>>) -> Result<Client<T>> {
-        let mut socket = UdpSocket::bind("0.0.0.0:0").await?;
-        // TODO: Custom STUN address.
-        let public_address =
-            memorage_stun::public_address(&mut socket, memorage_stun::DEFAULT_STUN_SERVER).await?;
-        info!(%public_address, "received public address");
-        let public_address = public_address.ip();
+        let socket = bind()?;
+        // The public address is only discovered before connecting to a peer,
+        // and certificates aren't verified against the address they name.
+        let address = socket.local_addr()?.ip();
 
         let key_pair = data.lock().key_pair();
+        let server_keys = config.lock().server_key.clone();
+        if server_keys.is_empty() {
+            warn!("no server keys pinned - accepting any coordination server");
+        }
 
-        let (send_config, recv_config) =
-            memorage_cert::gen_configs(public_address, &key_pair, None)?;
+        let send_config = memorage_cert::gen_pinned_send_config(address, &key_pair, &server_keys)?;
+        let recv_config = memorage_cert::gen_recv_config(address, &key_pair, None)?;
 
         let socket = socket.into_std()?;
         let cloned_socket = socket.try_clone()?;
@@ -60,33 +92,196 @@ where
             data,
             config,
             send_config,
-            public_address,
             endpoint,
             incoming,
             socket: UdpSocket::from_std(cloned_socket)?,
         })
     }
 
+    /// Moves the endpoint to a new socket, returning the socket's candidates,
+    /// highest priority first, and the behaviour of its NAT.
+    ///
+    /// This takes several STUN transactions, so it is only done before
+    /// connecting to a peer. They are sent before the endpoint is moved, as it
+    /// reads every packet that arrives on its socket.
+    async fn gather(&mut self) -> Result<(Vec<Candidate>, Option<NatType>)> {
+        let socket = bind()?;
+        let (candidates, stun_server) = Self::gather_candidates(&socket, &self.config).await?;
+        info!(?candidates, "gathered candidates");
+
+        let nat = match stun_server {
+            Some(stun_server) => match memorage_stun::nat_type(&socket, stun_server).await {
+                Ok(nat) => {
+                    info!(?nat, "discovered NAT behaviour");
+                    Some(nat)
+                }
+                Err(e) => {
+                    debug!(?e, "failed to discover NAT behaviour");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let socket = socket.into_std()?;
+        self.socket = UdpSocket::from_std(socket.try_clone()?)?;
+        self.endpoint.rebind(socket)?;
+        Ok((candidates, nat))
+    }
+
+    /// Gathers the candidates of the socket: its host candidates, and its
+    /// server-reflexive candidates from the configured STUN servers, or else
+    /// the coordination servers.
+    ///
+    /// IPv4 and IPv6 servers are queried separately. Returns the candidates,
+    /// highest priority first, and an IPv4 STUN server that reported the
+    /// socket's address.
+    async fn gather_candidates(
+        socket: &UdpSocket,
+        config: &Mutex<Config>,
+    ) -> Result<(Vec<Candidate>, Option<SocketAddr>)> {
+        let (stun_servers, servers) = {
+            let config = config.lock();
+            (config.stun_server.clone(), config.server_address.clone())
+        };
+
+        let mut addresses: Vec<SocketAddr> = Vec::new();
+        if stun_servers.is_empty() {
+            for server in servers {
+                match server.resolve().await {
+                    Ok(resolved) => addresses.extend(resolved.into_iter().map(|mut address| {
+                        address.set_port(memorage_stun::DEFAULT_STUN_PORT);
+                        address
+                    })),
+                    Err(e) => warn!(%server, ?e, "failed to resolve coordination server"),
+                }
+            }
+        } else {
+            for server in stun_servers {
+                match tokio::net::lookup_host(&server).await {
+                    Ok(resolved) => addresses.extend(resolved),
+                    Err(e) => warn!(%server, ?e, "failed to resolve STUN server"),
+                }
+            }
+        }
+        // A server that resolves to the same address twice only gets one vote.
+        let mut seen = std::collections::HashSet::new();
+        addresses.retain(|address| seen.insert(*address));
+        if addresses.is_empty() {
+            return Err(Error::NoServers);
+        }
+
+        let retransmission = Retransmission {
+            timeout: STUN_TIMEOUT,
+            ..Default::default()
+        };
+        let local_address = socket.local_addr()?;
+        let (ipv6_servers, ipv4_servers): (Vec<_>, Vec<_>) =
+            addresses.into_iter().partition(SocketAddr::is_ipv6);
+
+        let mut reflexive = Vec::new();
+        if local_address.is_ipv6() {
+            match consensus(socket, &ipv6_servers, &retransmission).await {
+                Ok(consensus) => reflexive.push(consensus.address),
+                Err(e) => debug!(?e, "failed to discover public IPv6 address"),
+            }
+        }
+        let mut stun_server = None;
+        match consensus(socket, &ipv4_servers, &retransmission).await {
+            Ok(consensus) => {
+                reflexive.push(consensus.address);
+                stun_server = Some(consensus.servers[0]);
+            }
+            Err(e) if reflexive.is_empty() => return Err(e),
+            Err(e) => warn!(?e, "failed to discover public IPv4 address"),
+        }
+
+        let mut candidates: Vec<Candidate> = host_addresses(local_address.is_ipv6())
+            .into_iter()
+            .map(|ip| {
+                let address = SocketAddr::new(ip, local_address.port());
+                Candidate::new(address, CandidateKind::Host)
+            })
+            .collect();
+        // Server-reflexive candidates are redundant if the address isn't
+        // translated.
+        for address in reflexive {
+            if !candidates
+                .iter()
+                .any(|candidate| candidate.address == address)
+            {
+                candidates.push(Candidate::new(address, CandidateKind::ServerReflexive));
+            }
+        }
+        candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.priority));
+
+        Ok((candidates, stun_server))
+    }
+
+    /// Connects to the first reachable coordination server, starting with the
+    /// server that last accepted a connection.
+    async fn connect_to_server(&self) -> Result<NewConnection> {
+        let mut servers = self.config.lock().server_address.clone();
+        if let Some(ref last) = *LAST_WORKING_SERVER.lock() {
+            if let Some(i) = servers.iter().position(|s| s == last) {
+                servers[..=i].rotate_right(1);
+            }
+        }
+
+        let mut last_error = Error::NoServers;
+        for server in servers {
+            let addresses = match server.resolve().await {
+                Ok(addresses) => addresses,
+                Err(e) => {
+                    warn!(%server, ?e, "failed to resolve coordination server");
+                    last_error = e;
+                    continue;
+                }
+            };
+
+            for address in addresses {
+                let connecting =
+                    self.endpoint
+                        .connect_with(self.send_config.clone(), address, "ooga.com")?;
+                let result = match tokio::time::timeout(SERVER_CONNECT_TIMEOUT, connecting).await {
+                    Ok(result) => result.map_err(Error::from),
+                    Err(_) => Err(Error::ServerTimeout),
+                };
+
+                match result {
+                    Ok(connection) => {
+                        *LAST_WORKING_SERVER.lock() = Some(server);
+                        return Ok(connection);
+                    }
+                    Err(e) => {
+                        warn!(%server, %address, ?e, "failed to connect to coordination server");
+                        last_error = e;
+                    }
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
     async fn request<R>(&self, request: R) -> Result<R::Response>
+    where
+        R: memorage_cs::Serialize + Request + std::fmt::Debug,
+    {
+        Ok(self.request_with_address(request).await?.0)
+    }
+
+    /// Sends a request, returning the response along with the address of the
+    /// coordination server that handled it.
+    async fn request_with_address<R>(&self, request: R) -> Result<(R::Response, SocketAddr)>
     where
         R: memorage_cs::Serialize + Request + std::fmt::Debug,
     {
         debug!(?request, "sending request");
 
-        let server_socket_addresses = self.config.lock().server_socket_addresses().clone();
-
-        let (mut send, recv) = self
-            .endpoint
-            .connect_with(
-                self.send_config.clone(),
-                // TODO: Iterate over all supplied addresses until one connects.
-                server_socket_addresses[0],
-                "ooga.com",
-            )?
-            .await?
-            .connection
-            .open_bi()
-            .await?;
+        let connection = self.connect_to_server().await?.connection;
+        let address = connection.remote_address();
+        let (mut send, recv) = connection.open_bi().await?;
 
         let encoded = memorage_cs::serialize(request)?;
         send.write_all(&encoded).await?;
@@ -96,7 +291,7 @@ where
         let response = memorage_cs::deserialize::<_, memorage_cs::Result<R::Response>>(&buffer)?
             .map_err(|e| e.into());
         debug!(?response, "received response");
-        response
+        response.map(|response| (response, address))
     }
 
     pub async fn register(&self) -> Result<PairingCode> {
@@ -109,7 +304,7 @@ where
         let register_response = self.config.lock().register_response;
 
         loop {
-            tokio::time::sleep(register_response.ping_delay).await;
+            tokio::time::sleep(register_response.delay(counter)).await;
 
             match self.request(request::GetRegisterResponse).await {
                 Ok(pk) => return Ok(pk.0),
@@ -135,7 +330,19 @@ impl Client<Data> {
     // receive_incoming_connection.
 
     /// Establish a connection to a peer.
+    ///
+    /// The connection is scheduled after the configured delay, which gives the
+    /// peer time to notice the request.
     pub async fn schedule_outgoing_connection(&self) -> Result<OffsetDateTime> {
+        let time = OffsetDateTime::now_utc() + self.config.lock().outgoing_schedule_delay;
+        self.schedule_outgoing_connection_at(time).await
+    }
+
+    /// Establish a connection to a peer at the given time.
+    pub async fn schedule_outgoing_connection_at(
+        &self,
+        time: OffsetDateTime,
+    ) -> Result<OffsetDateTime> {
         let data = (*self.data.lock()).clone();
         debug!(
             public_key=?data.key_pair.public,
@@ -143,7 +350,6 @@ impl Client<Data> {
             "trying to establish connection"
         );
         let target = data.peer;
-        let time = OffsetDateTime::now_utc() + self.config.lock().outgoing_schedule_delay;
 
         self.request(request::RequestConnection { target, time })
             .await?;
@@ -163,6 +369,10 @@ impl Client<Data> {
         })
     }
 
+    /// Checks for a connection request from the peer, accepting it if there
+    /// is one.
+    ///
+    /// Requests from other keys are ignored.
     pub async fn check_incoming_connection(&self) -> Result<Option<OffsetDateTime>> {
         let data = (*self.data.lock()).clone();
         debug!(
@@ -171,19 +381,38 @@ impl Client<Data> {
             "checking for peer connections"
         );
 
-        let response = match self.request(request::CheckConnection).await {
-            Ok(r) => r,
-            Err(Error::Server(memorage_cs::Error::NoData)) => return Ok(None),
-            Err(e) => return Err(e),
-        };
+        let requests = self.incoming_connection_requests().await?;
+        for request in requests.iter().filter(|r| r.initiator != data.peer) {
+            warn!(initiator=?request.initiator, "ignoring unauthorised connection request");
+        }
 
-        if response.initiator == data.peer {
-            Ok(Some(response.time))
-        } else {
-            Err(Error::UnauthorisedConnectionRequest)
+        // The peer only has one pending request, as a newer request replaces
+        // the old one.
+        match requests.into_iter().find(|r| r.initiator == data.peer) {
+            Some(request) => {
+                self.accept_incoming_connection(request.initiator).await?;
+                Ok(Some(request.time))
+            }
+            None => Ok(None),
         }
     }
 
+    /// Returns the pending connection requests, in the order they were made.
+    pub async fn incoming_connection_requests(&self) -> Result<Vec<ConnectionRequest>> {
+        match self.request(request::CheckConnection).await {
+            Ok(response) => Ok(response.0),
+            Err(Error::Server(memorage_cs::Error::NoData)) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Accepts the connection request from the initiator, allowing the
+    /// initiator and the client to exchange addresses.
+    pub async fn accept_incoming_connection(&self, initiator: PublicKey) -> Result<()> {
+        self.request(request::AcceptConnection(initiator)).await?;
+        Ok(())
+    }
+
     pub async fn receive_incoming_connection(self) -> Result<IncomingConnection> {
         let data = self.data.clone();
         let config = self.config.clone();
@@ -196,52 +425,167 @@ impl Client<Data> {
         })
     }
 
+    /// Converts an IPv4 address to an IPv4-mapped IPv6 address if the socket is
+    /// dual-stack, as not every platform accepts IPv4 destinations on
+    /// dual-stack sockets.
+    fn socket_address(&self, address: SocketAddr) -> Result<SocketAddr> {
+        Ok(match address.ip() {
+            IpAddr::V4(ip) if self.socket.local_addr()?.is_ipv6() => {
+                SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), address.port())
+            }
+            _ => address,
+        })
+    }
+
+    /// Connects to the peer through a relay on the coordination server, for
+    /// when the peer can't be reached directly.
+    ///
+    /// The relay is the peers' relayed candidate, which is only allocated once
+    /// their other candidates have failed, as relays are scarce.
+    async fn connect_relayed(
+        &mut self,
+        peer_key: PublicKey,
+        send_config: quinn::ClientConfig,
+        initiator: bool,
+    ) -> Result<NewConnection> {
+        let (relay, server_address) = self.request_with_address(request::Relay(peer_key)).await?;
+        let relay_address = SocketAddr::new(server_address.ip().to_canonical(), relay.port);
+        debug!(%relay_address, "received relay");
+
+        for _ in 0..RELAY_TOKEN_SENDS {
+            let destination = self.socket_address(relay_address)?;
+            self.socket.send_to(&relay.token, destination).await?;
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        let connection = tokio::time::timeout(
+            PEER_CONNECT_TIMEOUT,
+            self.connect_via(relay_address, send_config, initiator),
+        )
+        .await
+        .map_err(|_| Error::FailedConnection)??;
+        warn!(%relay_address, "session relayed through coordination server");
+        Ok(connection)
+    }
+
+    /// Connects to the peer at the given address, or accepts its connection.
+    async fn connect_via(
+        &mut self,
+        address: SocketAddr,
+        send_config: quinn::ClientConfig,
+        initiator: bool,
+    ) -> Result<NewConnection> {
+        if initiator {
+            self.endpoint
+                .connect_with(send_config, address, "ooga.com")?
+                .await
+                .map_err(|e| e.into())
+        } else {
+            self.accept().await
+        }
+    }
+
+    /// Accepts the first incoming connection to complete its handshake.
+    ///
+    /// The initiator checks its candidates one at a time, so a handshake that
+    /// it abandoned mustn't hold up later ones.
+    async fn accept(&mut self) -> Result<NewConnection> {
+        let mut handshakes = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                connecting = self.incoming.next() => {
+                    handshakes.push(connecting.ok_or(Error::FailedConnection)?);
+                }
+                Some(result) = handshakes.next() => match result {
+                    Ok(connection) => return Ok(connection),
+                    Err(e) => debug!(?e, "incoming handshake failed"),
+                },
+            }
+        }
+    }
+
     async fn connect_to_peer(mut self, initiator: bool) -> Result<NewConnection> {
         let data = (*self.data.lock()).clone();
         let peer_key = data.peer;
 
+        let (candidates, nat) = self.gather().await?;
+        let public_address = public_address(&candidates);
+
         let mut counter = 0;
 
         debug!("sending identification ping");
 
-        let _temp = self.request(request::Ping(peer_key)).await;
+        let ping = request::Ping {
+            target: peer_key,
+            nat,
+            candidates: candidates.clone(),
+        };
+        let _temp = self.request(ping.clone()).await;
         tokio::time::sleep(std::time::Duration::from_secs(5)).await;
 
         let request_connection = self.config.lock().request_connection;
 
         loop {
-            match self.request(request::Ping(peer_key)).await {
-                Ok(memorage_cs::response::Ping(peer_address)) => {
-                    info!(%peer_address, "received peer address");
-                    let (send_config, recv_config) = memorage_cert::gen_configs(
-                        self.public_address,
-                        &data.key_pair,
-                        Some(peer_key),
-                    )?;
+            match self.request(ping.clone()).await {
+                Ok(memorage_cs::response::Ping {
+                    candidates: peer_candidates,
+                    strategy,
+                }) => {
+                    info!(?peer_candidates, ?strategy, "received peer candidates");
+                    let (send_config, recv_config) =
+                        memorage_cert::gen_configs(public_address, &data.key_pair, Some(peer_key))?;
                     self.endpoint.set_server_config(Some(recv_config));
 
-                    self.socket.connect(peer_address).await?;
-                    debug!("connected to peer");
+                    let checks = checks(&candidates, &peer_candidates, strategy, initiator);
+                    if checks.is_empty() {
+                        return self.connect_relayed(peer_key, send_config, initiator).await;
+                    }
 
                     for _ in 0..10 {
-                        let result = self.socket.send(&[15, 96, 13]).await;
-                        trace!(?result, "punching");
+                        for candidate in &checks {
+                            // Only NATs allocate a new port for each
+                            // destination.
+                            let ports = match (strategy, candidate.kind) {
+                                (Strategy::PredictPorts, CandidateKind::ServerReflexive)
+                                    if candidate.address.is_ipv4() =>
+                                {
+                                    PREDICTED_PORTS
+                                }
+                                _ => 1,
+                            };
+                            for offset in 0..ports {
+                                let mut address = candidate.address;
+                                address.set_port(candidate.address.port().wrapping_add(offset));
+                                let destination = self.socket_address(address)?;
+                                let result = self.socket.send_to(&[15, 96, 13], destination).await;
+                                trace!(?result, %address, "punching");
+                            }
+                        }
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                     }
 
-                    return if initiator {
-                        self.endpoint
-                            .connect_with(send_config, peer_address, "ooga.com")?
-                            .await
-                            .map_err(|e| e.into())
-                    } else {
-                        self.incoming
-                            .next()
-                            .await
-                            .ok_or(Error::FailedConnection)?
-                            .await
-                            .map_err(|e| e.into())
-                    };
+                    // The initiator checks each candidate in turn, while the
+                    // other peer accepts a connection to any of them.
+                    let attempts = if initiator { checks.len() } else { 1 };
+                    let timeout = PEER_CONNECT_TIMEOUT / attempts as u32;
+                    for candidate in &checks[..attempts] {
+                        let address = candidate.address;
+                        let result = tokio::time::timeout(
+                            timeout,
+                            self.connect_via(address, send_config.clone(), initiator),
+                        )
+                        .await;
+                        match result {
+                            Ok(Ok(connection)) => {
+                                info!(?candidate, "connected to peer");
+                                return Ok(connection);
+                            }
+                            Ok(Err(e)) => warn!(?candidate, ?e, "connectivity check failed"),
+                            Err(_) => warn!(?candidate, "connectivity check timed out"),
+                        }
+                    }
+
+                    return self.connect_relayed(peer_key, send_config, initiator).await;
                 }
                 Err(Error::Server(memorage_cs::Error::NoData)) => {
                     counter += 1;
@@ -258,7 +602,206 @@ impl Client<Data> {
                 return Err(Error::PeerNoResponse);
             }
 
-            tokio::time::sleep(request_connection.ping_delay).await;
+            tokio::time::sleep(request_connection.delay(counter - 1)).await;
         }
     }
 }
+
+/// Returns the address to name in the certificate, which is preferably the
+/// public IPv4 address as every peer can reach it.
+fn public_address(candidates: &[Candidate]) -> IpAddr {
+    candidates
+        .iter()
+        .filter(|candidate| candidate.kind == CandidateKind::ServerReflexive)
+        .map(|candidate| candidate.address)
+        .find(SocketAddr::is_ipv4)
+        .unwrap_or(candidates[0].address)
+        .ip()
+}
+
+/// Returns the peer's candidates to check, in the order that the initiator
+/// checks them.
+///
+/// Every check is sent from the same socket, so each peer candidate is paired
+/// with the client's highest priority candidate of the same address family,
+/// and skipped if there is none. The peer's server-reflexive IPv4 candidates
+/// are skipped if the session must be relayed anyway.
+fn checks(
+    candidates: &[Candidate],
+    peer_candidates: &[Candidate],
+    strategy: Strategy,
+    initiator: bool,
+) -> Vec<Candidate> {
+    let mut pairs: Vec<_> = peer_candidates
+        .iter()
+        .filter(|peer_candidate| match peer_candidate.kind {
+            CandidateKind::Host => true,
+            CandidateKind::ServerReflexive => {
+                strategy != Strategy::Relay || peer_candidate.address.is_ipv6()
+            }
+            CandidateKind::Relayed => false,
+        })
+        .filter_map(|peer_candidate| {
+            let candidate = candidates
+                .iter()
+                .filter(|candidate| candidate.address.is_ipv6() == peer_candidate.address.is_ipv6())
+                .max_by_key(|candidate| candidate.priority)?;
+            let priority = if initiator {
+                Candidate::pair_priority(candidate, peer_candidate)
+            } else {
+                Candidate::pair_priority(peer_candidate, candidate)
+            };
+            Some((priority, *peer_candidate))
+        })
+        .collect();
+    pairs.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+    pairs
+        .into_iter()
+        .map(|(_, peer_candidate)| peer_candidate)
+        .collect()
+}
+
+/// Queries the STUN servers for the public address of the socket, warning
+/// about servers that disagree with the majority.
+async fn consensus(
+    socket: &UdpSocket,
+    servers: &[SocketAddr],
+    retransmission: &Retransmission,
+) -> Result<Consensus> {
+    if servers.is_empty() {
+        return Err(Error::NoServers);
+    }
+    let consensus =
+        memorage_stun::public_address_consensus(socket, servers, retransmission).await?;
+    for (server, address) in &consensus.dissenting {
+        warn!(%server, %address, "STUN server disagreed on public address");
+    }
+    Ok(consensus)
+}
+
+/// Binds a socket that sends and receives both IPv4 and IPv6 traffic, falling
+/// back to an IPv4 socket if IPv6 is unavailable.
+fn bind() -> std::io::Result<UdpSocket> {
+    let dual_stack = || -> std::io::Result<Socket> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+        Ok(socket)
+    };
+    let socket = match dual_stack() {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(?e, "error binding dual-stack socket");
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Returns the addresses of the host's network interfaces, which is where
+/// peers on the same network can reach it.
+#[cfg(unix)]
+fn host_addresses(ipv6: bool) -> Vec<IpAddr> {
+    use nix::{ifaddrs::getifaddrs, net::if_::InterfaceFlags};
+
+    let interfaces = match getifaddrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            warn!(?e, "failed to enumerate network interfaces");
+            return Vec::new();
+        }
+    };
+
+    let mut addresses = Vec::new();
+    for interface in interfaces {
+        if !interface.flags.contains(InterfaceFlags::IFF_UP)
+            || interface.flags.contains(InterfaceFlags::IFF_LOOPBACK)
+        {
+            continue;
+        }
+        let address = match interface.address {
+            Some(address) => address,
+            None => continue,
+        };
+        let ip = if let Some(address) = address.as_sockaddr_in() {
+            IpAddr::V4(address.ip())
+        } else if let Some(address) = address.as_sockaddr_in6().filter(|_| ipv6) {
+            IpAddr::V6(address.ip())
+        } else {
+            continue;
+        };
+
+        let usable = match ip {
+            IpAddr::V4(ip) => !(ip.is_unspecified() || ip.is_loopback() || ip.is_link_local()),
+            // Link-local addresses are only usable with a scope ID.
+            IpAddr::V6(ip) => {
+                !(ip.is_unspecified() || ip.is_loopback() || ip.segments()[0] & 0xffc0 == 0xfe80)
+            }
+        };
+        if usable && !addresses.contains(&ip) {
+            addresses.push(ip);
+        }
+    }
+    addresses
+}
+
+/// Returns the addresses of the host's network interfaces.
+///
+/// Interfaces can only be enumerated on Unix, so elsewhere peers on the same
+/// network connect through the server-reflexive candidates.
+#[cfg(not(unix))]
+fn host_addresses(_ipv6: bool) -> Vec<IpAddr> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_order() {
+        let candidate = |address: &str, kind| Candidate::new(address.parse().unwrap(), kind);
+        let host = candidate("192.168.0.2:1", CandidateKind::Host);
+        let ipv4 = candidate("1.2.3.4:1", CandidateKind::ServerReflexive);
+        let ipv6 = candidate("[2600::1]:1", CandidateKind::Host);
+        let peer_host = candidate("192.168.0.3:1", CandidateKind::Host);
+        let peer_ipv4 = candidate("5.6.7.8:1", CandidateKind::ServerReflexive);
+        let peer_ipv6 = candidate("[2600::2]:1", CandidateKind::ServerReflexive);
+        let peer_relayed = candidate("9.9.9.9:1", CandidateKind::Relayed);
+        let peer_candidates = [peer_ipv4, peer_relayed, peer_ipv6, peer_host];
+
+        // Host candidates on the same network are checked first, and IPv6
+        // candidates before IPv4 candidates of the same kind.
+        let candidates = [ipv6, host, ipv4];
+        let expected = [peer_host, peer_ipv6, peer_ipv4];
+        assert_eq!(
+            checks(&candidates, &peer_candidates, Strategy::Punch, true),
+            expected
+        );
+        assert_eq!(
+            checks(&candidates, &peer_candidates, Strategy::Punch, false),
+            expected
+        );
+
+        // Without an IPv6 candidate, the peer's IPv6 candidates are skipped.
+        assert_eq!(
+            checks(
+                &[host, ipv4],
+                &peer_candidates,
+                Strategy::PredictPorts,
+                true
+            ),
+            [peer_host, peer_ipv4]
+        );
+
+        // Relayed sessions can still use the local network and IPv6.
+        assert_eq!(
+            checks(&candidates, &peer_candidates, Strategy::Relay, true),
+            [peer_host, peer_ipv6]
+        );
+        assert!(checks(&[ipv4], &[peer_ipv4], Strategy::Relay, true).is_empty());
+    }
+}
@@ -1,5 +1,7 @@
+mod address;
 mod client;
 
+pub use address::{ServerAddress, ServerAddressError};
 pub use client::Client;
 
 pub mod peer;
@@ -2,7 +2,7 @@ use crate::{
     crypto::Encrypted,
     fs::index::Index,
     net::{
-        peer::{receive_packet, send_packet},
+        peer::{receive_packet, send_packet, TransferStats},
         protocol::{
             self,
             request::{self, RequestType},
@@ -30,8 +30,9 @@ pub struct IncomingConnection {
 }
 
 impl IncomingConnection {
-    pub async fn handle(mut self) -> Result<()> {
+    pub async fn handle(mut self) -> Result<TransferStats> {
         let config = (*self.config.lock()).clone();
+        let mut stats = TransferStats::default();
 
         loop {
             let (mut send, mut recv) = self.accept_stream().await?;
@@ -63,11 +64,12 @@ impl IncomingConnection {
                         Ok((path, len)) => {
                             let response = Ok(response::GetFile { len });
                             send_packet(&mut send, &response).await?;
-                            trace!("sent get file response, starting wide copy");
-                            // TODO: Communicate error to peer if it occurs during copying.
-                            // TODO: Handle len == None
-                            crate::util::async_wide_copy(File::open(path).await?, send).await?;
-                            trace!("get file wide copy complete");
+                            if len.is_some() {
+                                trace!("sent get file response, starting wide copy");
+                                // TODO: Communicate error to peer if it occurs during copying.
+                                crate::util::async_wide_copy(File::open(path).await?, send).await?;
+                                trace!("get file wide copy complete");
+                            }
                         }
                         Err(e) => {
                             send_packet(
@@ -78,22 +80,20 @@ impl IncomingConnection {
                         }
                     }
                 }
-                RequestType::Write(request::Write { name, .. }) => {
+                RequestType::Write(request::Write { name, len }) => {
                     let response: crate::Result<_> = try {
                         let path = config.peer_storage_path.file_path(name)?;
                         debug!(?path, "writing to file");
                         crate::util::async_wide_copy(recv, File::create(path).await?).await?;
+                        stats.files += 1;
+                        stats.bytes += len;
                         response::Write
                     };
                     send_packet(&mut send, &response.map_err(|e| e.into())).await?;
                 }
                 RequestType::Rename(request::Rename { from, to }) => {
                     let response: crate::Result<_> = try {
-                        tokio::fs::rename(
-                            config.peer_storage_path.file_path(&from)?,
-                            config.peer_storage_path.file_path(&to)?,
-                        )
-                        .await?;
+                        config.peer_storage_path.rename(&from, &to).await?;
                         response::Rename
                     };
                     send_packet(&mut send, &response.map_err(|e| e.into())).await?;
@@ -117,7 +117,7 @@ impl IncomingConnection {
                     send_packet(&mut send, &Ok(response::Complete)).await?;
                     trace!("sleeping after sending complete response");
                     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    return Ok(());
+                    return Ok(stats);
                 }
             }
             trace!("request handled");
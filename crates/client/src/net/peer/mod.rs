@@ -10,7 +10,16 @@ mod outgoing;
 mod stream;
 
 pub use incoming::IncomingConnection;
-pub use outgoing::OutgoingConnection;
+pub use outgoing::{BackupPlan, OutgoingConnection};
+
+/// Statistics about the files transferred during a connection.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TransferStats {
+    /// The number of files written.
+    pub files: u64,
+    /// The number of encrypted bytes written.
+    pub bytes: u64,
+}
 
 pub async fn sleep_till(time: OffsetDateTime) -> Result<()> {
     let delay = time - OffsetDateTime::now_utc();
@@ -1,12 +1,17 @@
 use crate::{
-    crypto::Encrypted,
-    fs::index::{Index, IndexDifference},
+    crypto::{DataKey, Encrypted, MasterKey, PathKey},
+    fs::{
+        index::{Index, IndexDifference, PathHashing},
+        restore::{ConflictPolicy, Destination, RestoreSummary},
+        HashedPath,
+    },
     net::{
         peer::{
             receive_packet, send_packet,
-            stream::{decrypt_and_wide_copy, encrypt_and_wide_copy},
+            stream::{decrypt_and_wide_copy, encrypt_and_wide_copy, encrypted_len},
+            TransferStats,
         },
-        protocol::{self, request, response, ENCRYPTED_FILE_FRAME_SIZE, FILE_FRAME_SIZE},
+        protocol::{self, request, response, LENGTH_HEADER_SIZE},
     },
     persistent::{config::Config, data::Data},
     Error, Result,
@@ -18,6 +23,16 @@ use memorage_core::Mutex;
 use quinn::{Connection, RecvStream, SendStream};
 use tracing::{debug, info};
 
+/// The changes a backup would make to the peer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BackupPlan {
+    pub difference: Vec<IndexDifference>,
+    /// The total unencrypted length of the files that would be written.
+    pub write_len: u64,
+    /// Whether stored files would be renamed to keyed path hashes.
+    pub migrate_path_hashing: bool,
+}
+
 #[derive(Debug)]
 pub struct OutgoingConnection {
     pub(crate) data: Arc<Mutex<Data>>,
@@ -30,70 +45,223 @@ impl OutgoingConnection {
         self.send_request(&request::Ping).await.map(|_| ())
     }
 
-    pub async fn backup(&self, new_index: &Index) -> Result<()> {
+    /// Computes the changes a backup of `new_index` would make without
+    /// changing anything on the peer.
+    pub async fn plan_backup(&self, new_index: &Index) -> Result<BackupPlan> {
         let old_index = self.get_index().await?;
         let difference = new_index.difference(&old_index);
 
-        if difference.is_empty() {
-            debug_assert_eq!(old_index, *new_index);
+        let backup_path = self.config.lock().backup_path.clone();
+        let mut write_len = 0;
+        for d in &difference {
+            if let IndexDifference::Write(name) = d {
+                write_len += tokio::fs::metadata(backup_path.join(name)).await?.len();
+            }
+        }
+
+        self.send_request(&request::Complete).await?;
+        Ok(BackupPlan {
+            difference,
+            write_len,
+            migrate_path_hashing: old_index.path_hashing() != PathHashing::Keyed,
+        })
+    }
+
+    pub async fn backup(&self, new_index: &Index) -> Result<TransferStats> {
+        let mut stats = TransferStats::default();
+        let mut old_index = self.get_index().await?;
+        let migrated = self.migrate_path_hashing(&mut old_index).await?;
+        let difference = new_index.difference(&old_index);
+
+        if difference.is_empty() && !migrated {
+            debug_assert!(old_index.same_entries(new_index));
             debug!("old index and new index identical");
         } else {
+            let mut new_index = new_index.clone();
+            new_index.inherit_keys(&old_index);
+
             for d in difference {
-                self.send_difference(d).await?;
+                self.send_difference(d, &mut new_index, &mut stats).await?;
             }
             debug!("setting index on peer");
-            let key_pair = self.data.lock().key_pair.private.clone();
-            self.send_request(&request::SetIndex {
-                index: Encrypted::encrypt(new_index, &key_pair)?,
-            })
-            .await?;
+            self.set_index(&new_index).await?;
         }
 
         self.send_request(&request::Complete).await?;
-        Ok(())
+        Ok(stats)
     }
 
-    pub async fn retrieve<P>(&mut self, output: P) -> Result<()>
+    /// Retrieves all stored files into `output`, resolving conflicts with
+    /// existing files using `policy`.
+    pub async fn retrieve<P>(&mut self, output: P, policy: ConflictPolicy) -> Result<RestoreSummary>
     where
         P: AsRef<std::path::Path>,
     {
         let index = self.get_index().await?;
-        let private = self.data.lock().key_pair.private.clone();
-        for (name, _) in index.into_iter() {
+        let (private, master_keys) = {
+            let data = self.data.lock();
+            (data.key_pair.private.clone(), data.master_keys())
+        };
+        let path_key = self.path_key();
+        let mut summary = RestoreSummary::default();
+        for (name, hash) in index.into_iter() {
+            let path = output.as_ref().join(name);
+            let destination = policy.destination(path.clone(), *hash).await?;
+            let write_path = match destination {
+                Destination::New(ref p)
+                | Destination::Overwrite(ref p)
+                | Destination::Renamed(ref p) => p.clone(),
+                Destination::Skip => {
+                    info!(?name, "skipping existing file");
+                    summary.record(path, destination);
+                    continue;
+                }
+            };
+
             info!(?name, "retrieving file");
+            let data_key = index
+                .key(hash)
+                .map(|key| key.unwrap_key(&master_keys))
+                .transpose()?;
 
-            let hashed_name = name.as_path().into();
-            let (response::GetFile { len }, (_, mut recv)) = self
+            let hashed_name = index.hashed_path(name, &path_key);
+            let (response::GetFile { mut len }, (_, mut recv)) = self
                 .send_request(&request::GetFile { name: hashed_name })
                 .await?;
+            if len.is_none() && index.path_hashing() == PathHashing::Migrating {
+                // The file hasn't been renamed by the interrupted migration yet.
+                (response::GetFile { len }, (_, recv)) = self
+                    .send_request(&request::GetFile {
+                        name: HashedPath::unkeyed(name),
+                    })
+                    .await?;
+            }
             // TODO: Remove cast?
             let len = len.ok_or(Error::NotFoundOnPeer)? as usize;
 
             debug!(?name, ?len);
 
-            let mut path = output.as_ref().to_owned();
-            tokio::fs::create_dir_all(path.clone()).await?;
-            path.push(name);
+            if let Some(parent) = write_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            // Decrypt into a temporary file so that a failed retrieval doesn't
+            // clobber an existing file.
+            let mut temp_name = std::ffi::OsString::from(".");
+            temp_name.push(write_path.file_name().unwrap_or_default());
+            temp_name.push(".memorage");
+            let temp_path = write_path.with_file_name(temp_name);
 
-            debug!("writing decrypted file to {}", path.display());
+            debug!("writing decrypted file to {}", write_path.display());
 
-            decrypt_and_wide_copy(&mut recv, &private, &path, len).await?;
+            let result: Result<()> = try {
+                match data_key {
+                    Some(ref data_key) => {
+                        decrypt_and_wide_copy(&mut recv, data_key, &temp_path, len).await?
+                    }
+                    // Files backed up before data keys were introduced are
+                    // encrypted under the private key.
+                    None => decrypt_and_wide_copy(&mut recv, &private, &temp_path, len).await?,
+                }
+                tokio::fs::rename(&temp_path, &write_path).await?;
+            };
+            if result.is_err() {
+                // The temporary file may not have been created.
+                let _ = tokio::fs::remove_file(&temp_path).await;
+            }
+            result?;
             info!(?name, "successfully retrieved file");
+
+            summary.record(path, destination);
         }
 
+        self.send_request(&request::Complete).await?;
+        Ok(summary)
+    }
+
+    /// Wraps the data keys of all backed up files under the current master key
+    /// and uploads the index.
+    ///
+    /// Data keys may be wrapped under any of the master keys in the data file,
+    /// so an interrupted rotation can be resumed. No files are re-uploaded.
+    pub async fn rotate_key(&self) -> Result<()> {
+        let mut index = self.get_index().await?;
+        let (master_keys, master_key) = {
+            let data = self.data.lock();
+            (data.master_keys(), data.master_key())
+        };
+        index.rewrap_keys(&master_keys, &master_key)?;
+
+        debug!("setting rotated index on peer");
+        self.set_index(&index).await?;
+
         self.send_request(&request::Complete).await?;
         Ok(())
     }
 
+    /// Renames files stored under unkeyed path hashes to their keyed names.
+    ///
+    /// The index is marked as migrating on the peer before any files are
+    /// renamed, and renames can be repeated, so an interrupted migration is
+    /// resumed by the next backup.
+    ///
+    /// Returns whether any migration took place, in which case the index must
+    /// be set on the peer even if no files changed.
+    async fn migrate_path_hashing(&self, index: &mut Index) -> Result<bool> {
+        match index.path_hashing() {
+            PathHashing::Keyed => return Ok(false),
+            PathHashing::Unkeyed => {
+                index.set_path_hashing(PathHashing::Migrating);
+                self.set_index(index).await?;
+            }
+            PathHashing::Migrating => info!("resuming interrupted migration"),
+        }
+
+        info!("migrating file names on peer to keyed hashes");
+        let path_key = self.path_key();
+        // The bimap iterator isn't Send, so the renames are computed up front.
+        let renames = index
+            .into_iter()
+            .map(|(name, _)| (HashedPath::unkeyed(name), HashedPath::new(name, &path_key)))
+            .collect::<Vec<_>>();
+        for (from, to) in renames {
+            self.send_request(&request::Rename { from, to }).await?;
+        }
+        index.set_path_hashing(PathHashing::Keyed);
+        Ok(true)
+    }
+
+    fn master_key(&self) -> MasterKey {
+        self.data.lock().master_key()
+    }
+
+    fn path_key(&self) -> PathKey {
+        PathKey::from(&self.data.lock().key_pair.private)
+    }
+
     async fn get_index(&self) -> Result<Index> {
         let private = self.data.lock().key_pair.private.clone();
         Ok(match self.send_request(&request::GetIndex).await?.0.index {
-            Some(i) => i.decrypt(&private)?,
+            Some(i) => i.decrypt_index(&private)?,
             None => Index::new(),
         })
     }
 
-    async fn send_difference(&self, diff: IndexDifference) -> Result<()> {
+    async fn set_index(&self, index: &Index) -> Result<()> {
+        let private = self.data.lock().key_pair.private.clone();
+        self.send_request(&request::SetIndex {
+            index: Encrypted::encrypt_index(index, &private)?,
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn send_difference(
+        &self,
+        diff: IndexDifference,
+        new_index: &mut Index,
+        stats: &mut TransferStats,
+    ) -> Result<()> {
         debug!(difference=?diff, "sending difference");
         match diff {
             IndexDifference::Write(name) => {
@@ -101,37 +269,46 @@ impl OutgoingConnection {
                 debug!(?name, ?path, "writing file to peer");
 
                 let len = tokio::fs::metadata(&path).await?.len();
-                let num_chunks = len.div_ceil(FILE_FRAME_SIZE as u64);
-                let encrypted_len =
-                    len + num_chunks * (ENCRYPTED_FILE_FRAME_SIZE - FILE_FRAME_SIZE) as u64;
+                let padding = self.config.lock().padding;
+                let padded_len = padding.padded_len(len + LENGTH_HEADER_SIZE as u64);
+                let encrypted_len = encrypted_len(padded_len);
 
-                debug!(?len, ?num_chunks, ?encrypted_len, "sending write request");
+                debug!(?len, ?padded_len, ?encrypted_len, "sending write request");
 
                 let (mut send, mut recv) = self
                     .send_request_without_response(&request::Write {
                         len: encrypted_len,
-                        name: name.as_path().into(),
+                        name: HashedPath::new(&name, &self.path_key()),
                     })
                     .await?;
 
-                let private = self.data.lock().key_pair.private.clone();
-                encrypt_and_wide_copy(&mut send, &private, &path, len).await?;
+                let data_key = DataKey::generate();
+                encrypt_and_wide_copy(&mut send, &data_key, &path, len, padded_len).await?;
 
                 send.finish().await?;
                 receive_packet::<protocol::Result<protocol::response::Write>>(&mut recv).await??;
 
+                // The unwrap is safe as writes are only generated for files in the new index.
+                let hash = *new_index.hash(&name).unwrap();
+                new_index.set_key(hash, data_key.wrap(&self.master_key())?);
+                stats.files += 1;
+                stats.bytes += encrypted_len;
+
                 debug!("successfully wrote file to peer");
             }
             IndexDifference::Rename { from, to } => {
+                let path_key = self.path_key();
                 self.send_request(&request::Rename {
-                    from: from.into(),
-                    to: to.into(),
+                    from: HashedPath::new(from, &path_key),
+                    to: HashedPath::new(to, &path_key),
                 })
                 .await?;
             }
             IndexDifference::Delete(name) => {
-                self.send_request(&request::Delete { name: name.into() })
-                    .await?;
+                self.send_request(&request::Delete {
+                    name: HashedPath::new(name, &self.path_key()),
+                })
+                .await?;
             }
         }
         Ok(())
@@ -1,59 +1,115 @@
 use crate::{
-    crypto,
-    net::protocol::{ENCRYPTED_FILE_FRAME_SIZE, FILE_FRAME_SIZE, NONCE_LENGTH, TAG_LENGTH},
+    crypto::{self, DataKey, EncryptionKey},
+    net::protocol::{
+        ENCRYPTED_FILE_FRAME_SIZE, FILE_FRAME_SIZE, LENGTH_HEADER_SIZE, NONCE_LENGTH,
+        STREAM_HEADER_SIZE, STREAM_MARKER, STREAM_VERSION, TAG_LENGTH,
+    },
     Error, Result,
 };
 
-use std::path::Path;
+use std::{io::Cursor, path::Path};
 
-use memorage_core::PrivateKey;
-use quinn::{RecvStream, SendStream};
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
 };
 use tracing::{debug, trace};
 
-pub(crate) async fn encrypt_and_wide_copy(
-    send: &mut SendStream,
-    private_key: &PrivateKey,
+/// Returns the length of the stream produced by [`encrypt_and_wide_copy`].
+pub(crate) fn encrypted_len(padded_len: u64) -> u64 {
+    let num_chunks = padded_len.div_ceil(FILE_FRAME_SIZE as u64);
+    STREAM_HEADER_SIZE as u64
+        + padded_len
+        + num_chunks * (ENCRYPTED_FILE_FRAME_SIZE - FILE_FRAME_SIZE) as u64
+}
+
+/// Encrypts the file and copies it to the stream.
+///
+/// The stream starts with the unencrypted stream format marker and version.
+/// The plaintext consists of the length of the file, the contents of the file,
+/// and zeros up to a total length of `padded_len`.
+pub(crate) async fn encrypt_and_wide_copy<W>(
+    send: &mut W,
+    data_key: &DataKey,
     path: &Path,
     contents_len: u64,
-) -> Result<()> {
+    padded_len: u64,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    debug_assert!(padded_len >= contents_len + LENGTH_HEADER_SIZE as u64);
+
     let mut buf = [0; ENCRYPTED_FILE_FRAME_SIZE];
-    let mut contents_len_left = contents_len as usize;
+    let mut padded_len_left = padded_len as usize;
 
-    let mut file = File::open(path).await?;
+    send.write_all(&STREAM_MARKER).await?;
+    send.write_all(&[STREAM_VERSION]).await?;
 
-    while contents_len_left != 0 {
-        let data_read_len = std::cmp::min(FILE_FRAME_SIZE, contents_len_left as usize);
+    let file = File::open(path).await?;
+    let mut reader = Cursor::new(contents_len.to_le_bytes())
+        .chain(file.take(contents_len))
+        .chain(tokio::io::repeat(0));
+
+    while padded_len_left != 0 {
+        let data_read_len = std::cmp::min(FILE_FRAME_SIZE, padded_len_left);
 
         debug!(?data_read_len);
 
         let buf_slice = &mut buf[..(NONCE_LENGTH + data_read_len + TAG_LENGTH)];
         let (nonce_slice, data_slice, tag_slice) = crypto::split_encrypted_buf(buf_slice);
 
-        file.read_exact(data_slice).await?;
-        let (nonce, tag) = crypto::encrypt_in_place(data_slice, private_key)?;
+        reader.read_exact(data_slice).await?;
+        let (nonce, tag) = crypto::encrypt_in_place(data_slice, data_key)?;
         nonce_slice.copy_from_slice(nonce.as_slice());
         tag_slice.copy_from_slice(tag.as_slice());
 
         send.write_all(buf_slice).await?;
-        contents_len_left -= data_read_len;
+        padded_len_left -= data_read_len;
     }
     Ok(())
 }
 
-pub(crate) async fn decrypt_and_wide_copy(
-    recv: &mut RecvStream,
-    private_key: &PrivateKey,
+/// Decrypts the stream and copies it to the file, stripping the length
+/// header and padding.
+///
+/// Streams without a format marker were encrypted before files were padded,
+/// and are copied without stripping anything.
+pub(crate) async fn decrypt_and_wide_copy<R, K>(
+    recv: &mut R,
+    key: &K,
     path: &Path,
     contents_len: usize,
-) -> Result<()> {
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    K: EncryptionKey,
+{
     let mut file = File::create(path).await?;
 
     let mut buf = [0; ENCRYPTED_FILE_FRAME_SIZE];
     let mut contents_len_left = contents_len;
+    // The number of bytes of the first frame read while checking for a marker.
+    let mut read_ahead = 0;
+
+    let versioned = contents_len >= STREAM_HEADER_SIZE && {
+        recv.read_exact(&mut buf[..STREAM_HEADER_SIZE]).await?;
+        if buf[..STREAM_MARKER.len()] == STREAM_MARKER {
+            match buf[STREAM_MARKER.len()] {
+                STREAM_VERSION => {}
+                version => return Err(Error::UnsupportedStreamVersion(version)),
+            }
+            contents_len_left -= STREAM_HEADER_SIZE;
+            true
+        } else {
+            read_ahead = STREAM_HEADER_SIZE;
+            false
+        }
+    };
+    trace!(?versioned, "read stream header");
+
+    // Set once the header has been read from the first frame.
+    let mut unpadded_len_left = None;
 
     while contents_len_left != 0 {
         let read_len: usize = std::cmp::min(ENCRYPTED_FILE_FRAME_SIZE, contents_len_left);
@@ -65,12 +121,103 @@ pub(crate) async fn decrypt_and_wide_copy(
 
         trace!(?read_len, "reading frame");
 
-        recv.read_exact(&mut buf[..read_len]).await?;
-
-        let data = crypto::decrypt_in_place(private_key, &mut buf[..read_len])?;
+        recv.read_exact(&mut buf[read_ahead..read_len]).await?;
+        read_ahead = 0;
+
+        let mut data = crypto::decrypt_in_place(key, &mut buf[..read_len])?;
+
+        if versioned {
+            let len_left = match unpadded_len_left {
+                Some(ref mut len_left) => len_left,
+                None => {
+                    if data.len() < LENGTH_HEADER_SIZE {
+                        return Err(Error::FrameTooShort);
+                    }
+                    let (header, rest) = data.split_at(LENGTH_HEADER_SIZE);
+                    // The unwrap is safe as the header has the correct length.
+                    let len = u64::from_le_bytes(header.try_into().unwrap()) as usize;
+                    if len > contents_len {
+                        return Err(Error::InvalidLengthHeader);
+                    }
+                    trace!(?len, "read length header");
+                    data = rest;
+                    unpadded_len_left.insert(len)
+                }
+            };
+
+            let write_len = std::cmp::min(*len_left, data.len());
+            data = &data[..write_len];
+            *len_left -= write_len;
+        }
         file.write_all(data).await?;
 
         contents_len_left -= read_len;
     }
-    Ok(())
+
+    match (versioned, unpadded_len_left) {
+        (false, _) | (true, Some(0)) => Ok(()),
+        _ => Err(Error::UnexpectedEof),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memorage_core::KeyPair;
+
+    const CONTENTS: &[u8] = b"super secret message pls don't steal";
+
+    #[tokio::test]
+    async fn padded_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("input"), dir.path().join("output"));
+        tokio::fs::write(&input, CONTENTS).await.unwrap();
+
+        let data_key = DataKey::generate();
+        let padded_len = 128;
+        let mut stream = Vec::new();
+        encrypt_and_wide_copy(
+            &mut stream,
+            &data_key,
+            &input,
+            CONTENTS.len() as u64,
+            padded_len,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.len() as u64, encrypted_len(padded_len));
+        assert!(stream.starts_with(&STREAM_MARKER));
+
+        decrypt_and_wide_copy(&mut &stream[..], &data_key, &output, stream.len())
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&output).await.unwrap(), CONTENTS);
+
+        stream[STREAM_MARKER.len()] = STREAM_VERSION + 1;
+        assert!(matches!(
+            decrypt_and_wide_copy(&mut &stream[..], &data_key, &output, stream.len()).await,
+            Err(Error::UnsupportedStreamVersion(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unversioned_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+
+        // Files were encrypted under the private key, without a marker, header
+        // or padding.
+        let key = KeyPair::from_entropy().private;
+        let mut frame = vec![0; NONCE_LENGTH + CONTENTS.len() + TAG_LENGTH];
+        let (nonce_slice, data_slice, tag_slice) = crypto::split_encrypted_buf(&mut frame);
+        data_slice.copy_from_slice(CONTENTS);
+        let (nonce, tag) = crypto::encrypt_in_place(data_slice, &key).unwrap();
+        nonce_slice.copy_from_slice(&nonce);
+        tag_slice.copy_from_slice(&tag);
+
+        decrypt_and_wide_copy(&mut &frame[..], &key, &output, frame.len())
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&output).await.unwrap(), CONTENTS);
+    }
 }
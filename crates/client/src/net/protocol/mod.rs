@@ -15,3 +15,12 @@ pub(crate) const FILE_FRAME_SIZE: usize = 65536;
 pub(crate) const NONCE_LENGTH: usize = 24;
 pub(crate) const TAG_LENGTH: usize = 16;
 pub(crate) const ENCRYPTED_FILE_FRAME_SIZE: usize = NONCE_LENGTH + FILE_FRAME_SIZE + TAG_LENGTH;
+/// The length of the header containing the unpadded length of a file.
+pub(crate) const LENGTH_HEADER_SIZE: usize = 8;
+/// Prefixes encrypted files, followed by the version of the stream format.
+///
+/// Files encrypted before the format was versioned start with a random nonce,
+/// and so practically never start with the marker.
+pub(crate) const STREAM_MARKER: [u8; 8] = *b"memorage";
+pub(crate) const STREAM_VERSION: u8 = 1;
+pub(crate) const STREAM_HEADER_SIZE: usize = STREAM_MARKER.len() + 1;
@@ -1,19 +1,39 @@
 use crate::{
     fs::RootDirectory,
+    net::ServerAddress,
     persistent::{Persistent, CONFIG_PATH, PROJECT_DIRS},
+    schedule::Schedule,
 };
 
-use std::{
-    net::{IpAddr, SocketAddr},
-    path::PathBuf,
-    time::Duration,
-};
+use std::{path::PathBuf, time::Duration};
 
+use memorage_core::{
+    rand::{thread_rng, Rng},
+    PublicKey,
+};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Config {
-    pub server_address: Vec<IpAddr>,
+    /// Coordination servers, tried in order.
+    pub server_address: Vec<ServerAddress>,
+    /// Public keys that coordination servers must use.
+    ///
+    /// If empty, any key is accepted.
+    #[serde(
+        default,
+        serialize_with = "serialize_public_keys",
+        deserialize_with = "deserialize_public_keys"
+    )]
+    pub server_key: Vec<PublicKey>,
+    /// The STUN servers used to determine the public address, including their
+    /// ports.
+    ///
+    /// A majority of the servers that respond must agree on the address. If
+    /// empty, the coordination servers are queried on
+    /// [`memorage_stun::DEFAULT_STUN_PORT`].
+    #[serde(default)]
+    pub stun_server: Vec<String>,
     /// Path to backup.
     pub backup_path: PathBuf,
     /// Path at which the peer's encrypted data is stored.
@@ -33,8 +53,20 @@ pub struct Config {
         deserialize_with = "deserialize_duration"
     )]
     pub check_incoming_interval: Duration,
+    /// When backups to the peer run, as a cron-like schedule in local time.
+    ///
+    /// If not set, backups run every `schedule_outgoing_interval`. If set,
+    /// connections are requested `outgoing_schedule_delay` before each run.
+    #[serde(default)]
+    pub outgoing_schedule: Option<Schedule>,
+    /// How files are padded before being sent to the peer.
+    #[serde(default)]
+    pub padding: Padding,
     pub register_response: RetryConfig,
     pub request_connection: RetryConfig,
+    /// How the daemon retries after a failed backup.
+    #[serde(default = "RetryConfig::error_retry")]
+    pub error_retry: RetryConfig,
 }
 
 impl Config {
@@ -47,11 +79,60 @@ impl Config {
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RetryConfig {
     pub tries: usize,
+    /// The delay before the first retry.
     #[serde(
         serialize_with = "serialize_duration",
         deserialize_with = "deserialize_duration"
     )]
     pub ping_delay: Duration,
+    /// How the delay grows with each retry.
+    #[serde(default)]
+    pub backoff: Backoff,
+    /// The factor by which the delay grows with each retry when using
+    /// [`Backoff::Exponential`].
+    #[serde(default = "default_multiplier")]
+    pub multiplier: u32,
+    /// The maximum delay between retries, before jitter is applied.
+    #[serde(
+        default = "default_max_delay",
+        serialize_with = "serialize_duration",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub max_delay: Duration,
+    /// How the delay is randomised, so that peers retrying at the same time
+    /// drift apart.
+    #[serde(default)]
+    pub jitter: Jitter,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backoff {
+    /// The delay is always `ping_delay`.
+    #[default]
+    Constant,
+    /// The delay is multiplied by `multiplier` after each retry.
+    Exponential,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Jitter {
+    /// The delay is not randomised.
+    #[default]
+    None,
+    /// The delay is chosen uniformly between zero and the delay.
+    Full,
+    /// The delay is chosen uniformly between half the delay and the delay.
+    Equal,
+}
+
+fn default_multiplier() -> u32 {
+    2
+}
+
+fn default_max_delay() -> Duration {
+    Duration::from_secs(60)
 }
 
 impl RetryConfig {
@@ -59,6 +140,10 @@ impl RetryConfig {
         Self {
             ping_delay: Duration::from_secs(3),
             tries: 20,
+            backoff: Backoff::Constant,
+            multiplier: default_multiplier(),
+            max_delay: default_max_delay(),
+            jitter: Jitter::Equal,
         }
     }
 
@@ -66,6 +151,92 @@ impl RetryConfig {
         Self {
             ping_delay: Duration::from_secs(5),
             tries: 4,
+            backoff: Backoff::Exponential,
+            multiplier: default_multiplier(),
+            max_delay: Duration::from_secs(30),
+            jitter: Jitter::Equal,
+        }
+    }
+
+    fn error_retry() -> Self {
+        Self {
+            ping_delay: Duration::from_secs(30),
+            tries: 6,
+            backoff: Backoff::Exponential,
+            multiplier: default_multiplier(),
+            max_delay: Duration::from_secs(30 * 60),
+            jitter: Jitter::Full,
+        }
+    }
+
+    /// Returns the delay before retry number `attempt`, starting from zero.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Constant => self.ping_delay,
+            Backoff::Exponential => {
+                let factor = u32::try_from(attempt)
+                    .ok()
+                    .and_then(|attempt| self.multiplier.checked_pow(attempt));
+                match factor {
+                    Some(factor) => self.ping_delay.saturating_mul(factor),
+                    None => Duration::MAX,
+                }
+            }
+        };
+        let delay = std::cmp::min(delay, self.max_delay);
+
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(thread_rng().gen()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(thread_rng().gen()),
+        }
+    }
+
+    /// Returns the delay before retrying after `failures` consecutive
+    /// failures, or [`None`] if there have been no failures or the retries
+    /// have been exhausted.
+    pub fn retry_delay(&self, failures: usize) -> Option<Duration> {
+        if failures == 0 || failures > self.tries {
+            None
+        } else {
+            Some(self.delay(failures - 1))
+        }
+    }
+}
+
+/// Padding schemes that hide the exact length of files from the peer.
+///
+/// Schemes that hide more information have a larger storage and bandwidth
+/// overhead.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Padding {
+    /// Files are not padded.
+    None,
+    /// Files are padded to the next power of two, which has an overhead of up
+    /// to 100%.
+    PowerOfTwo,
+    /// Files are padded using Padmé, which has an overhead of at most 12% and
+    /// leaks O(log log n) bits of the length.
+    #[default]
+    Padme,
+}
+
+impl Padding {
+    /// Returns the padded length of data with length `len`.
+    pub fn padded_len(self, len: u64) -> u64 {
+        match self {
+            Self::None => len,
+            Self::PowerOfTwo => len.checked_next_power_of_two().unwrap_or(len),
+            Self::Padme => {
+                if len < 2 {
+                    return len;
+                }
+                let e = u64::from(len.ilog2());
+                let s = u64::from(e.ilog2()) + 1;
+                let mask = (1 << (e - s)) - 1;
+                (len + mask) & !mask
+            }
         }
     }
 }
@@ -74,13 +245,18 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             server_address: vec!["45.79.238.170".parse().unwrap()],
+            server_key: Vec::new(),
+            stun_server: Vec::new(),
             backup_path: PathBuf::new(),
             peer_storage_path: PROJECT_DIRS.data_dir().to_owned().join("peer_data").into(),
             outgoing_schedule_delay: Duration::from_secs(600),
             check_incoming_interval: Duration::from_secs(580),
             schedule_outgoing_interval: Duration::from_secs(2 * 60 * 60),
+            outgoing_schedule: None,
+            padding: Padding::default(),
             register_response: RetryConfig::register_response(),
             request_connection: RetryConfig::request_connection(),
+            error_retry: RetryConfig::error_retry(),
         }
     }
 }
@@ -91,15 +267,6 @@ impl Persistent for Config {
     }
 }
 
-impl Config {
-    pub fn server_socket_addresses(&self) -> Vec<SocketAddr> {
-        self.server_address
-            .iter()
-            .map(|a| SocketAddr::new(*a, memorage_core::PORT))
-            .collect()
-    }
-}
-
 fn serialize_duration<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -107,6 +274,31 @@ where
     serializer.serialize_f64(duration.as_secs_f64())
 }
 
+fn serialize_public_keys<S>(keys: &[PublicKey], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(keys.iter().map(|key| {
+        key.as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    }))
+}
+
+fn deserialize_public_keys<'de, D>(deserializer: D) -> Result<Vec<PublicKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|key| {
+            key.parse()
+                .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(key), &"a public key"))
+        })
+        .collect()
+}
+
 fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
@@ -148,4 +340,98 @@ mod tests {
             config
         );
     }
+
+    #[tokio::test]
+    async fn serialize_schedule() {
+        let config = Config {
+            outgoing_schedule: Some("0 13,23 * * mon-fri".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        config.to_disk(Some(&path)).await.unwrap();
+        assert_eq!(
+            (*Config::from_disk(Some(&path)).await.unwrap().lock()),
+            config
+        );
+    }
+
+    #[tokio::test]
+    async fn serialize_server_key() {
+        let key = memorage_core::KeyPair::from_entropy().public;
+        let config = Config {
+            server_key: vec![key],
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        config.to_disk(Some(&path)).await.unwrap();
+        assert_eq!(
+            (*Config::from_disk(Some(&path)).await.unwrap().lock()),
+            config
+        );
+
+        // Keys can be copied from the server's logs, which separate bytes with
+        // spaces.
+        assert_eq!(key.to_string().parse::<PublicKey>().unwrap(), key);
+    }
+
+    #[test]
+    fn retry_delay() {
+        let retry = RetryConfig {
+            tries: 5,
+            ping_delay: Duration::from_secs(1),
+            backoff: Backoff::Exponential,
+            multiplier: 3,
+            max_delay: Duration::from_secs(20),
+            jitter: Jitter::None,
+        };
+        assert_eq!(retry.delay(0), Duration::from_secs(1));
+        assert_eq!(retry.delay(2), Duration::from_secs(9));
+        assert_eq!(retry.delay(3), Duration::from_secs(20));
+        assert_eq!(retry.delay(usize::MAX), Duration::from_secs(20));
+        assert_eq!(retry.retry_delay(0), None);
+        assert_eq!(retry.retry_delay(1), Some(Duration::from_secs(1)));
+        assert_eq!(retry.retry_delay(6), None);
+
+        let constant = RetryConfig {
+            backoff: Backoff::Constant,
+            ..retry
+        };
+        assert_eq!(constant.delay(4), Duration::from_secs(1));
+
+        for jitter in [Jitter::Full, Jitter::Equal] {
+            let jittered = RetryConfig { jitter, ..retry };
+            for _ in 0..100 {
+                let delay = jittered.delay(2);
+                assert!(delay <= Duration::from_secs(9));
+                if jitter == Jitter::Equal {
+                    assert!(delay >= Duration::from_millis(4500));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn padded_len() {
+        for len in [0, 1, 2, 3, 100, 4097, 1_000_000, u64::MAX / 2] {
+            for padding in [Padding::None, Padding::PowerOfTwo, Padding::Padme] {
+                assert!(padding.padded_len(len) >= len);
+            }
+        }
+
+        assert_eq!(Padding::None.padded_len(100), 100);
+        assert_eq!(Padding::PowerOfTwo.padded_len(100), 128);
+        assert_eq!(Padding::PowerOfTwo.padded_len(128), 128);
+        // E = 9, S = 4, so the last 5 bits are zeroed.
+        assert_eq!(Padding::Padme.padded_len(1000), 1024);
+        assert_eq!(Padding::Padme.padded_len(1025), 1088);
+        // Padmé has a maximum overhead of 12%.
+        let len = 1_000_001;
+        assert!(Padding::Padme.padded_len(len) - len < len * 12 / 100);
+    }
 }
@@ -1,4 +1,7 @@
-use crate::persistent::{Persistent, DATA_PATH};
+use crate::{
+    crypto::MasterKey,
+    persistent::{Persistent, DATA_PATH},
+};
 
 use memorage_core::{KeyPair, PublicKey};
 
@@ -11,9 +14,36 @@ pub struct Data {
         deserialize_with = "deserialize_key_pair"
     )]
     pub key_pair: KeyPair,
+    /// The master key, if it has been rotated.
+    #[serde(default)]
+    pub master_key: Option<MasterKey>,
+    /// The master key that was replaced by an unfinished rotation.
+    ///
+    /// It is kept until the peer's index has been wrapped under the new master
+    /// key.
+    #[serde(default)]
+    pub previous_master_key: Option<MasterKey>,
     pub peer: PublicKey,
 }
 
+impl Data {
+    /// Returns the master key that new data keys are wrapped under.
+    pub fn master_key(&self) -> MasterKey {
+        match self.master_key {
+            Some(ref key) => key.clone(),
+            None => MasterKey::from(&self.key_pair.private),
+        }
+    }
+
+    /// Returns every master key that data keys may be wrapped under, starting
+    /// with the current master key.
+    pub fn master_keys(&self) -> Vec<MasterKey> {
+        let mut keys = vec![self.master_key()];
+        keys.extend(self.previous_master_key.clone());
+        keys
+    }
+}
+
 impl Persistent for Data {
     fn default_path() -> &'static std::path::Path {
         &DATA_PATH
@@ -27,6 +57,10 @@ pub struct DataWithoutPeer {
         deserialize_with = "deserialize_key_pair"
     )]
     pub key_pair: KeyPair,
+    #[serde(default)]
+    pub master_key: Option<MasterKey>,
+    #[serde(default)]
+    pub previous_master_key: Option<MasterKey>,
     pub peer: Option<PublicKey>,
 }
 
@@ -40,9 +74,20 @@ impl DataWithoutPeer {
     pub fn from_key_pair(key_pair: KeyPair) -> Self {
         Self {
             key_pair,
+            master_key: None,
+            previous_master_key: None,
             peer: None,
         }
     }
+
+    pub fn with_peer(self, peer: PublicKey) -> Data {
+        Data {
+            key_pair: self.key_pair,
+            master_key: self.master_key,
+            previous_master_key: self.previous_master_key,
+            peer,
+        }
+    }
 }
 
 pub trait KeyPairData: private::Sealed {
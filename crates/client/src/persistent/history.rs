@@ -0,0 +1,264 @@
+use crate::{
+    net::peer::TransferStats,
+    persistent::{Persistent, HISTORY_PATH},
+    Error, Result,
+};
+
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use memorage_core::{time::OffsetDateTime, PublicKey};
+use serde::{Deserialize, Serialize};
+
+/// A log of past runs, and of runs that are scheduled but haven't happened.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default, with = "memorage_core::time::serde::rfc3339::option")]
+    pub scheduled_outgoing: Option<OffsetDateTime>,
+    #[serde(default, with = "memorage_core::time::serde::rfc3339::option")]
+    pub scheduled_incoming: Option<OffsetDateTime>,
+    /// Runs, from oldest to newest.
+    #[serde(default)]
+    pub runs: Vec<Run>,
+}
+
+impl History {
+    /// The maximum number of runs kept in the history.
+    pub const MAX_RUNS: usize = 1000;
+
+    /// Loads the history, returning an empty history if the file doesn't
+    /// exist.
+    pub async fn from_disk_or_default<P>(path: Option<P>) -> Result<Self>
+    where
+        P: AsRef<Path> + Send,
+    {
+        match Self::from_disk(path).await {
+            Ok(history) => Ok(history.lock().clone()),
+            Err(Error::NotFound { .. }) => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the path of the history file stored next to the given data
+    /// file.
+    pub fn path_next_to<P>(data_path: Option<P>) -> Option<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        data_path.map(|p| p.as_ref().with_file_name("history.toml"))
+    }
+
+    /// Loads the history, applies `f` to it, and saves it.
+    ///
+    /// The daemon and the CLI may update the history at the same time, so the
+    /// update holds a lock on a file next to the history, and replaces the
+    /// history atomically.
+    pub async fn update<P, F>(path: Option<P>, f: F) -> Result<()>
+    where
+        P: AsRef<Path> + Send + Sync,
+        F: FnOnce(&mut Self),
+    {
+        let path = match path {
+            Some(ref p) => p.as_ref(),
+            None => Self::default_path(),
+        };
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let lock_path = path.with_extension("lock");
+        // The lock is released when the file is closed.
+        let _lock = tokio::task::spawn_blocking(move || -> Result<_> {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(lock_path)?;
+            file.lock_exclusive()?;
+            Ok(file)
+        })
+        .await??;
+
+        let mut history = Self::from_disk_or_default(Some(path)).await?;
+        f(&mut history);
+
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, toml::to_string(&history)?).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+
+    /// Adds a run to the history, replacing the run in the same direction with
+    /// the same start time if there is one.
+    ///
+    /// The corresponding schedule is cleared once the run has ended, and the
+    /// oldest runs are discarded if the history is full.
+    pub fn push(&mut self, run: Run) {
+        if run.end.is_some() {
+            match run.direction {
+                Direction::Outgoing => self.scheduled_outgoing = None,
+                Direction::Incoming => self.scheduled_incoming = None,
+            }
+        }
+        match self
+            .runs
+            .iter_mut()
+            .rfind(|r| r.direction == run.direction && r.start == run.start)
+        {
+            Some(existing) => *existing = run,
+            None => self.runs.push(run),
+        }
+        if self.runs.len() > Self::MAX_RUNS {
+            let excess = self.runs.len() - Self::MAX_RUNS;
+            self.runs.drain(..excess);
+        }
+    }
+
+    /// Returns the most recent successful run in the given direction.
+    pub fn last_success(&self, direction: Direction) -> Option<&Run> {
+        self.runs
+            .iter()
+            .rev()
+            .find(|run| run.direction == direction && run.end.is_some() && run.error.is_none())
+    }
+}
+
+impl Persistent for History {
+    fn default_path() -> &'static Path {
+        &HISTORY_PATH
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// Files were backed up to the peer.
+    Outgoing,
+    /// Files were received from the peer.
+    Incoming,
+}
+
+/// A single backup, in either direction.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    pub direction: Direction,
+    #[serde(with = "memorage_core::time::serde::rfc3339")]
+    pub start: OffsetDateTime,
+    /// When the run ended, or [`None`] if it is still in progress or was
+    /// interrupted.
+    #[serde(default, with = "memorage_core::time::serde::rfc3339::option")]
+    pub end: Option<OffsetDateTime>,
+    /// The number of files written.
+    pub files: u64,
+    /// The number of encrypted bytes transferred.
+    pub bytes: u64,
+    /// The error that ended the run, if it failed.
+    pub error: Option<String>,
+    pub peer: PublicKey,
+}
+
+impl Run {
+    /// Creates a run that hasn't ended.
+    pub fn in_progress(direction: Direction, start: OffsetDateTime, peer: PublicKey) -> Self {
+        Self {
+            direction,
+            start,
+            end: None,
+            files: 0,
+            bytes: 0,
+            error: None,
+            peer,
+        }
+    }
+
+    /// Creates a run that ended now.
+    pub fn new(
+        direction: Direction,
+        start: OffsetDateTime,
+        peer: PublicKey,
+        result: &Result<TransferStats>,
+    ) -> Self {
+        let (stats, error) = match result {
+            Ok(stats) => (*stats, None),
+            Err(e) => (TransferStats::default(), Some(e.to_string())),
+        };
+        Self {
+            direction,
+            start,
+            end: Some(OffsetDateTime::now_utc()),
+            files: stats.files,
+            bytes: stats.bytes,
+            error,
+            peer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memorage_core::KeyPair;
+
+    #[tokio::test]
+    async fn serialize_history() {
+        let peer = KeyPair::from_entropy().public;
+        let mut history = History {
+            scheduled_outgoing: Some(OffsetDateTime::UNIX_EPOCH),
+            ..Default::default()
+        };
+        let start = OffsetDateTime::UNIX_EPOCH;
+        history.push(Run::in_progress(Direction::Outgoing, start, peer));
+        assert!(history.scheduled_outgoing.is_some());
+        assert!(history.last_success(Direction::Outgoing).is_none());
+        history.push(Run::new(
+            Direction::Outgoing,
+            start,
+            peer,
+            &Ok(TransferStats { files: 2, bytes: 3 }),
+        ));
+        assert_eq!(history.runs.len(), 1);
+        history.push(Run::new(
+            Direction::Outgoing,
+            start + memorage_core::time::Duration::SECOND,
+            peer,
+            &Err(Error::PeerNoResponse),
+        ));
+        assert_eq!(history.scheduled_outgoing, None);
+        assert_eq!(history.last_success(Direction::Outgoing).unwrap().files, 2);
+        assert!(history.last_success(Direction::Incoming).is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.toml");
+        assert_eq!(
+            History::from_disk_or_default(Some(&path)).await.unwrap(),
+            History::default()
+        );
+        history.to_disk(Some(&path)).await.unwrap();
+        assert_eq!(
+            History::from_disk_or_default(Some(&path)).await.unwrap(),
+            history
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates() {
+        let peer = KeyPair::from_entropy().public;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.toml");
+
+        let updates = (0..20).map(|i| {
+            let path = path.clone();
+            tokio::spawn(async move {
+                let start = OffsetDateTime::UNIX_EPOCH + memorage_core::time::Duration::seconds(i);
+                let run = Run::in_progress(Direction::Outgoing, start, peer);
+                History::update(Some(path), |h| h.push(run)).await.unwrap();
+            })
+        });
+        for update in updates.collect::<Vec<_>>() {
+            update.await.unwrap();
+        }
+
+        let history = History::from_disk_or_default(Some(&path)).await.unwrap();
+        assert_eq!(history.runs.len(), 20);
+    }
+}
@@ -11,6 +11,9 @@ lazy_static::lazy_static! {
     pub static ref DATA_PATH: std::path::PathBuf = {
         PROJECT_DIRS.data_dir().to_owned().join("data.toml")
     };
+    pub static ref HISTORY_PATH: std::path::PathBuf = {
+        PROJECT_DIRS.data_dir().to_owned().join("history.toml")
+    };
 }
 
 #[async_trait::async_trait]
@@ -53,6 +56,7 @@ pub trait Persistent: serde::Serialize + serde::de::DeserializeOwned {
 
 pub mod config;
 pub mod data;
+pub mod history;
 
 #[cfg(test)]
 mod tests {
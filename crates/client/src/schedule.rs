@@ -0,0 +1,405 @@
+//! Cron-like schedules.
+//!
+//! A schedule consists of five space-separated fields: minute, hour, day of
+//! month, month, and day of week. Each field is a comma-separated list of
+//! `*`, a value, or a range `a-b`, optionally followed by a step `/n`. Months
+//! and days of the week may be given by their three-letter English names, and
+//! Sunday may be written as either 0 or 7. As in cron, if both the day of
+//! month and the day of week are restricted, a day matches if either field
+//! matches.
+//!
+//! The aliases `@hourly`, `@daily`, `@midnight`, `@weekly`, `@monthly`,
+//! `@yearly` and `@annually` are also supported.
+//!
+//! ```
+//! # use memorage_client::schedule::Schedule;
+//! // Nightly at 02:00.
+//! let nightly: Schedule = "0 2 * * *".parse().unwrap();
+//! // Weekdays at 13:00 and 23:00.
+//! let weekdays: Schedule = "0 13,23 * * mon-fri".parse().unwrap();
+//! ```
+
+use std::str::FromStr;
+
+use memorage_core::time::{
+    Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The furthest into the future [`Schedule::next_after`] will search.
+///
+/// Eight years is enough to find February 29 even across a century that isn't
+/// a leap year.
+const SEARCH_LIMIT: Duration = Duration::days(8 * 366);
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schedule {
+    source: String,
+    minutes: Field,
+    hours: Field,
+    days_of_month: Field,
+    months: Field,
+    days_of_week: Field,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Field {
+    /// Bit `n` is set if the value `n` matches.
+    bits: u64,
+    /// Whether the field was `*`, possibly with a step.
+    star: bool,
+}
+
+impl Field {
+    fn contains(&self, value: u8) -> bool {
+        self.bits & (1 << value) != 0
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum ScheduleError {
+    #[error("expected 5 fields, found {0}")]
+    FieldCount(usize),
+    #[error("unknown schedule alias {0:?}")]
+    UnknownAlias(String),
+    #[error("invalid value {0:?}")]
+    InvalidValue(String),
+    #[error("{value} is outside the range {min}-{max}")]
+    OutOfRange { value: u8, min: u8, max: u8 },
+    #[error("range {0}-{1} is backwards")]
+    BackwardsRange(u8, u8),
+    #[error("step must be greater than zero")]
+    ZeroStep,
+}
+
+impl FromStr for Schedule {
+    type Err = ScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let expanded = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ if s.starts_with('@') => return Err(ScheduleError::UnknownAlias(s.to_owned())),
+            _ => s,
+        };
+
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(ScheduleError::FieldCount(fields.len()));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7, &WEEKDAYS)?;
+        // Sunday can be written as both 0 and 7.
+        if days_of_week.contains(7) {
+            days_of_week.bits |= 1;
+        }
+
+        Ok(Self {
+            source: s.to_owned(),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days_of_month: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTHS)?,
+            days_of_week,
+        })
+    }
+}
+
+/// Parses a field. `names[i]` is an alias for the value `min + i`.
+fn parse_field(field: &str, min: u8, max: u8, names: &[&str]) -> Result<Field, ScheduleError> {
+    let mut bits = 0;
+    let mut star = false;
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(parse_number(step)?)),
+            None => (item, None),
+        };
+        let step = match step {
+            Some(0) => return Err(ScheduleError::ZeroStep),
+            Some(step) => step,
+            None => 1,
+        };
+
+        let (start, end) = if range == "*" {
+            star = true;
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            let start = parse_value(start, min, max, names)?;
+            let end = parse_value(end, min, max, names)?;
+            if start > end {
+                return Err(ScheduleError::BackwardsRange(start, end));
+            }
+            (start, end)
+        } else {
+            let start = parse_value(range, min, max, names)?;
+            // As in cron, `a/n` is shorthand for `a-max/n`.
+            (start, if step == 1 { start } else { max })
+        };
+
+        for value in (start..=end).step_by(step.into()) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(Field { bits, star })
+}
+
+fn parse_value(s: &str, min: u8, max: u8, names: &[&str]) -> Result<u8, ScheduleError> {
+    let lowercase = s.to_ascii_lowercase();
+    // The unwrap is safe as names has at most 12 elements.
+    let value = match names.iter().position(|name| *name == lowercase) {
+        Some(i) => min + u8::try_from(i).unwrap(),
+        None => parse_number(s)?,
+    };
+
+    if value < min || value > max {
+        Err(ScheduleError::OutOfRange { value, min, max })
+    } else {
+        Ok(value)
+    }
+}
+
+fn parse_number(s: &str) -> Result<u8, ScheduleError> {
+    s.parse()
+        .map_err(|_| ScheduleError::InvalidValue(s.to_owned()))
+}
+
+impl Schedule {
+    /// Returns the first time strictly after `time` that matches the
+    /// schedule, when the schedule is interpreted in the given offset.
+    ///
+    /// Returns [`None`] if the schedule never matches, e.g. `0 0 31 2 *`.
+    pub fn next_after(&self, time: OffsetDateTime, offset: UtcOffset) -> Option<OffsetDateTime> {
+        let time = time.to_offset(offset);
+        let limit = time + SEARCH_LIMIT;
+
+        let mut next =
+            PrimitiveDateTime::new(time.date(), start_of_minute(time.hour(), time.minute()))
+                .assume_offset(offset)
+                + Duration::MINUTE;
+
+        while next <= limit {
+            if !self.months.contains(next.month().into()) {
+                next = start_of_next_month(next.date())?.assume_offset(offset);
+            } else if !self.day_matches(next.date()) {
+                next = PrimitiveDateTime::new(next.date().next_day()?, Time::MIDNIGHT)
+                    .assume_offset(offset);
+            } else if !self.hours.contains(next.hour()) {
+                next = PrimitiveDateTime::new(next.date(), start_of_minute(next.hour(), 0))
+                    .assume_offset(offset)
+                    + Duration::HOUR;
+            } else if !self.minutes.contains(next.minute()) {
+                next += Duration::MINUTE;
+            } else {
+                return Some(next);
+            }
+        }
+
+        None
+    }
+
+    fn day_matches(&self, date: Date) -> bool {
+        let day_of_month = self.days_of_month.contains(date.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(date.weekday().number_days_from_sunday());
+
+        if self.days_of_month.star || self.days_of_week.star {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        }
+    }
+}
+
+fn start_of_minute(hour: u8, minute: u8) -> Time {
+    Time::MIDNIGHT + Duration::minutes(60 * i64::from(hour) + i64::from(minute))
+}
+
+fn start_of_next_month(date: Date) -> Option<PrimitiveDateTime> {
+    let (year, month) = match date.month() {
+        Month::December => (date.year() + 1, Month::January),
+        month => (date.year(), month.next()),
+    };
+    Some(PrimitiveDateTime::new(
+        Date::from_calendar_date(year, month, 1).ok()?,
+        Time::MIDNIGHT,
+    ))
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a UTC time of the form `2022-07-01 02:00`, optionally with
+    /// seconds.
+    fn at(s: &str) -> OffsetDateTime {
+        let n = s
+            .split(['-', ' ', ':'])
+            .map(|n| n.parse().unwrap())
+            .collect::<Vec<u16>>();
+        let date =
+            Date::from_calendar_date(n[0].into(), (n[1] as u8).try_into().unwrap(), n[2] as u8)
+                .unwrap();
+        let time =
+            Time::from_hms(n[3] as u8, n[4] as u8, n.get(5).map_or(0, |s| *s as u8)).unwrap();
+        PrimitiveDateTime::new(date, time).assume_utc()
+    }
+
+    fn next(schedule: &str, time: OffsetDateTime) -> Option<OffsetDateTime> {
+        schedule
+            .parse::<Schedule>()
+            .unwrap()
+            .next_after(time, UtcOffset::UTC)
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "* * * *".parse::<Schedule>(),
+            Err(ScheduleError::FieldCount(4))
+        );
+        assert_eq!(
+            "60 * * * *".parse::<Schedule>(),
+            Err(ScheduleError::OutOfRange {
+                value: 60,
+                min: 0,
+                max: 59
+            })
+        );
+        assert_eq!(
+            "* * 0 * *".parse::<Schedule>(),
+            Err(ScheduleError::OutOfRange {
+                value: 0,
+                min: 1,
+                max: 31
+            })
+        );
+        assert_eq!(
+            "*/0 * * * *".parse::<Schedule>(),
+            Err(ScheduleError::ZeroStep)
+        );
+        assert_eq!(
+            "5-1 * * * *".parse::<Schedule>(),
+            Err(ScheduleError::BackwardsRange(5, 1))
+        );
+        assert_eq!(
+            "x * * * *".parse::<Schedule>(),
+            Err(ScheduleError::InvalidValue("x".to_owned()))
+        );
+        assert_eq!(
+            "@fortnightly".parse::<Schedule>(),
+            Err(ScheduleError::UnknownAlias("@fortnightly".to_owned()))
+        );
+    }
+
+    #[test]
+    fn nightly() {
+        assert_eq!(
+            next("0 2 * * *", at("2022-07-01 01:59:59")),
+            Some(at("2022-07-01 02:00"))
+        );
+        assert_eq!(
+            next("0 2 * * *", at("2022-07-01 02:00")),
+            Some(at("2022-07-02 02:00"))
+        );
+        assert_eq!(
+            next("@daily", at("2022-12-31 12:00")),
+            Some(at("2023-01-01 00:00"))
+        );
+    }
+
+    #[test]
+    fn weekdays() {
+        let schedule = "0 13,23 * * mon-fri";
+        // 2022-07-01 is a Friday.
+        assert_eq!(
+            next(schedule, at("2022-07-01 13:00")),
+            Some(at("2022-07-01 23:00"))
+        );
+        assert_eq!(
+            next(schedule, at("2022-07-01 23:00")),
+            Some(at("2022-07-04 13:00"))
+        );
+        assert_eq!(
+            next("0 0 * * 7", at("2022-07-01 00:00")),
+            Some(at("2022-07-03 00:00"))
+        );
+    }
+
+    #[test]
+    fn steps_and_names() {
+        assert_eq!(
+            next("*/15 * * * *", at("2022-07-01 10:16")),
+            Some(at("2022-07-01 10:30"))
+        );
+        assert_eq!(
+            next("30 9 1 jan,Jul *", at("2022-01-01 10:00")),
+            Some(at("2022-07-01 09:30"))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", at("2022-03-01 00:00")),
+            Some(at("2024-02-29 00:00"))
+        );
+        assert_eq!(next("0 0 31 2 *", at("2022-01-01 00:00")), None);
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // The 15th or any Monday.
+        let schedule = "0 0 15 * mon";
+        assert_eq!(
+            next(schedule, at("2022-07-01 00:00")),
+            Some(at("2022-07-04 00:00"))
+        );
+        assert_eq!(
+            next(schedule, at("2022-07-11 00:00")),
+            Some(at("2022-07-15 00:00"))
+        );
+    }
+
+    #[test]
+    fn offset() {
+        let schedule = "0 2 * * *".parse::<Schedule>().unwrap();
+        let offset = UtcOffset::from_hms(10, 0, 0).unwrap();
+        assert_eq!(
+            schedule.next_after(at("2022-07-01 00:00"), offset),
+            Some(at("2022-07-01 16:00"))
+        );
+    }
+}
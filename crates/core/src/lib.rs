@@ -13,8 +13,11 @@
     rustdoc::broken_intra_doc_links
 )]
 
+mod nat;
+
 use serde::{Deserialize, Serialize};
 
+pub use nat::{Behaviour, NatType};
 pub use parking_lot::Mutex;
 pub use rand;
 pub use time;
@@ -94,6 +97,29 @@ impl std::fmt::Display for PublicKey {
     }
 }
 
+/// Parses a public key from hexadecimal, ignoring whitespace so that the
+/// output of [`Display`](std::fmt::Display) can be parsed.
+impl std::str::FromStr for PublicKey {
+    type Err = KeyGenerationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_digit(16).ok_or(KeyGenerationError))
+            .collect::<Result<Vec<_>, _>>()?;
+        if digits.len() % 2 != 0 {
+            return Err(KeyGenerationError);
+        }
+
+        let bytes = digits
+            .chunks_exact(2)
+            .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+            .collect::<Vec<_>>();
+        Self::try_from(&bytes[..])
+    }
+}
+
 impl AsRef<[u8]> for PublicKey {
     fn as_ref(&self) -> &[u8] {
         self.0.as_ref()
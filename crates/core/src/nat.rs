@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+/// How a NAT treats traffic, classified as in [RFC 4787].
+///
+/// [RFC 4787]: https://datatracker.ietf.org/doc/html/rfc4787
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Behaviour {
+    /// The behaviour doesn't depend on the remote address.
+    EndpointIndependent,
+    /// The behaviour depends on the remote IP address, but not the port.
+    AddressDependent,
+    /// The behaviour depends on both the remote IP address and port.
+    AddressAndPortDependent,
+}
+
+/// The behaviour of the NAT in front of a socket.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NatType {
+    /// Whether the NAT reuses the public address of a socket for different
+    /// remote addresses.
+    pub mapping: Behaviour,
+    /// Which remote addresses the NAT lets through to a socket, having sent
+    /// to one remote address.
+    pub filtering: Behaviour,
+}
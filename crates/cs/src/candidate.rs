@@ -0,0 +1,98 @@
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// How a candidate address was obtained, as in ICE ([RFC 8445]).
+///
+/// [RFC 8445]: https://datatracker.ietf.org/doc/html/rfc8445
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CandidateKind {
+    /// The address of a local network interface, which peers on the same
+    /// network can reach without going through a NAT.
+    Host,
+    /// The address that a server saw the client's traffic come from, which is
+    /// usually the public address of the client's NAT.
+    ServerReflexive,
+    /// An address on a relay that forwards traffic to the client.
+    ///
+    /// Relayed candidates aren't exchanged, as both peers share a relay
+    /// session on the coordination server, which is only allocated once the
+    /// other candidates have failed.
+    Relayed,
+}
+
+impl CandidateKind {
+    /// The type preference recommended by RFC 8445.
+    fn preference(self) -> u32 {
+        match self {
+            Self::Host => 126,
+            Self::ServerReflexive => 100,
+            Self::Relayed => 0,
+        }
+    }
+}
+
+/// An address at which a peer might be reachable.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Candidate {
+    pub address: SocketAddr,
+    pub kind: CandidateKind,
+    /// Candidates with higher priorities are checked first.
+    pub priority: u32,
+}
+
+impl Candidate {
+    /// Creates a candidate with the priority recommended by RFC 8445, which
+    /// prefers IPv6 addresses to IPv4 addresses of the same kind.
+    pub fn new(address: SocketAddr, kind: CandidateKind) -> Self {
+        let local_preference = if address.is_ipv6() { 65_535 } else { 32_767 };
+        // Peers only exchange one stream, so every candidate is for the first
+        // component.
+        let component = 1;
+        Self {
+            address,
+            kind,
+            priority: (kind.preference() << 24) + (local_preference << 8) + (256 - component),
+        }
+    }
+
+    /// Returns the priority of a pair of candidates, so that both peers agree
+    /// on the order in which pairs are checked.
+    ///
+    /// The controlling candidate belongs to the peer that initiated the
+    /// connection.
+    pub fn pair_priority(controlling: &Self, controlled: &Self) -> u64 {
+        let (g, d) = (
+            u64::from(controlling.priority),
+            u64::from(controlled.priority),
+        );
+        (1 << 32) * g.min(d) + 2 * g.max(d) + u64::from(g > d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority() {
+        let host = Candidate::new("192.168.0.2:1".parse().unwrap(), CandidateKind::Host);
+        let ipv4 = Candidate::new("1.2.3.4:1".parse().unwrap(), CandidateKind::ServerReflexive);
+        let ipv6 = Candidate::new(
+            "[2600::1]:1".parse().unwrap(),
+            CandidateKind::ServerReflexive,
+        );
+        let relayed = Candidate::new("5.6.7.8:1".parse().unwrap(), CandidateKind::Relayed);
+
+        assert_eq!(host.priority, 2_122_317_823);
+        assert!(host.priority > ipv6.priority);
+        assert!(ipv6.priority > ipv4.priority);
+        assert!(ipv4.priority > relayed.priority);
+
+        assert!(Candidate::pair_priority(&host, &host) > Candidate::pair_priority(&host, &ipv4));
+        assert_ne!(
+            Candidate::pair_priority(&host, &ipv4),
+            Candidate::pair_priority(&ipv4, &host)
+        );
+    }
+}
@@ -12,4 +12,10 @@ pub enum Error {
     InvalidSignature,
     #[error("server has no data on requested key")]
     NoData,
+    #[error("too many requests")]
+    RateLimited,
+    #[error("relay unavailable")]
+    RelayUnavailable,
+    #[error("requested time too far in the future")]
+    InvalidTime,
 }
@@ -13,12 +13,14 @@
     rustdoc::broken_intra_doc_links
 )]
 
+mod candidate;
 mod code;
 mod error;
 pub mod serde;
 mod time;
 
 pub use crate::serde::{deserialize, serialize, Deserialize, Serialize};
+pub use candidate::{Candidate, CandidateKind};
 pub use code::PairingCode;
 pub use error::{Error, Result};
 
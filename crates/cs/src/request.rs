@@ -1,6 +1,6 @@
-use crate::PairingCode;
+use crate::{Candidate, PairingCode};
 
-use memorage_core::{time::OffsetDateTime, PublicKey};
+use memorage_core::{time::OffsetDateTime, NatType, PublicKey};
 use serde::{Deserialize, Serialize};
 
 pub trait Request: crate::private::Sealed {
@@ -19,9 +19,15 @@ pub enum RequestType {
     GetRegisterResponse(GetRegisterResponse),
     /// Request to connect to a given [`PublicKey`].
     RequestConnection(RequestConnection),
-    /// Request any socket addresses that have requested a connection.
+    /// Request any pending connection requests.
     CheckConnection(CheckConnection),
+    /// Report the client's address to a given [`PublicKey`], and request its
+    /// address.
     Ping(Ping),
+    /// Accept the connection request from a given [`PublicKey`].
+    AcceptConnection(AcceptConnection),
+    /// Request a relay for the session with a given [`PublicKey`].
+    Relay(Relay),
 }
 
 impl crate::private::Sealed for crate::request::RequestType {}
@@ -46,8 +52,21 @@ pub struct RequestConnection {
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CheckConnection;
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ping {
+    pub target: PublicKey,
+    /// The behaviour of the client's NAT, if it could be discovered.
+    pub nat: Option<NatType>,
+    /// The candidates the client gathered, in addition to the address the
+    /// server sees.
+    pub candidates: Vec<Candidate>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcceptConnection(pub PublicKey);
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Ping(pub PublicKey);
+pub struct Relay(pub PublicKey);
 
 macro_rules! impl_request {
     // IDK why this works with ident but not ty
@@ -73,4 +92,6 @@ impl_request![
     RequestConnection,
     CheckConnection,
     Ping,
+    AcceptConnection,
+    Relay,
 ];
@@ -1,6 +1,7 @@
-use std::net::SocketAddr;
+/// The length of a relay token in bytes.
+pub const RELAY_TOKEN_LENGTH: usize = 16;
 
-use crate::PairingCode;
+use crate::{Candidate, PairingCode};
 
 use memorage_core::{time::OffsetDateTime, PublicKey};
 use serde::{Deserialize, Serialize};
@@ -22,16 +23,51 @@ pub struct GetRegisterResponse(pub PublicKey);
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RequestConnection;
 
+/// The pending connection requests, in the order they were made.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckConnection(pub Vec<ConnectionRequest>);
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub struct CheckConnection {
+pub struct ConnectionRequest {
     pub initiator: PublicKey,
     #[serde(serialize_with = "crate::time::serialize_offset_date_time")]
     #[serde(deserialize_with = "crate::time::deserialize_offset_date_time")]
     pub time: OffsetDateTime,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ping {
+    /// The candidates of the peer, starting with the address the server saw.
+    pub candidates: Vec<Candidate>,
+    /// How the peers should connect, given the behaviour of their NATs.
+    pub strategy: Strategy,
+}
+
+/// How a pair of peers should connect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Strategy {
+    /// Punch holes to each other's addresses.
+    Punch,
+    /// Punch holes to a range of ports following the peer's port, as its NAT
+    /// allocates a new port for each destination.
+    PredictPorts,
+    /// Connect through a relay on the coordination server.
+    Relay,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub struct Ping(pub SocketAddr);
+pub struct AcceptConnection;
+
+/// A relay session on the coordination server.
+///
+/// The client sends the token to the port from the socket used for the peer
+/// connection, after which datagrams sent to the port are forwarded to the
+/// peer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relay {
+    pub port: u16,
+    pub token: [u8; RELAY_TOKEN_LENGTH],
+}
 
 macro_rules! impl_response {
     ($($t:ident),*$(,)?) => {
@@ -50,4 +86,6 @@ impl_response![
     RequestConnection,
     CheckConnection,
     Ping,
+    AcceptConnection,
+    Relay,
 ];
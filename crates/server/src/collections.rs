@@ -1,91 +1,104 @@
-use std::hash::Hash;
+use std::{collections::BTreeMap, hash::Hash};
 
 use hashbrown::HashMap;
 
-struct RingBuffer<K, const N: usize> {
-    cursor: usize,
-    // TODO: Use maybeunint rather than option?
-    buffer: [Option<K>; N],
-}
-
-impl<K, const N: usize> RingBuffer<K, N> {
-    // https://github.com/rust-lang/rust/issues/44796
-    const INIT: Option<K> = None;
-
-    fn new() -> Self {
-        Self {
-            cursor: 0,
-            buffer: [Self::INIT; N],
-        }
-    }
-
-    fn push(&mut self, value: K) -> Option<K> {
-        let result = unsafe { self.buffer.get_unchecked_mut(self.cursor).replace(value) };
-        if self.cursor + 1 == N {
-            self.cursor = 0;
-        } else {
-            self.cursor += 1;
-        }
-        result
-    }
-}
-
-pub(crate) struct MaxSizeHashMap<K, V, const N: usize>
+/// A hash map that holds at most a fixed number of entries, evicting the
+/// least recently inserted entry to make room for a new key once it is full.
+pub(crate) struct MaxSizeHashMap<K, V>
 where
     K: Hash + Eq + Clone,
 {
-    hash_map: HashMap<K, V>,
-    ring_buffer: RingBuffer<K, N>,
-    key_overwrites: HashMap<K, usize>,
+    capacity: usize,
+    /// The entries, along with the order in which they were inserted.
+    hash_map: HashMap<K, (u64, V)>,
+    /// The keys of the entries, ordered by when they were inserted.
+    order: BTreeMap<u64, K>,
+    next: u64,
 }
 
-impl<K, V, const N: usize> MaxSizeHashMap<K, V, N>
+impl<K, V> MaxSizeHashMap<K, V>
 where
     K: Hash + Eq + Clone + std::fmt::Debug,
 {
-    pub(crate) fn new() -> Self {
+    /// Creates a map that holds at most `capacity` entries. A capacity of zero
+    /// is treated as one.
+    pub(crate) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
         Self {
-            hash_map: HashMap::with_capacity(N),
-            ring_buffer: RingBuffer::new(),
-            key_overwrites: HashMap::with_capacity(N),
+            capacity,
+            hash_map: HashMap::with_capacity(capacity),
+            order: BTreeMap::new(),
+            next: 0,
         }
     }
 
+    #[cfg(test)]
     pub(crate) fn insert(&mut self, key: K, value: V) -> Option<V> {
-        // TODO: Do we want this
-        // if self.hash_map.get(&key) == Some(&value) {
-        //     return None;
-        // }
-        // TODO: Remove clone?
-        if let Some(oldest_key) = self.ring_buffer.push(key.clone()) {
-            if let Some(x) = self.key_overwrites.get_mut(&oldest_key) {
-                *x -= 1;
-                if *x == 0 {
-                    self.key_overwrites.remove(&oldest_key);
-                    // This may fail if user called Self::remove earlier but we don't
-                    // really care.
-                    self.hash_map.remove(&oldest_key);
-                }
-            } else {
-                debug_assert!(false, "oldest key not in key overwrites");
+        self.insert_evicting(key, value).0
+    }
+
+    /// Inserts a key-value pair into the map, additionally returning the entry
+    /// that was evicted to make room for it.
+    ///
+    /// An entry is only evicted if the map is full and doesn't already contain
+    /// the key.
+    pub(crate) fn insert_evicting(&mut self, key: K, value: V) -> (Option<V>, Option<(K, V)>) {
+        let mut evicted = None;
+        if let Some((order, _)) = self.hash_map.get(&key) {
+            self.order.remove(order);
+        } else if self.hash_map.len() >= self.capacity {
+            if let Some((_, oldest_key)) = self.order.pop_first() {
+                evicted = self
+                    .hash_map
+                    .remove(&oldest_key)
+                    .map(|(_, value)| (oldest_key, value));
             }
         }
-        if let Some(x) = self.key_overwrites.get_mut(&key) {
-            *x += 1;
-        } else {
-            // TODO: Remove clone?
-            self.key_overwrites.insert(key.clone(), 1);
-        }
 
-        self.hash_map.insert(key, value)
+        let order = self.next;
+        self.next += 1;
+        // TODO: Remove clone?
+        self.order.insert(order, key.clone());
+        let previous = self.hash_map.insert(key, (order, value));
+        (previous.map(|(_, value)| value), evicted)
     }
 
+    pub(crate) fn len(&self) -> usize {
+        self.hash_map.len()
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<&V> {
+        self.hash_map.get(key).map(|(_, value)| value)
+    }
+
+    #[cfg(test)]
     pub(crate) fn contains_key(&self, key: &K) -> bool {
         self.hash_map.contains_key(key)
     }
 
     pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
-        self.hash_map.remove(key)
+        let (order, value) = self.hash_map.remove(key)?;
+        self.order.remove(&order);
+        Some(value)
+    }
+
+    /// Removes the entries for which `f` returns true, returning their keys.
+    pub(crate) fn remove_where<F>(&mut self, mut f: F) -> Vec<K>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let removed: Vec<_> = self
+            .hash_map
+            .drain_filter(|key, (_, value)| f(key, value))
+            .map(|(key, (order, _))| (key, order))
+            .collect();
+        removed
+            .into_iter()
+            .map(|(key, order)| {
+                self.order.remove(&order);
+                key
+            })
+            .collect()
     }
 }
 
@@ -93,67 +106,28 @@ where
 mod tests {
     use super::*;
 
-    #[test]
-    fn ring_buffer() {
-        let mut ring_buffer = RingBuffer::<_, 3>::new();
-        assert_eq!(ring_buffer.cursor, 0);
-        assert_eq!(ring_buffer.buffer, [None, None, None]);
-
-        assert_eq!(ring_buffer.push(1), None);
-        assert_eq!(ring_buffer.cursor, 1);
-        assert_eq!(ring_buffer.buffer, [Some(1), None, None]);
-
-        assert_eq!(ring_buffer.push(2), None);
-        assert_eq!(ring_buffer.cursor, 2);
-        assert_eq!(ring_buffer.buffer, [Some(1), Some(2), None]);
-
-        assert_eq!(ring_buffer.push(3), None);
-        assert_eq!(ring_buffer.cursor, 0);
-        assert_eq!(ring_buffer.buffer, [Some(1), Some(2), Some(3)]);
-
-        assert_eq!(ring_buffer.push(4), Some(1));
-        assert_eq!(ring_buffer.cursor, 1);
-        assert_eq!(ring_buffer.buffer, [Some(4), Some(2), Some(3)]);
-
-        assert_eq!(ring_buffer.push(5), Some(2));
-        assert_eq!(ring_buffer.cursor, 2);
-        assert_eq!(ring_buffer.buffer, [Some(4), Some(5), Some(3)]);
-
-        assert_eq!(ring_buffer.push(6), Some(3));
-        assert_eq!(ring_buffer.cursor, 0);
-        assert_eq!(ring_buffer.buffer, [Some(4), Some(5), Some(6)]);
-
-        assert_eq!(ring_buffer.push(7), Some(4));
-        assert_eq!(ring_buffer.cursor, 1);
-        assert_eq!(ring_buffer.buffer, [Some(7), Some(5), Some(6)]);
+    macro_rules! assert_map_contents {
+        ($i:ident;$($k:expr => $v:expr),*$(,)?) => {
+            let expected = [$(($k, $v)),*];
+            assert_eq!($i.len(), expected.len());
+            for (key, value) in expected {
+                assert!($i.contains_key(&key));
+                assert_eq!($i.get(&key), Some(&value));
+            }
+        }
     }
 
     #[test]
     fn max_size_hash_map() {
-        macro_rules! assert_map_contents {
-            ($i:ident;$($k:expr => $v:expr),*$(,)?) => {
-                $(
-                    assert!($i.contains_key(&$k));
-                    assert_eq!($i.hash_map.get(&$k), Some(&$v));
-                )*
-            }
-        }
-
-        let mut hash_map = MaxSizeHashMap::<_, _, 3>::new();
-        assert_eq!(hash_map.ring_buffer.cursor, 0);
-        assert_eq!(hash_map.ring_buffer.buffer, [None, None, None]);
+        let mut hash_map = MaxSizeHashMap::new(3);
 
         assert_eq!(hash_map.insert(1, "When"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 1);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(1), None, None]);
         assert_map_contents! {
             hash_map;
             1 => "When",
         }
 
         assert_eq!(hash_map.insert(2, "I"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 2);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(1), Some(2), None]);
         assert_map_contents! {
             hash_map;
             1 => "When",
@@ -161,8 +135,6 @@ mod tests {
         }
 
         assert_eq!(hash_map.insert(3, "get"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 0);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(1), Some(2), Some(3)]);
         assert_map_contents! {
             hash_map;
             1 => "When",
@@ -171,8 +143,6 @@ mod tests {
         }
 
         assert_eq!(hash_map.insert(4, "signed,"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 1);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(4), Some(2), Some(3)]);
         assert_map_contents! {
             hash_map;
             2 => "I",
@@ -181,8 +151,6 @@ mod tests {
         }
 
         assert_eq!(hash_map.remove(&4), Some("signed,"));
-        assert_eq!(hash_map.ring_buffer.cursor, 1);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(4), Some(2), Some(3)]);
         assert_map_contents! {
             hash_map;
             2 => "I",
@@ -190,38 +158,33 @@ mod tests {
         }
         assert!(!hash_map.contains_key(&4));
 
+        // The removed entry made room, so nothing is evicted.
         assert_eq!(hash_map.insert(5, "homie,"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 2);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(4), Some(5), Some(3)]);
         assert_map_contents! {
             hash_map;
+            2 => "I",
             3 => "get",
             5 => "homie,",
         }
 
-        // TODO: Keys are sometimes removed when they don't need to be. This happens
-        // after a full rotation of the buffer so it probs isn't important.
+        // Overwriting a key doesn't evict anything, but makes it the newest.
         assert_eq!(hash_map.insert(5, "I'ma"), Some("homie,"));
-        assert_eq!(hash_map.ring_buffer.cursor, 0);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(4), Some(5), Some(5)]);
         assert_map_contents! {
             hash_map;
+            2 => "I",
+            3 => "get",
             5 => "I'ma",
         }
-        assert!(!hash_map.contains_key(&3));
 
         assert_eq!(hash_map.insert(6, "act"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 1);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(6), Some(5), Some(5)]);
         assert_map_contents! {
             hash_map;
+            3 => "get",
             5 => "I'ma",
             6 => "act",
         }
 
         assert_eq!(hash_map.insert(7, "a"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 2);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(6), Some(7), Some(5)]);
         assert_map_contents! {
             hash_map;
             5 => "I'ma",
@@ -230,8 +193,6 @@ mod tests {
         }
 
         assert_eq!(hash_map.insert(8, "fool"), None);
-        assert_eq!(hash_map.ring_buffer.cursor, 0);
-        assert_eq!(hash_map.ring_buffer.buffer, [Some(6), Some(7), Some(8)]);
         assert_map_contents! {
             hash_map;
             6 => "act",
@@ -239,4 +200,30 @@ mod tests {
             8 => "fool",
         }
     }
+
+    #[test]
+    fn remove_where_frees_space() {
+        let mut hash_map = MaxSizeHashMap::new(4);
+        for key in 0..4 {
+            hash_map.insert(key, key % 2 == 0);
+        }
+
+        let mut removed = hash_map.remove_where(|_, &expired| expired);
+        removed.sort_unstable();
+        assert_eq!(removed, [0, 2]);
+
+        // Inserting into the swept space doesn't evict the live entries.
+        for key in 4..6 {
+            assert_eq!(hash_map.insert_evicting(key, false), (None, None));
+        }
+        assert_map_contents! {
+            hash_map;
+            1 => false,
+            3 => false,
+            4 => false,
+            5 => false,
+        }
+
+        assert_eq!(hash_map.insert_evicting(6, false), (None, Some((1, false))));
+    }
 }
@@ -0,0 +1,204 @@
+use crate::{setup::Limits, Result};
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The configuration of the server binary.
+///
+/// Every field is optional in the config file, falling back to its default.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The addresses to listen on.
+    ///
+    /// IPv6 addresses only accept IPv6 connections, so a dual-stack server
+    /// should listen on both `0.0.0.0` and `::`, as it does by default. If
+    /// `::` can't be bound, such as on hosts without IPv6, it is skipped.
+    pub bind: Vec<IpAddr>,
+    pub port: u16,
+    /// The public address of the server, which is included in its
+    /// certificate.
+    ///
+    /// If not set, it is determined using `stun_server`, one of which must be
+    /// set.
+    pub public_address: Option<IpAddr>,
+    /// The STUN server to query for the public address if it isn't set.
+    pub stun_server: Option<String>,
+    /// Whether to answer STUN Binding requests, so that clients can use the
+    /// server for address discovery.
+    ///
+    /// Enabled by default. If `stun_port` can't be bound on a bind address,
+    /// such as when another STUN server is running, a warning is logged and
+    /// requests aren't answered on that address.
+    pub stun: bool,
+    /// The port on which STUN requests are answered, on every bind address.
+    pub stun_port: u16,
+    /// A second IP address of the server, which lets clients discover the
+    /// behaviour of their NAT (RFC 5780).
+    ///
+    /// If set, STUN requests are answered on the public address and this
+    /// address, each on `stun_port` and `stun_port + 1`, rather than on the
+    /// bind addresses. Both addresses must be assigned to the server itself,
+    /// not to a NAT in front of it.
+    pub stun_alternate_address: Option<IpAddr>,
+    /// The path of the server's key pair, which is generated if it doesn't
+    /// exist.
+    ///
+    /// If not set, a new key pair is generated on every start, so clients
+    /// can't pin the server's public key.
+    pub key: Option<PathBuf>,
+    /// The path of the on-disk store. If not set, state is kept in memory.
+    pub store: Option<PathBuf>,
+    /// The address on which to serve metrics in the Prometheus text format.
+    ///
+    /// If not set, metrics aren't served.
+    pub metrics: Option<SocketAddr>,
+    /// The default log level, which is overridden by `RUST_LOG`.
+    pub log_level: String,
+    pub limits: Limits,
+}
+
+impl Config {
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ],
+            port: memorage_core::PORT,
+            public_address: None,
+            stun_server: None,
+            stun: true,
+            stun_port: memorage_stun::DEFAULT_STUN_PORT,
+            stun_alternate_address: None,
+            key: None,
+            store: None,
+            metrics: None,
+            log_level: "info".to_owned(),
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Serializes durations as seconds, accepting both integers and floats.
+pub(crate) mod duration {
+    use std::time::Duration;
+
+    use serde::{de, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct DurationVisitor;
+
+        impl de::Visitor<'_> for DurationVisitor {
+            type Value = Duration;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("a non-negative number of seconds")
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(Duration::from_secs(v))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                u64::try_from(v)
+                    .map(Duration::from_secs)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Duration::try_from_secs_f64(v)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Float(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn serialize_config() {
+        let config = Config {
+            public_address: Some(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))),
+            key: Some(PathBuf::from("/var/lib/memorage-server/key")),
+            store: Some(PathBuf::from("/var/lib/memorage-server/store")),
+            ..Default::default()
+        };
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&serialized).unwrap(), config);
+    }
+
+    #[test]
+    fn partial_config() {
+        let config: Config = toml::from_str(
+            r#"
+            bind = ["0.0.0.0", "::"]
+            public_address = "1.2.3.4"
+
+            [limits]
+            code_ttl = 60
+            request_grace = 0.5
+
+            [limits.key_limit]
+            burst = 5
+            interval = 2
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.bind,
+            ["0.0.0.0".parse::<IpAddr>().unwrap(), "::".parse().unwrap()]
+        );
+        assert_eq!(config.port, memorage_core::PORT);
+        assert_eq!(config.public_address, Some("1.2.3.4".parse().unwrap()));
+        assert!(config.stun);
+        assert_eq!(config.stun_port, memorage_stun::DEFAULT_STUN_PORT);
+        assert_eq!(config.limits.code_ttl, Duration::from_secs(60));
+        assert_eq!(config.limits.request_grace, Duration::from_millis(500));
+        assert_eq!(config.limits.key_limit.burst, 5);
+        assert_eq!(
+            config.limits.address_map_size,
+            Limits::default().address_map_size
+        );
+    }
+}
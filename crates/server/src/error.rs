@@ -10,6 +10,14 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("error generating server config")]
     ServerConfig(#[from] memorage_cert::Error),
+    #[error("error parsing config file")]
+    Config(#[from] toml::de::Error),
+    #[error("invalid key file")]
+    InvalidKey,
+    #[error("invalid limits: {0}")]
+    InvalidLimits(&'static str),
+    #[error("error accessing store")]
+    Store(#[from] sled::Error),
     #[error("error sending response")]
     Write(#[from] quinn::WriteError),
 }
@@ -0,0 +1,57 @@
+use crate::{Error, Result};
+
+use std::{io::Write, path::Path};
+
+use memorage_core::KeyPair;
+use tracing::info;
+
+/// Loads the server's key pair from the path, generating and saving a new key
+/// pair if the file doesn't exist.
+///
+/// The key pair is stored as PKCS #8.
+pub fn load_or_generate_key<P>(path: P) -> Result<KeyPair>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match std::fs::read(path) {
+        Ok(bytes) => KeyPair::try_from_pkcs8(&bytes).map_err(|_| Error::InvalidKey),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key_pair = KeyPair::from_entropy();
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)?.write_all(&key_pair.to_pkcs8())?;
+
+            info!(?path, "generated new key pair");
+            Ok(key_pair)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_generated_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server").join("key");
+
+        let generated = load_or_generate_key(&path).unwrap();
+        let loaded = load_or_generate_key(&path).unwrap();
+        assert_eq!(generated, loaded);
+
+        std::fs::write(&path, b"not a key").unwrap();
+        assert!(matches!(
+            load_or_generate_key(&path),
+            Err(Error::InvalidKey)
+        ));
+    }
+}
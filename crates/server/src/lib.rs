@@ -13,24 +13,35 @@
     rustdoc::broken_intra_doc_links
 )]
 
-const ADDRESS_MAP_SIZE: usize = 256;
-const CODE_MAP_SIZE: usize = 256;
-const REQUEST_MAP_SIZE: usize = 256;
-
 mod collections;
+pub mod config;
 mod error;
+mod key;
 mod manager;
+pub mod metrics;
 pub mod setup;
+mod store;
+mod stun;
 
-use std::{fmt::Write, net::SocketAddr};
+use std::{
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
 
 use futures_util::StreamExt;
 use memorage_core::PublicKey;
 use memorage_cs::{deserialize, request::RequestType, response, serialize};
-use tracing::{info, warn};
+use metrics::{Outcome, RequestKind};
+use tracing::{debug, info, warn};
 
+pub use config::Config;
 pub use error::{Error, Result};
-pub use setup::setup;
+pub use key::load_or_generate_key;
+pub use metrics::serve_metrics;
+pub use setup::{setup, setup_with, Limits, RateLimit};
+pub use store::Store;
+pub use stun::{serve_stun, serve_stun_with_alternate};
 
 pub async fn handle_connection(conn: quinn::Connecting, channels: setup::Channels) -> Result<()> {
     // remote_address must be called before awaiting the connection
@@ -116,6 +127,55 @@ fn format_key(key: &PublicKey) -> String {
     string
 }
 
+async fn acquire_token(
+    channels: &setup::Channels,
+    key: PublicKey,
+    address: IpAddr,
+    get_key: bool,
+) -> memorage_cs::Result<()> {
+    let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+    let cmd = manager::limit::Command::Acquire {
+        key,
+        address,
+        get_key,
+        resp: resp_tx,
+    };
+    channels
+        .limit
+        .send(cmd)
+        .await
+        .map_err(|_| memorage_cs::Error::Generic)?;
+    resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
+}
+
+/// Checks that the peers have a confirmed rendezvous.
+///
+/// Addresses and relays are only provided to such peers, so that a client
+/// can't learn a peer's address by pinging it.
+async fn authorise(
+    channels: &setup::Channels,
+    peer: PublicKey,
+    other_peer: PublicKey,
+) -> memorage_cs::Result<()> {
+    let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+    let cmd = manager::request::Command::Authorise {
+        peer,
+        other_peer,
+        resp: resp_tx,
+    };
+    channels
+        .request
+        .send(cmd)
+        .await
+        .map_err(|_| memorage_cs::Error::Generic)?;
+    if resp_rx.await.map_err(|_| memorage_cs::Error::Generic)? {
+        Ok(())
+    } else {
+        debug!("no confirmed rendezvous");
+        Err(memorage_cs::Error::NoData)
+    }
+}
+
 #[inline]
 #[tracing::instrument(skip_all, fields(addr = ?client_address, key = %format_key(&client_key)))]
 async fn handle_request(
@@ -125,11 +185,24 @@ async fn handle_request(
     channels: setup::Channels,
 ) -> Result<Vec<u8>> {
     info!("accepted connection");
+    let start = Instant::now();
+    let mut kind = RequestKind::Invalid;
+    let outcome;
 
     let request: memorage_cs::Result<RequestType> = match maybe_buf {
         Ok(buf) => deserialize(&buf).map_err(|_| memorage_cs::Error::Generic),
         Err(e) => Err(e),
     };
+    let request = match request {
+        Ok(ty) => {
+            kind = RequestKind::from(&ty);
+            let get_key = matches!(ty, RequestType::GetKey(_));
+            acquire_token(&channels, client_key, client_address.ip(), get_key)
+                .await
+                .map(|_| ty)
+        }
+        Err(e) => Err(e),
+    };
 
     // TODO: Verify that client_key matches address stored in hashmap.
     let resp = match request {
@@ -152,6 +225,7 @@ async fn handle_request(
                         resp_rx.await.map_err(|_| memorage_cs::Error::Generic)
                     }
                     .await;
+                    outcome = Outcome::of(&response);
                     serialize(response)
                 }
                 RequestType::GetKey(r) => {
@@ -172,6 +246,15 @@ async fn handle_request(
                         resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
                     }
                     .await;
+                    // Only invalid codes count towards the failure limit.
+                    if response != Err(memorage_cs::Error::NoData) {
+                        let cmd = manager::limit::Command::GetKeySuccess {
+                            key: client_key,
+                            address: client_address.ip(),
+                        };
+                        let _ = channels.limit.send(cmd).await;
+                    }
+                    outcome = Outcome::of(&response);
                     serialize(response)
                 }
                 RequestType::GetRegisterResponse(_) => {
@@ -192,6 +275,7 @@ async fn handle_request(
                             resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
                         }
                         .await;
+                    outcome = Outcome::of(&response);
                     serialize(response)
                 }
                 RequestType::RequestConnection(r) => {
@@ -211,9 +295,10 @@ async fn handle_request(
                                 .send(cmd)
                                 .await
                                 .map_err(|_| memorage_cs::Error::Generic)?;
-                            resp_rx.await.map_err(|_| memorage_cs::Error::Generic)
+                            resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
                         }
                         .await;
+                    outcome = Outcome::of(&response);
                     serialize(response)
                 }
                 RequestType::CheckConnection(_) => {
@@ -234,20 +319,46 @@ async fn handle_request(
                             resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
                         }
                         .await;
+                    outcome = Outcome::of(&response);
                     serialize(response)
                 }
-                // initiator address
-                RequestType::Ping(r) => {
-                    info!("received ping request");
+                RequestType::AcceptConnection(r) => {
+                    info!("received accept connection request");
                     let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
-                    let cmd = manager::establish::Command::Ping {
-                        initiator_key: client_key,
-                        initiator_address: client_address,
-                        target: r.0,
+                    let cmd = manager::request::Command::AcceptConnection {
+                        initiator: r.0,
+                        target: client_key,
                         resp: resp_tx,
                     };
 
+                    let response: memorage_cs::Result<memorage_cs::response::AcceptConnection> =
+                        async {
+                            channels
+                                .request
+                                .send(cmd)
+                                .await
+                                .map_err(|_| memorage_cs::Error::Generic)?;
+                            resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
+                        }
+                        .await;
+                    outcome = Outcome::of(&response);
+                    serialize(response)
+                }
+                // initiator address
+                RequestType::Ping(r) => {
+                    info!("received ping request");
                     let response: memorage_cs::Result<memorage_cs::response::Ping> = async {
+                        authorise(&channels, client_key, r.target).await?;
+
+                        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+                        let cmd = manager::establish::Command::Ping {
+                            initiator_key: client_key,
+                            initiator_address: client_address,
+                            initiator_candidates: r.candidates,
+                            initiator_nat: r.nat,
+                            target: r.target,
+                            resp: resp_tx,
+                        };
                         channels
                             .establish
                             .send(cmd)
@@ -256,14 +367,42 @@ async fn handle_request(
                         resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
                     }
                     .await;
+                    outcome = Outcome::of(&response);
+                    serialize(response)
+                }
+                RequestType::Relay(r) => {
+                    info!("received relay request");
+                    let response: memorage_cs::Result<memorage_cs::response::Relay> = async {
+                        authorise(&channels, client_key, r.0).await?;
+
+                        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+                        let cmd = manager::relay::Command::Allocate {
+                            peer: client_key,
+                            other_peer: r.0,
+                            resp: resp_tx,
+                        };
+                        channels
+                            .relay
+                            .send(cmd)
+                            .await
+                            .map_err(|_| memorage_cs::Error::Generic)?;
+                        resp_rx.await.map_err(|_| memorage_cs::Error::Generic)?
+                    }
+                    .await;
+                    outcome = Outcome::of(&response);
                     serialize(response)
                 }
             }
         }
         // TODO not sure if this is sound. alternatively we can just ignore the request.
-        Err(e) => serialize(memorage_cs::Result::<response::Register>::Err(e)),
+        Err(e) => {
+            outcome = Outcome::of(&Err::<(), _>(e));
+            serialize(memorage_cs::Result::<response::Register>::Err(e))
+        }
     }?;
 
+    channels.metrics.record(kind, outcome, start.elapsed());
+
     info!("closing connection");
     Ok(resp)
 }
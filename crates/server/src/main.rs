@@ -1,26 +1,235 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#![allow(deprecated)]
 
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use clap::Parser;
 use futures_util::StreamExt;
-use tracing::info;
+use memorage_server::Config;
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{info, warn};
+
+/// The memorage coordination server
+#[derive(Parser, Debug)]
+#[clap(version)]
+struct Args {
+    /// Use the specified configuration file
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// Listen on the specified address
+    ///
+    /// Can be given multiple times. Overrides the addresses in the
+    /// configuration file.
+    #[clap(short, long)]
+    bind: Vec<IpAddr>,
+    /// Listen on the specified port
+    #[clap(short, long)]
+    port: Option<u16>,
+    /// Use the specified public address
+    #[clap(long)]
+    public_address: Option<IpAddr>,
+    /// Query the specified STUN server for the public address if it isn't
+    /// configured
+    #[clap(long)]
+    stun_server: Option<String>,
+    /// Answer STUN requests on the specified port
+    #[clap(long)]
+    stun_port: Option<u16>,
+    /// Don't answer STUN requests
+    #[clap(long)]
+    no_stun: bool,
+    /// Also answer STUN requests on the specified address of the server, so
+    /// that clients can discover the behaviour of their NAT
+    #[clap(long)]
+    stun_alternate_address: Option<IpAddr>,
+    /// Use the key pair at the specified path, generating it if it doesn't
+    /// exist
+    #[clap(short, long)]
+    key: Option<PathBuf>,
+    /// Print the server's public key and exit
+    ///
+    /// Clients can pin the key in their configuration file.
+    #[clap(long)]
+    print_public_key: bool,
+    /// Keep state in the specified on-disk store
+    #[clap(long)]
+    store: Option<PathBuf>,
+    /// Relay up to the specified number of concurrent sessions between peers
+    /// that can't connect directly
+    #[clap(long)]
+    relay_sessions: Option<usize>,
+    /// Serve metrics on the specified address
+    #[clap(long)]
+    metrics: Option<SocketAddr>,
+    /// Log at the specified level unless RUST_LOG is set
+    #[clap(long)]
+    log_level: Option<String>,
+}
+
+impl Args {
+    fn config(self) -> memorage_server::Result<Config> {
+        let mut config = match self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+
+        if !self.bind.is_empty() {
+            config.bind = self.bind;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(public_address) = self.public_address {
+            config.public_address = Some(public_address);
+        }
+        if let Some(stun_server) = self.stun_server {
+            config.stun_server = Some(stun_server);
+        }
+        if let Some(stun_port) = self.stun_port {
+            config.stun_port = stun_port;
+        }
+        if self.no_stun {
+            config.stun = false;
+        }
+        if let Some(stun_alternate_address) = self.stun_alternate_address {
+            config.stun_alternate_address = Some(stun_alternate_address);
+        }
+        if let Some(key) = self.key {
+            config.key = Some(key);
+        }
+        if let Some(store) = self.store {
+            config.store = Some(store);
+        }
+        if let Some(relay_sessions) = self.relay_sessions {
+            config.limits.relay_sessions = relay_sessions;
+        }
+        if let Some(metrics) = self.metrics {
+            config.metrics = Some(metrics);
+        }
+        if let Some(log_level) = self.log_level {
+            config.log_level = log_level;
+        }
+
+        Ok(config)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     human_panic::setup_panic!();
+    let args = Args::parse();
+    let print_public_key = args.print_public_key;
+    let config = args.config()?;
+
+    if print_public_key {
+        return match config.key {
+            Some(ref path) => {
+                println!("{}", memorage_server::load_or_generate_key(path)?.public);
+                Ok(())
+            }
+            None => Err("no key file configured".into()),
+        };
+    }
+
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", &config.log_level)
+    }
     memorage_server::setup_logger();
 
-    let (channels, handles) = memorage_server::setup();
+    let (channels, handles) = match config.store {
+        Some(ref path) => {
+            let store = memorage_server::Store::open(path)?;
+            info!(?path, "opened store");
+            memorage_server::setup_with(config.limits, Some(&store))?
+        }
+        None => memorage_server::setup_with(config.limits, None)?,
+    };
 
-    let mut socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
-    let public_address =
-        memorage_stun::public_address(&mut socket, memorage_stun::DEFAULT_STUN_SERVER).await?;
-    info!(%public_address, "received public address");
+    if let Some(address) = config.metrics {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        info!(%address, "serving metrics");
+        tokio::spawn(memorage_server::serve_metrics(listener, channels.clone()));
+    }
+
+    let public_address = match (config.public_address, &config.stun_server) {
+        (Some(public_address), _) => public_address,
+        (None, Some(stun_server)) => {
+            let mut socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+            let public_address = memorage_stun::public_address(&mut socket, stun_server).await?;
+            info!(%public_address, "received public address");
+            public_address.ip()
+        }
+        (None, None) => return Err("no public address or STUN server configured".into()),
+    };
+
+    if config.stun {
+        match config.stun_alternate_address {
+            Some(alternate_address) => {
+                let alternate_port = config
+                    .stun_port
+                    .checked_add(1)
+                    .ok_or("the STUN port leaves no room for an alternate port")?;
+                let bind_ports = |ip| -> std::io::Result<_> {
+                    Ok([
+                        bind_tokio(SocketAddr::new(ip, config.stun_port))?,
+                        bind_tokio(SocketAddr::new(ip, alternate_port))?,
+                    ])
+                };
+                let sockets = [bind_ports(public_address)?, bind_ports(alternate_address)?];
+                info!(%public_address, %alternate_address, "answering STUN requests");
+                tokio::spawn(memorage_server::serve_stun_with_alternate(sockets));
+            }
+            None => {
+                for &ip in &config.bind {
+                    let address = SocketAddr::new(ip, config.stun_port);
+                    // STUN is answered by default, so it mustn't prevent the
+                    // server from starting.
+                    let socket = match bind_tokio(address) {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            warn!(%address, ?e, "failed to bind - not answering STUN requests");
+                            continue;
+                        }
+                    };
+                    info!(%address, "answering STUN requests");
+                    tokio::spawn(memorage_server::serve_stun(socket));
+                }
+            }
+        }
+    }
 
-    let key_pair = memorage_core::KeyPair::from_entropy();
-    let server_config = memorage_cert::gen_recv_config(public_address.ip(), &key_pair, None)?;
+    let key_pair = match config.key {
+        Some(ref path) => memorage_server::load_or_generate_key(path)?,
+        None => {
+            warn!("no key file configured - generating an ephemeral key pair");
+            memorage_core::KeyPair::from_entropy()
+        }
+    };
+    info!(public_key = %key_pair.public, "loaded key pair");
+    let server_config = memorage_cert::gen_recv_config(public_address, &key_pair, None)?;
 
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), memorage_core::PORT);
-    let (_endpoint, mut incoming) = quinn::Endpoint::server(server_config, addr)?;
+    let mut endpoints = Vec::with_capacity(config.bind.len());
+    let mut incomings = Vec::with_capacity(config.bind.len());
+    for ip in config.bind {
+        let address = SocketAddr::new(ip, config.port);
+        let socket = match bind(address) {
+            Ok(socket) => socket,
+            Err(e) if is_optional(address) => {
+                warn!(%address, ?e, "IPv6 unavailable - not listening");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (endpoint, incoming) =
+            quinn::Endpoint::new(Default::default(), Some(server_config.clone()), socket)?;
+        info!(%address, "listening");
+        endpoints.push(endpoint);
+        incomings.push(incoming);
+    }
 
+    let mut incoming = futures_util::stream::select_all(incomings);
     while let Some(conn) = incoming.next().await {
         tokio::spawn(memorage_server::handle_connection(conn, channels.clone()));
     }
@@ -28,3 +237,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     handles.join().await?;
     Ok(())
 }
+
+/// Binds a UDP socket, which only accepts IPv6 traffic if the address is IPv6
+/// so that IPv4 and IPv6 unspecified addresses can be bound simultaneously.
+fn bind(address: SocketAddr) -> std::io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&address.into())?;
+    Ok(socket.into())
+}
+
+/// Whether failing to bind the address can be ignored, as the IPv6 unspecified
+/// address can't be bound on hosts without IPv6.
+fn is_optional(address: SocketAddr) -> bool {
+    address.ip() == IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+}
+
+/// Binds a UDP socket as in [`bind`], for use with tokio.
+fn bind_tokio(address: SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
+    let socket = bind(address)?;
+    socket.set_nonblocking(true)?;
+    tokio::net::UdpSocket::from_std(socket)
+}
@@ -1,39 +1,100 @@
-use std::net::SocketAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
-use crate::{collections::MaxSizeHashMap, ADDRESS_MAP_SIZE};
-
-use memorage_core::PublicKey;
-use memorage_cs::{response::Ping, Error, Result};
+use crate::{
+    metrics::{Map, Metrics},
+    setup::Limits,
+    store::{Expiring, PersistentMap, Table},
+};
 
+use memorage_core::{time::OffsetDateTime, Behaviour, NatType, PublicKey};
+use memorage_cs::{
+    response::{Ping, Strategy},
+    Candidate, CandidateKind, Error, Result,
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot};
-use tracing::info_span;
+use tracing::{info, info_span};
 
 #[derive(Debug)]
 pub enum Command {
     Ping {
         initiator_key: PublicKey,
         initiator_address: SocketAddr,
+        initiator_candidates: Vec<Candidate>,
+        initiator_nat: Option<NatType>,
         target: PublicKey,
         resp: oneshot::Sender<Result<Ping>>,
     },
 }
 
+/// The maximum number of candidates a peer can publish in addition to the
+/// address it pinged from.
+const MAX_PUBLISHED_CANDIDATES: usize = 8;
+
+/// The candidates and NAT behaviour reported by a pinging peer.
+///
+/// They are stored under the pinging peer's and the target's keys, so that
+/// they are only revealed to the peer they were published for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Pinged {
+    candidates: Vec<Candidate>,
+    nat: Option<NatType>,
+}
+
 #[tracing::instrument]
-pub async fn manager(mut rx: mpsc::Receiver<Command>) {
-    let mut addresses = MaxSizeHashMap::<PublicKey, SocketAddr, ADDRESS_MAP_SIZE>::new();
-    while let Some(cmd) = rx.recv().await {
+pub async fn manager(
+    mut rx: mpsc::Receiver<Command>,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    addresses: Option<Table<(PublicKey, PublicKey), Expiring<Pinged>>>,
+) {
+    let mut addresses = PersistentMap::new(limits.address_map_size, addresses);
+    let mut sweep = super::sweep_interval(&limits);
+
+    loop {
+        metrics.set_map_entries(Map::Addresses, addresses.len());
+        let cmd = tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            _ = sweep.tick() => {
+                let now = OffsetDateTime::now_utc();
+                addresses.sweep(now);
+                continue;
+            }
+        };
+
         let span = info_span!("received command", ?cmd).entered();
         match cmd {
             Command::Ping {
                 initiator_key,
                 initiator_address,
+                initiator_candidates,
+                initiator_nat,
                 target,
                 resp,
             } => {
-                addresses.insert(initiator_key, initiator_address);
-                let _ = resp.send(match addresses.remove(&target) {
-                    // TODO: Only reveal socket address to valid initiators?
-                    Some(a) => Ok(Ping(a)),
+                addresses.insert(
+                    (initiator_key, target),
+                    Pinged {
+                        candidates: peer_candidates(initiator_address, initiator_candidates),
+                        nat: initiator_nat,
+                    },
+                    OffsetDateTime::now_utc() + limits.address_ttl,
+                );
+                let _ = resp.send(match addresses.remove(&(target, initiator_key)) {
+                    Some(target) => {
+                        let strategy = plan(initiator_nat, target.nat);
+                        info!(?initiator_nat, target_nat = ?target.nat, ?strategy, "planned connection");
+                        Ok(Ping {
+                            candidates: target.candidates,
+                            strategy,
+                        })
+                    }
                     None => Err(Error::NoData),
                 });
             }
@@ -41,3 +102,161 @@ pub async fn manager(mut rx: mpsc::Receiver<Command>) {
         drop(span);
     }
 }
+
+/// Returns the candidates that the peer might be reached at, starting with
+/// the address it pinged from.
+///
+/// Published candidates that no peer could reach are dropped, so that a peer
+/// can't direct connectivity checks at arbitrary hosts. Private addresses are
+/// only accepted as host candidates, which lets peers on the same network
+/// connect directly, at the cost of letting a peer direct the other peer's
+/// checks at hosts on its local network, as in ICE. Priorities are recomputed,
+/// so that every peer's candidates are ordered the same way.
+fn peer_candidates(address: SocketAddr, published: Vec<Candidate>) -> Vec<Candidate> {
+    let reachable = |candidate: &Candidate| {
+        let local = candidate.kind == CandidateKind::Host;
+        candidate.address.port() != 0
+            && match candidate.address.ip() {
+                IpAddr::V4(ip) => {
+                    !(ip.is_unspecified()
+                        || ip.is_loopback()
+                        || (ip.is_private() && !local)
+                        || ip.is_link_local()
+                        || ip.is_multicast()
+                        || ip.is_broadcast()
+                        || ip.is_documentation())
+                }
+                IpAddr::V6(ip) => {
+                    // Only global unicast addresses, excluding those reserved
+                    // for documentation, and unique local addresses.
+                    let segments = ip.segments();
+                    let global = segments[0] & 0xe000 == 0x2000 && segments[..2] != [0x2001, 0xdb8];
+                    let unique_local = segments[0] & 0xfe00 == 0xfc00;
+                    global || (unique_local && local)
+                }
+            }
+    };
+
+    let mut candidates = vec![Candidate::new(address, CandidateKind::ServerReflexive)];
+    let published = published
+        .into_iter()
+        // Relayed candidates are only allocated by the server.
+        .filter(|candidate| candidate.kind != CandidateKind::Relayed)
+        .filter(reachable)
+        .take(MAX_PUBLISHED_CANDIDATES);
+    for published in published {
+        let published = Candidate::new(published.address, published.kind);
+        match candidates
+            .iter_mut()
+            .find(|candidate| candidate.address == published.address)
+        {
+            Some(candidate) if candidate.priority < published.priority => *candidate = published,
+            Some(_) => {}
+            None => candidates.push(published),
+        }
+    }
+    candidates
+}
+
+/// Chooses how a pair of peers should connect, given the behaviour of their
+/// NATs.
+///
+/// Hole punching works unless one of the NATs allocates a new port for each
+/// destination, in which case the other peer must guess that port if its NAT
+/// only lets through the exact address it sent to. If neither NAT keeps its
+/// port, no guessing is reliable, so the session is relayed.
+fn plan(nat: Option<NatType>, other_nat: Option<NatType>) -> Strategy {
+    let (nat, other_nat) = match (nat, other_nat) {
+        (Some(nat), Some(other_nat)) => (nat, other_nat),
+        // Without both behaviours, punching is attempted before falling back
+        // to the relay.
+        _ => return Strategy::Punch,
+    };
+    let symmetric = |nat: NatType| nat.mapping == Behaviour::AddressAndPortDependent;
+
+    match (symmetric(nat), symmetric(other_nat)) {
+        (true, true) => Strategy::Relay,
+        (true, false) if other_nat.filtering == Behaviour::AddressAndPortDependent => {
+            Strategy::PredictPorts
+        }
+        (false, true) if nat.filtering == Behaviour::AddressAndPortDependent => {
+            Strategy::PredictPorts
+        }
+        _ => Strategy::Punch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONE: NatType = NatType {
+        mapping: Behaviour::EndpointIndependent,
+        filtering: Behaviour::EndpointIndependent,
+    };
+    const PORT_RESTRICTED: NatType = NatType {
+        mapping: Behaviour::EndpointIndependent,
+        filtering: Behaviour::AddressAndPortDependent,
+    };
+    const SYMMETRIC: NatType = NatType {
+        mapping: Behaviour::AddressAndPortDependent,
+        filtering: Behaviour::AddressAndPortDependent,
+    };
+
+    #[test]
+    fn published_candidates() {
+        let address = "1.2.3.4:1".parse().unwrap();
+        let published = [
+            ("1.2.3.4:1", CandidateKind::ServerReflexive),
+            ("192.168.0.2:1", CandidateKind::Host),
+            ("192.168.0.3:1", CandidateKind::ServerReflexive),
+            ("[2001:db8::1]:1", CandidateKind::Host),
+            ("[2600::1]:1", CandidateKind::ServerReflexive),
+            ("[fd00::1]:1", CandidateKind::Host),
+            ("[fd00::2]:1", CandidateKind::ServerReflexive),
+            ("[fe80::1]:1", CandidateKind::Host),
+            ("5.6.7.8:0", CandidateKind::Host),
+            ("5.6.7.8:2", CandidateKind::Relayed),
+        ]
+        .map(|(address, kind)| Candidate {
+            address: address.parse().unwrap(),
+            kind,
+            priority: u32::MAX,
+        });
+        let expected = [
+            ("1.2.3.4:1", CandidateKind::ServerReflexive),
+            ("192.168.0.2:1", CandidateKind::Host),
+            ("[2600::1]:1", CandidateKind::ServerReflexive),
+            ("[fd00::1]:1", CandidateKind::Host),
+        ]
+        .map(|(address, kind)| Candidate::new(address.parse().unwrap(), kind));
+        assert_eq!(peer_candidates(address, published.to_vec()), expected);
+
+        // A host candidate at the address the server saw replaces it.
+        let host = Candidate::new(address, CandidateKind::Host);
+        assert_eq!(peer_candidates(address, vec![host]), [host]);
+
+        let many = (1..=20)
+            .map(|i| Candidate::new(SocketAddr::new(address.ip(), i + 1), CandidateKind::Host));
+        assert_eq!(
+            peer_candidates(address, many.collect()).len(),
+            1 + MAX_PUBLISHED_CANDIDATES
+        );
+    }
+
+    #[test]
+    fn plan_strategy() {
+        assert_eq!(plan(None, Some(SYMMETRIC)), Strategy::Punch);
+        assert_eq!(plan(Some(CONE), Some(PORT_RESTRICTED)), Strategy::Punch);
+        assert_eq!(plan(Some(SYMMETRIC), Some(CONE)), Strategy::Punch);
+        assert_eq!(
+            plan(Some(PORT_RESTRICTED), Some(SYMMETRIC)),
+            Strategy::PredictPorts
+        );
+        assert_eq!(
+            plan(Some(SYMMETRIC), Some(PORT_RESTRICTED)),
+            Strategy::PredictPorts
+        );
+        assert_eq!(plan(Some(SYMMETRIC), Some(SYMMETRIC)), Strategy::Relay);
+    }
+}
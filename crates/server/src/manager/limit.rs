@@ -0,0 +1,256 @@
+use std::{
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr},
+    time::Instant,
+};
+
+use crate::setup::{Limits, RateLimit};
+
+use hashbrown::HashMap;
+use memorage_core::PublicKey;
+use memorage_cs::{Error, Result};
+
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info_span, warn};
+
+#[derive(Debug)]
+pub enum Command {
+    /// Takes a token from the key's and address's buckets.
+    Acquire {
+        key: PublicKey,
+        address: IpAddr,
+        /// Whether the request is a get key request, which additionally takes
+        /// a token from the key's and address's failure buckets.
+        ///
+        /// The failure tokens are taken up front, so that concurrent requests
+        /// can't try more codes than the failure limit allows.
+        get_key: bool,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    /// Returns the failure tokens taken by a get key request whose pairing
+    /// code was valid.
+    GetKeySuccess { key: PublicKey, address: IpAddr },
+}
+
+/// A token bucket, which holds up to [`RateLimit::burst`] tokens and regains
+/// one token every [`RateLimit::interval`].
+#[derive(Copy, Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last_update: now,
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        let regained = if limit.interval.is_zero() {
+            f64::INFINITY
+        } else {
+            elapsed.as_secs_f64() / limit.interval.as_secs_f64()
+        };
+        self.tokens = (self.tokens + regained).min(limit.burst as f64);
+        self.last_update = now;
+    }
+
+    fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    fn is_full(&self, limit: RateLimit) -> bool {
+        self.tokens >= limit.burst as f64
+    }
+}
+
+/// Token buckets for a particular limit.
+#[derive(Debug)]
+struct Buckets<K> {
+    limit: RateLimit,
+    buckets: HashMap<K, Bucket>,
+}
+
+impl<K> Buckets<K>
+where
+    K: Hash + Eq,
+{
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the key's bucket has a token, without creating a bucket
+    /// for keys that don't have one yet.
+    fn has_token(&mut self, key: &K, now: Instant) -> bool {
+        match self.buckets.get_mut(key) {
+            Some(bucket) => {
+                bucket.refill(self.limit, now);
+                bucket.has_token()
+            }
+            None => Bucket::full(self.limit, now).has_token(),
+        }
+    }
+
+    /// Takes a token from the key's bucket.
+    fn take(&mut self, key: K, now: Instant) {
+        let bucket = self.get(key, now);
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+    }
+
+    /// Returns a token to the key's bucket.
+    ///
+    /// Keys without a bucket already have a full bucket.
+    fn refund(&mut self, key: &K, now: Instant) {
+        let limit = self.limit;
+        if let Some(bucket) = self.buckets.get_mut(key) {
+            bucket.refill(limit, now);
+            bucket.tokens = (bucket.tokens + 1.0).min(limit.burst as f64);
+        }
+    }
+
+    /// Returns the refilled bucket for the key.
+    fn get(&mut self, key: K, now: Instant) -> &mut Bucket {
+        let limit = self.limit;
+        let bucket = self
+            .buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(limit, now));
+        bucket.refill(limit, now);
+        bucket
+    }
+
+    /// Removes the buckets that have been idle for long enough to refill, as
+    /// they are equivalent to a new bucket.
+    fn sweep(&mut self, now: Instant) {
+        let limit = self.limit;
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(limit, now);
+            !bucket.is_full(limit)
+        });
+    }
+}
+
+/// Returns the address's bucket key.
+///
+/// IPv6 clients are usually assigned an entire /64, so they are limited by
+/// prefix rather than being able to rotate through its addresses.
+fn address_key(address: IpAddr) -> IpAddr {
+    match address.to_canonical() {
+        IpAddr::V4(address) => IpAddr::V4(address),
+        IpAddr::V6(address) => IpAddr::V6(Ipv6Addr::from(u128::from(address) & !(u128::MAX >> 64))),
+    }
+}
+
+#[tracing::instrument]
+pub async fn manager(mut rx: mpsc::Receiver<Command>, limits: Limits) {
+    let mut key_buckets = Buckets::<PublicKey>::new(limits.key_limit);
+    let mut address_buckets = Buckets::<IpAddr>::new(limits.address_limit);
+    let mut key_failure_buckets = Buckets::<PublicKey>::new(limits.get_key_failure_limit);
+    let mut address_failure_buckets = Buckets::<IpAddr>::new(limits.get_key_failure_limit);
+
+    let mut sweep = super::sweep_interval(&limits);
+
+    loop {
+        let cmd = tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            _ = sweep.tick() => {
+                let now = Instant::now();
+                key_buckets.sweep(now);
+                address_buckets.sweep(now);
+                key_failure_buckets.sweep(now);
+                address_failure_buckets.sweep(now);
+                continue;
+            }
+        };
+
+        let span = info_span!("received command", ?cmd).entered();
+        let now = Instant::now();
+        match cmd {
+            Command::Acquire {
+                key,
+                address,
+                get_key,
+                resp,
+            } => {
+                let address = address_key(address);
+                // Rejected requests don't create buckets, so they can't grow the
+                // maps between sweeps.
+                let has_key_token = key_buckets.has_token(&key, now);
+                let has_address_token = address_buckets.has_token(&address, now);
+
+                let has_failure_tokens = !get_key
+                    || (key_failure_buckets.has_token(&key, now)
+                        && address_failure_buckets.has_token(&address, now));
+
+                let result = if has_key_token && has_address_token && has_failure_tokens {
+                    key_buckets.take(key, now);
+                    address_buckets.take(address, now);
+                    if get_key {
+                        key_failure_buckets.take(key, now);
+                        address_failure_buckets.take(address, now);
+                    }
+                    Ok(())
+                } else {
+                    warn!("rate limited request");
+                    Err(Error::RateLimited)
+                };
+                let _ = resp.send(result);
+            }
+            Command::GetKeySuccess { key, address } => {
+                key_failure_buckets.refund(&key, now);
+                address_failure_buckets.refund(&address_key(address), now);
+            }
+        }
+        drop(span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_key_prefix() {
+        let a: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+        let b: IpAddr = "2001:db8:1:2:ffff::1".parse().unwrap();
+        let c: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+        assert_eq!(address_key(a), "2001:db8:1:2::".parse::<IpAddr>().unwrap());
+        assert_eq!(address_key(a), address_key(b));
+        assert_ne!(address_key(a), address_key(c));
+
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        let mapped: IpAddr = "::ffff:1.2.3.4".parse().unwrap();
+        assert_eq!(address_key(v4), v4);
+        assert_eq!(address_key(mapped), v4);
+    }
+
+    #[test]
+    fn rejected_keys_have_no_bucket() {
+        let limit = RateLimit {
+            burst: 1,
+            interval: std::time::Duration::from_secs(60),
+        };
+        let mut buckets = Buckets::<u8>::new(limit);
+        let now = Instant::now();
+
+        assert!(buckets.has_token(&0, now));
+        assert!(buckets.buckets.is_empty());
+
+        buckets.take(0, now);
+        assert!(!buckets.has_token(&0, now));
+        assert_eq!(buckets.buckets.len(), 1);
+
+        buckets.sweep(now + limit.interval);
+        assert!(buckets.buckets.is_empty());
+    }
+}
@@ -1,3 +1,15 @@
 pub(crate) mod establish;
+pub(crate) mod limit;
 pub(crate) mod pair;
+pub(crate) mod relay;
 pub(crate) mod request;
+
+use crate::setup::Limits;
+
+use tokio::time::{Interval, MissedTickBehavior};
+
+fn sweep_interval(limits: &Limits) -> Interval {
+    let mut interval = tokio::time::interval(limits.sweep_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
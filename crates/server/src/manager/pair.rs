@@ -1,6 +1,12 @@
-use crate::{collections::MaxSizeHashMap, CODE_MAP_SIZE};
+use crate::{
+    metrics::{Map, Metrics},
+    setup::Limits,
+    store::{Expiring, PersistentMap, Table},
+};
+
+use std::sync::Arc;
 
-use memorage_core::PublicKey;
+use memorage_core::{time::OffsetDateTime, PublicKey};
 use memorage_cs::{
     response::{GetKey, GetRegisterResponse, Register},
     Error, PairingCode, Result,
@@ -27,11 +33,34 @@ pub enum Command {
 }
 
 #[tracing::instrument]
-pub async fn manager(mut rx: mpsc::Receiver<Command>) {
-    let mut code_map = MaxSizeHashMap::<_, _, CODE_MAP_SIZE>::new();
-    let mut requestor_map = MaxSizeHashMap::<_, _, CODE_MAP_SIZE>::new();
+pub async fn manager(
+    mut rx: mpsc::Receiver<Command>,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    codes: Option<Table<PairingCode, Expiring<PublicKey>>>,
+    requestors: Option<Table<PublicKey, Expiring<PublicKey>>>,
+) {
+    let mut code_map = PersistentMap::new(limits.code_map_size, codes);
+    let mut requestor_map = PersistentMap::new(limits.code_map_size, requestors);
+
+    let mut sweep = super::sweep_interval(&limits);
+
+    loop {
+        metrics.set_map_entries(Map::Codes, code_map.len());
+        metrics.set_map_entries(Map::Requestors, requestor_map.len());
+        let cmd = tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            _ = sweep.tick() => {
+                let now = OffsetDateTime::now_utc();
+                code_map.sweep(now);
+                requestor_map.sweep(now);
+                continue;
+            }
+        };
 
-    while let Some(cmd) = rx.recv().await {
         let span = info_span!("received command", ?cmd).entered();
         match cmd {
             Command::Register { key, resp } => {
@@ -41,7 +70,11 @@ pub async fn manager(mut rx: mpsc::Receiver<Command>) {
                 while code_map.contains_key(&code) {
                     code = PairingCode::new()
                 }
-                code_map.insert(code.clone(), key);
+                code_map.insert(
+                    code.clone(),
+                    key,
+                    OffsetDateTime::now_utc() + limits.code_ttl,
+                );
 
                 info!(?code, "generated pairing code");
                 let _ = resp.send(Register(code));
@@ -53,7 +86,11 @@ pub async fn manager(mut rx: mpsc::Receiver<Command>) {
             } => {
                 let _ = resp.send(match code_map.remove(&code) {
                     Some(initiator) => {
-                        requestor_map.insert(initiator, requestor);
+                        requestor_map.insert(
+                            initiator,
+                            requestor,
+                            OffsetDateTime::now_utc() + limits.code_ttl,
+                        );
                         Ok(GetKey(initiator))
                     }
                     None => Err(Error::NoData),
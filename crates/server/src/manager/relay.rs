@@ -0,0 +1,218 @@
+use crate::{
+    manager::request::Rendezvous,
+    metrics::{Map, Metrics},
+    setup::Limits,
+};
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use hashbrown::HashMap;
+use memorage_core::{
+    rand::{thread_rng, RngCore},
+    PublicKey,
+};
+use memorage_cs::{
+    response::{Relay, RELAY_TOKEN_LENGTH},
+    Error, Result,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+
+/// The maximum size of a relayed datagram.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+type Token = [u8; RELAY_TOKEN_LENGTH];
+
+#[derive(Debug)]
+pub enum Command {
+    /// Allocates a relay session between the peers, or returns the existing
+    /// session.
+    Allocate {
+        peer: PublicKey,
+        other_peer: PublicKey,
+        resp: oneshot::Sender<Result<Relay>>,
+    },
+}
+
+/// A relay session, which runs until it has been idle for
+/// [`Limits::relay_idle_timeout`].
+#[derive(Debug)]
+struct Session {
+    port: u16,
+    tokens: [(PublicKey, Token); 2],
+    handle: JoinHandle<()>,
+}
+
+impl Session {
+    fn spawn(peer: PublicKey, other_peer: PublicKey, idle_timeout: Duration) -> io::Result<Self> {
+        let socket = bind()?;
+        let port = socket.local_addr()?.port();
+        let tokens = [peer, other_peer].map(|key| {
+            let mut token = [0; RELAY_TOKEN_LENGTH];
+            thread_rng().fill_bytes(&mut token);
+            (key, token)
+        });
+
+        let handle = tokio::spawn(
+            relay(socket, tokens.map(|(_, token)| token), idle_timeout)
+                .instrument(info_span!("relay session", port)),
+        );
+        info!(port, "allocated relay session");
+
+        Ok(Self {
+            port,
+            tokens,
+            handle,
+        })
+    }
+
+    fn relay(&self, peer: &PublicKey) -> Option<Relay> {
+        self.tokens
+            .iter()
+            .find(|(key, _)| key == peer)
+            .map(|(_, token)| Relay {
+                port: self.port,
+                token: *token,
+            })
+    }
+}
+
+#[tracing::instrument]
+pub async fn manager(mut rx: mpsc::Receiver<Command>, limits: Limits, metrics: Arc<Metrics>) {
+    let mut sessions: HashMap<Rendezvous, Session> = HashMap::new();
+
+    let mut sweep = super::sweep_interval(&limits);
+
+    loop {
+        metrics.set_map_entries(Map::Relays, sessions.len());
+        let cmd = tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            _ = sweep.tick() => {
+                sessions.retain(|_, session| !session.handle.is_finished());
+                continue;
+            }
+        };
+
+        let span = info_span!("received command", ?cmd).entered();
+        match cmd {
+            Command::Allocate {
+                peer,
+                other_peer,
+                resp,
+            } => {
+                sessions.retain(|_, session| !session.handle.is_finished());
+
+                let rendezvous = Rendezvous::new(peer, other_peer);
+                let result = if let Some(session) = sessions.get(&rendezvous) {
+                    session.relay(&peer).ok_or(Error::Generic)
+                } else if sessions.len() >= limits.relay_sessions {
+                    Err(Error::RelayUnavailable)
+                } else {
+                    match Session::spawn(peer, other_peer, limits.relay_idle_timeout) {
+                        Ok(session) => {
+                            let relay = session.relay(&peer).ok_or(Error::Generic);
+                            sessions.insert(rendezvous, session);
+                            relay
+                        }
+                        Err(e) => {
+                            warn!(?e, "error binding relay socket");
+                            Err(Error::Generic)
+                        }
+                    }
+                };
+                let _ = resp.send(result);
+            }
+        }
+        drop(span);
+    }
+
+    for session in sessions.into_values() {
+        session.handle.abort();
+    }
+}
+
+/// Binds a socket that accepts both IPv4 and IPv6 traffic, falling back to an
+/// IPv4 socket if IPv6 is unavailable.
+fn bind() -> io::Result<UdpSocket> {
+    let dual_stack = || -> io::Result<Socket> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+        Ok(socket)
+    };
+    let socket = match dual_stack() {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(?e, "error binding dual-stack relay socket");
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Forwards datagrams between the peers.
+///
+/// A peer's address is learnt from the source of its token. Datagrams from any
+/// other address are dropped, and the contents of relayed datagrams are never
+/// inspected.
+async fn relay(socket: UdpSocket, tokens: [Token; 2], idle_timeout: Duration) {
+    let mut peers: [Option<SocketAddr>; 2] = [None, None];
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut deadline = Instant::now() + idle_timeout;
+
+    loop {
+        let (len, source) = tokio::select! {
+            result = socket.recv_from(&mut buf) => match result {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!(?e, "error receiving datagram");
+                    continue;
+                }
+            },
+            _ = tokio::time::sleep_until(deadline) => break,
+        };
+        let datagram = &buf[..len];
+
+        if let Some(index) = tokens.iter().position(|token| datagram == token) {
+            if peers[index] != Some(source) {
+                debug!(%source, "bound peer address");
+                peers[index] = Some(source);
+            }
+            deadline = Instant::now() + idle_timeout;
+            continue;
+        }
+
+        let index = match peers.iter().position(|peer| *peer == Some(source)) {
+            Some(index) => index,
+            None => {
+                trace!(%source, "dropping datagram from unknown address");
+                continue;
+            }
+        };
+        if let Some(destination) = peers[1 - index] {
+            if let Err(e) = socket.send_to(datagram, destination).await {
+                debug!(?e, %destination, "error forwarding datagram");
+            }
+            deadline = Instant::now() + idle_timeout;
+        }
+    }
+
+    info!("relay session idle");
+}
@@ -1,11 +1,18 @@
-use crate::{collections::MaxSizeHashMap, REQUEST_MAP_SIZE};
+use crate::{
+    metrics::{Map, Metrics},
+    setup::Limits,
+    store::{Expiring, PersistentMap, Table},
+};
+
+use std::{sync::Arc, time::Duration};
 
 use memorage_core::{time::OffsetDateTime, PublicKey};
 use memorage_cs::{
-    response::{CheckConnection, RequestConnection},
+    response::{AcceptConnection, CheckConnection, ConnectionRequest, RequestConnection},
     Error, Result,
 };
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot};
 use tracing::info_span;
 
@@ -16,19 +23,73 @@ pub enum Command {
         initiator: PublicKey,
         target: PublicKey,
         time: OffsetDateTime,
-        resp: oneshot::Sender<RequestConnection>,
+        resp: oneshot::Sender<Result<RequestConnection>>,
     },
     CheckConnection {
         target: PublicKey,
         resp: oneshot::Sender<Result<CheckConnection>>,
     },
+    AcceptConnection {
+        initiator: PublicKey,
+        target: PublicKey,
+        resp: oneshot::Sender<Result<AcceptConnection>>,
+    },
+    /// Checks whether the peers have a confirmed rendezvous, in which case
+    /// their addresses can be revealed to each other.
+    Authorise {
+        peer: PublicKey,
+        other_peer: PublicKey,
+        resp: oneshot::Sender<bool>,
+    },
+}
+
+/// A pair of peers that have a confirmed rendezvous, i.e. one peer requested a
+/// connection and the other accepted the request.
+///
+/// The keys are ordered so that the pair is the same regardless of which peer
+/// initiated the connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct Rendezvous(PublicKey, PublicKey);
+
+impl Rendezvous {
+    pub(crate) fn new(a: PublicKey, b: PublicKey) -> Self {
+        if a.as_ref() <= b.as_ref() {
+            Self(a, b)
+        } else {
+            Self(b, a)
+        }
+    }
 }
 
 #[tracing::instrument]
-pub async fn manager(mut rx: mpsc::Receiver<Command>) {
-    let mut requests = MaxSizeHashMap::<PublicKey, CheckConnection, REQUEST_MAP_SIZE>::new();
+pub async fn manager(
+    mut rx: mpsc::Receiver<Command>,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+    requests: Option<Table<PublicKey, Expiring<Vec<ConnectionRequest>>>>,
+    rendezvous: Option<Table<Rendezvous, Expiring<()>>>,
+) {
+    let mut requests = PersistentMap::new(limits.request_map_size, requests);
+    let mut rendezvous = PersistentMap::new(limits.request_map_size, rendezvous);
+
+    let mut sweep = super::sweep_interval(&limits);
+
+    loop {
+        metrics.set_map_entries(Map::Requests, requests.len());
+        metrics.set_map_entries(Map::Rendezvous, rendezvous.len());
+        let cmd = tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            _ = sweep.tick() => {
+                let now = OffsetDateTime::now_utc();
+                requests.sweep(now);
+                rendezvous.sweep(now);
+                continue;
+            }
+        };
 
-    while let Some(cmd) = rx.recv().await {
         let span = info_span!("received command", ?cmd).entered();
         match cmd {
             Command::RequestConnection {
@@ -37,15 +98,82 @@ pub async fn manager(mut rx: mpsc::Receiver<Command>) {
                 time,
                 resp,
             } => {
-                // TODO multiple people want to connect to same target (hashmap value is vec)
-                requests.insert(target, CheckConnection { initiator, time });
-                // TODO do we even need resp
-                let _ = resp.send(RequestConnection);
+                // Otherwise the request, and the rendezvous if it is accepted,
+                // would never expire.
+                let result = if time > OffsetDateTime::now_utc() + limits.request_horizon {
+                    Err(Error::InvalidTime)
+                } else {
+                    let mut queue = pending(requests.remove(&target), limits.request_grace);
+                    // A newer request from the same initiator replaces the old
+                    // one.
+                    queue.retain(|request| request.initiator != initiator);
+                    let excess = (queue.len() + 1).saturating_sub(limits.request_queue_size.max(1));
+                    queue.drain(..excess);
+                    queue.push(ConnectionRequest { initiator, time });
+
+                    if let Some(expiry) = expiry(&queue, limits.request_grace) {
+                        requests.insert(target, queue, expiry);
+                    }
+                    Ok(RequestConnection)
+                };
+                let _ = resp.send(result);
             }
             Command::CheckConnection { target, resp } => {
-                let _ = resp.send(requests.remove(&target).ok_or(Error::NoData));
+                let queue = pending(requests.get(&target).cloned(), limits.request_grace);
+                let _ = resp.send(if queue.is_empty() {
+                    Err(Error::NoData)
+                } else {
+                    Ok(CheckConnection(queue))
+                });
+            }
+            Command::AcceptConnection {
+                initiator,
+                target,
+                resp,
+            } => {
+                let mut queue = pending(requests.remove(&target), limits.request_grace);
+                let accepted = queue
+                    .iter()
+                    .position(|request| request.initiator == initiator)
+                    .map(|index| queue.remove(index));
+                if let Some(expiry) = expiry(&queue, limits.request_grace) {
+                    requests.insert(target, queue, expiry);
+                }
+
+                let _ = resp.send(match accepted {
+                    Some(request) => {
+                        rendezvous.insert(
+                            Rendezvous::new(initiator, target),
+                            (),
+                            request.time + limits.request_grace,
+                        );
+                        Ok(AcceptConnection)
+                    }
+                    None => Err(Error::NoData),
+                });
+            }
+            Command::Authorise {
+                peer,
+                other_peer,
+                resp,
+            } => {
+                let _ = resp.send(rendezvous.contains_key(&Rendezvous::new(peer, other_peer)));
             }
         }
         drop(span);
     }
 }
+
+/// Returns the requests in the queue that are still within their grace period.
+fn pending(queue: Option<Vec<ConnectionRequest>>, grace: Duration) -> Vec<ConnectionRequest> {
+    let now = OffsetDateTime::now_utc();
+    let mut queue = queue.unwrap_or_default();
+    queue.retain(|request| request.time + grace > now);
+    queue
+}
+
+/// Returns when the last request in the queue expires, or [`None`] if the queue
+/// is empty.
+fn expiry(queue: &[ConnectionRequest], grace: Duration) -> Option<OffsetDateTime> {
+    queue.iter().map(|request| request.time + grace).max()
+}
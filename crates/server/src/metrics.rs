@@ -0,0 +1,307 @@
+use crate::{setup::Channels, Result};
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use memorage_cs::request::RequestType;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, warn};
+
+/// The upper bounds of the request latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.1, 0.5];
+
+/// The maximum size of a request to the metrics endpoint.
+const MAX_HTTP_REQUEST_SIZE: usize = 8192;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum RequestKind {
+    Register,
+    GetKey,
+    GetRegisterResponse,
+    RequestConnection,
+    CheckConnection,
+    Ping,
+    AcceptConnection,
+    Relay,
+    /// A request that couldn't be deserialized.
+    Invalid,
+}
+
+impl RequestKind {
+    const ALL: [Self; 9] = [
+        Self::Register,
+        Self::GetKey,
+        Self::GetRegisterResponse,
+        Self::RequestConnection,
+        Self::CheckConnection,
+        Self::Ping,
+        Self::AcceptConnection,
+        Self::Relay,
+        Self::Invalid,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Register => "register",
+            Self::GetKey => "get_key",
+            Self::GetRegisterResponse => "get_register_response",
+            Self::RequestConnection => "request_connection",
+            Self::CheckConnection => "check_connection",
+            Self::Ping => "ping",
+            Self::AcceptConnection => "accept_connection",
+            Self::Relay => "relay",
+            Self::Invalid => "invalid",
+        }
+    }
+}
+
+impl From<&RequestType> for RequestKind {
+    fn from(request: &RequestType) -> Self {
+        match request {
+            RequestType::Register(_) => Self::Register,
+            RequestType::GetKey(_) => Self::GetKey,
+            RequestType::GetRegisterResponse(_) => Self::GetRegisterResponse,
+            RequestType::RequestConnection(_) => Self::RequestConnection,
+            RequestType::CheckConnection(_) => Self::CheckConnection,
+            RequestType::Ping(_) => Self::Ping,
+            RequestType::AcceptConnection(_) => Self::AcceptConnection,
+            RequestType::Relay(_) => Self::Relay,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Ok,
+    NoData,
+    RateLimited,
+    Error,
+}
+
+impl Outcome {
+    const ALL: [Self; 4] = [Self::Ok, Self::NoData, Self::RateLimited, Self::Error];
+
+    pub(crate) fn of<T>(result: &memorage_cs::Result<T>) -> Self {
+        match result {
+            Ok(_) => Self::Ok,
+            Err(memorage_cs::Error::NoData) => Self::NoData,
+            Err(memorage_cs::Error::RateLimited) => Self::RateLimited,
+            Err(_) => Self::Error,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::NoData => "no_data",
+            Self::RateLimited => "rate_limited",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// The maps held by the managers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Map {
+    Codes,
+    Requestors,
+    Requests,
+    Rendezvous,
+    Addresses,
+    Relays,
+}
+
+impl Map {
+    const ALL: [Self; 6] = [
+        Self::Codes,
+        Self::Requestors,
+        Self::Requests,
+        Self::Rendezvous,
+        Self::Addresses,
+        Self::Relays,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Codes => "codes",
+            Self::Requestors => "requestors",
+            Self::Requests => "requests",
+            Self::Rendezvous => "rendezvous",
+            Self::Addresses => "addresses",
+            Self::Relays => "relays",
+        }
+    }
+}
+
+/// Counters and gauges describing the server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    responses: [[AtomicU64; Outcome::ALL.len()]; RequestKind::ALL.len()],
+    latency_buckets: [[AtomicU64; LATENCY_BUCKETS.len()]; RequestKind::ALL.len()],
+    latency_micros: [AtomicU64; RequestKind::ALL.len()],
+    map_entries: [AtomicUsize; Map::ALL.len()],
+    map_capacity: [AtomicUsize; Map::ALL.len()],
+}
+
+impl Metrics {
+    pub(crate) fn record(&self, kind: RequestKind, outcome: Outcome, latency: Duration) {
+        let kind = kind as usize;
+        self.responses[kind][outcome as usize].fetch_add(1, Ordering::Relaxed);
+
+        let seconds = latency.as_secs_f64();
+        for (bucket, bound) in self.latency_buckets[kind].iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_micros[kind].fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_map_entries(&self, map: Map, entries: usize) {
+        self.map_entries[map as usize].store(entries, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_map_capacity(&self, map: Map, capacity: usize) {
+        self.map_capacity[map as usize].store(capacity, Ordering::Relaxed);
+    }
+}
+
+/// Renders the metrics in the Prometheus text format.
+fn render(channels: &Channels) -> String {
+    let metrics = &channels.metrics;
+    let mut output = String::new();
+
+    output.push_str("# HELP memorage_requests_total Requests handled, by type and result.\n");
+    output.push_str("# TYPE memorage_requests_total counter\n");
+    for kind in RequestKind::ALL {
+        for outcome in Outcome::ALL {
+            let count = metrics.responses[kind as usize][outcome as usize].load(Ordering::Relaxed);
+            let _ = writeln!(
+                output,
+                "memorage_requests_total{{type=\"{}\",result=\"{}\"}} {count}",
+                kind.label(),
+                outcome.label()
+            );
+        }
+    }
+
+    output.push_str("# HELP memorage_request_duration_seconds Time taken to handle requests.\n");
+    output.push_str("# TYPE memorage_request_duration_seconds histogram\n");
+    for kind in RequestKind::ALL {
+        let kind_index = kind as usize;
+        let count: u64 = metrics.responses[kind_index]
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum();
+        for (bucket, bound) in metrics.latency_buckets[kind_index]
+            .iter()
+            .zip(LATENCY_BUCKETS)
+        {
+            let _ = writeln!(
+                output,
+                "memorage_request_duration_seconds_bucket{{type=\"{}\",le=\"{bound}\"}} {}",
+                kind.label(),
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(
+            output,
+            "memorage_request_duration_seconds_bucket{{type=\"{}\",le=\"+Inf\"}} {count}",
+            kind.label()
+        );
+        let _ = writeln!(
+            output,
+            "memorage_request_duration_seconds_sum{{type=\"{}\"}} {}",
+            kind.label(),
+            metrics.latency_micros[kind_index].load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(
+            output,
+            "memorage_request_duration_seconds_count{{type=\"{}\"}} {count}",
+            kind.label()
+        );
+    }
+
+    output.push_str("# HELP memorage_map_entries Entries held by each map.\n");
+    output.push_str("# TYPE memorage_map_entries gauge\n");
+    for map in Map::ALL {
+        let _ = writeln!(
+            output,
+            "memorage_map_entries{{map=\"{}\"}} {}",
+            map.label(),
+            metrics.map_entries[map as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    output.push_str("# HELP memorage_map_capacity Maximum entries held by each map.\n");
+    output.push_str("# TYPE memorage_map_capacity gauge\n");
+    for map in Map::ALL {
+        let _ = writeln!(
+            output,
+            "memorage_map_capacity{{map=\"{}\"}} {}",
+            map.label(),
+            metrics.map_capacity[map as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    output.push_str("# HELP memorage_queue_depth Commands waiting for each manager.\n");
+    output.push_str("# TYPE memorage_queue_depth gauge\n");
+    for (manager, depth) in channels.queue_depths() {
+        let _ = writeln!(
+            output,
+            "memorage_queue_depth{{manager=\"{manager}\"}} {depth}"
+        );
+    }
+
+    output
+}
+
+/// Serves the metrics over HTTP at `/metrics`.
+pub async fn serve_metrics(listener: TcpListener, channels: Channels) -> Result<()> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        debug!(%address, "accepted metrics connection");
+        let channels = channels.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_http(stream, &channels).await {
+                warn!(%address, ?e, "error serving metrics");
+            }
+        });
+    }
+}
+
+async fn handle_http(mut stream: TcpStream, channels: &Channels) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < MAX_HTTP_REQUEST_SIZE {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let request_line = buf.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|b| *b == b' ');
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", render(channels))
+        }
+        _ => ("404 Not Found", "text/plain", "not found\n".to_owned()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: \
+         close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -1,12 +1,146 @@
-use crate::manager::{establish, pair, request};
+use crate::{
+    manager::{establish, limit, pair, relay, request},
+    metrics::{Map, Metrics},
+    store::{Store, Tables},
+};
 
+use std::{sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
 use tokio::{sync::mpsc, task};
 
+/// The limits on how long the managers keep state, how much state they keep
+/// and how often clients can make requests.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// How long a pairing code, and the response to the subsequent register
+    /// request, stay valid.
+    #[serde(with = "crate::config::duration")]
+    pub code_ttl: Duration,
+    /// How long after the requested time a connection request stays valid.
+    #[serde(with = "crate::config::duration")]
+    pub request_grace: Duration,
+    /// How far in the future a connection request can be made for, which
+    /// must exceed the delay with which clients schedule their connections.
+    #[serde(with = "crate::config::duration")]
+    pub request_horizon: Duration,
+    /// How long a pinged address stays valid.
+    #[serde(with = "crate::config::duration")]
+    pub address_ttl: Duration,
+    /// How often expired entries are removed, which must be non-zero.
+    #[serde(with = "crate::config::duration")]
+    pub sweep_interval: Duration,
+    /// The maximum number of pairing codes, and of responses to register
+    /// requests.
+    pub code_map_size: usize,
+    /// The maximum number of connection requests, and of confirmed
+    /// rendezvous.
+    pub request_map_size: usize,
+    /// The maximum number of pending connection requests for a single target.
+    ///
+    /// If exceeded, the oldest request is dropped.
+    pub request_queue_size: usize,
+    /// The maximum number of pinged addresses.
+    pub address_map_size: usize,
+    /// The maximum number of concurrent relay sessions between peers that
+    /// can't connect directly. Relaying is disabled if zero.
+    pub relay_sessions: usize,
+    /// How long a relay session lasts without forwarding any datagrams.
+    #[serde(with = "crate::config::duration")]
+    pub relay_idle_timeout: Duration,
+    /// The rate at which a public key can make requests.
+    pub key_limit: RateLimit,
+    /// The rate at which an IP address can make requests.
+    pub address_limit: RateLimit,
+    /// The rate at which a public key or IP address can make get key requests
+    /// with invalid pairing codes.
+    pub get_key_failure_limit: RateLimit,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            code_ttl: Duration::from_secs(10 * 60),
+            request_grace: Duration::from_secs(5 * 60),
+            request_horizon: Duration::from_secs(60 * 60),
+            address_ttl: Duration::from_secs(5 * 60),
+            sweep_interval: Duration::from_secs(60),
+            code_map_size: 256,
+            request_map_size: 256,
+            request_queue_size: 8,
+            address_map_size: 256,
+            relay_sessions: 0,
+            relay_idle_timeout: Duration::from_secs(30),
+            key_limit: RateLimit {
+                burst: 20,
+                interval: Duration::from_secs(1),
+            },
+            address_limit: RateLimit {
+                burst: 50,
+                interval: Duration::from_millis(200),
+            },
+            get_key_failure_limit: RateLimit {
+                burst: 5,
+                interval: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+impl Limits {
+    /// Checks that the limits can be used by the managers.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.sweep_interval.is_zero() {
+            return Err(crate::Error::InvalidLimits(
+                "sweep_interval must be non-zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A token bucket rate limit.
+///
+/// Clients can make up to `burst` requests at once, after which they can make
+/// one request every `interval`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    #[serde(with = "crate::config::duration")]
+    pub interval: Duration,
+}
+
+const PAIR_QUEUE_SIZE: usize = 16;
+const REQUEST_QUEUE_SIZE: usize = 32;
+const ESTABLISH_QUEUE_SIZE: usize = 32;
+const LIMIT_QUEUE_SIZE: usize = 64;
+const RELAY_QUEUE_SIZE: usize = 16;
+
 #[derive(Clone, Debug)]
 pub struct Channels {
     pub pair: mpsc::Sender<pair::Command>,
     pub request: mpsc::Sender<request::Command>,
     pub establish: mpsc::Sender<establish::Command>,
+    pub limit: mpsc::Sender<limit::Command>,
+    pub relay: mpsc::Sender<relay::Command>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl Channels {
+    /// Returns the number of commands waiting for each manager.
+    pub(crate) fn queue_depths(&self) -> [(&'static str, usize); 5] {
+        [
+            ("pair", PAIR_QUEUE_SIZE - self.pair.capacity()),
+            ("request", REQUEST_QUEUE_SIZE - self.request.capacity()),
+            (
+                "establish",
+                ESTABLISH_QUEUE_SIZE - self.establish.capacity(),
+            ),
+            ("limit", LIMIT_QUEUE_SIZE - self.limit.capacity()),
+            ("relay", RELAY_QUEUE_SIZE - self.relay.capacity()),
+        ]
+    }
 }
 
 #[derive(Debug)]
@@ -14,35 +148,93 @@ pub struct Handles {
     pair: task::JoinHandle<()>,
     request: task::JoinHandle<()>,
     establish: task::JoinHandle<()>,
+    limit: task::JoinHandle<()>,
+    relay: task::JoinHandle<()>,
 }
 
 impl Handles {
     pub async fn join(self) -> Result<(), task::JoinError> {
         self.pair.await?;
         self.request.await?;
-        self.establish.await
+        self.establish.await?;
+        self.limit.await?;
+        self.relay.await
     }
 }
 
+/// Spawns the managers with the default [`Limits`], keeping their state in
+/// memory.
 pub fn setup() -> (Channels, Handles) {
-    let (pair_tx, pair_rx) = mpsc::channel(16);
-    let (request_tx, request_rx) = mpsc::channel(32);
-    let (establish_tx, establish_rx) = mpsc::channel(32);
+    spawn(Limits::default(), Tables::default())
+}
+
+/// Spawns the managers with the given limits, which must be
+/// [valid](Limits::validate).
+///
+/// If a store is given, the managers load their state from it and write
+/// changes through to it.
+pub fn setup_with(limits: Limits, store: Option<&Store>) -> crate::Result<(Channels, Handles)> {
+    limits.validate()?;
+    let tables = match store {
+        Some(store) => Tables::open(store)?,
+        None => Tables::default(),
+    };
+    Ok(spawn(limits, tables))
+}
+
+fn spawn(limits: Limits, tables: Tables) -> (Channels, Handles) {
+    let (pair_tx, pair_rx) = mpsc::channel(PAIR_QUEUE_SIZE);
+    let (request_tx, request_rx) = mpsc::channel(REQUEST_QUEUE_SIZE);
+    let (establish_tx, establish_rx) = mpsc::channel(ESTABLISH_QUEUE_SIZE);
+    let (limit_tx, limit_rx) = mpsc::channel(LIMIT_QUEUE_SIZE);
+    let (relay_tx, relay_rx) = mpsc::channel(RELAY_QUEUE_SIZE);
+
+    let metrics = Arc::new(Metrics::default());
+    metrics.set_map_capacity(Map::Codes, limits.code_map_size);
+    metrics.set_map_capacity(Map::Requestors, limits.code_map_size);
+    metrics.set_map_capacity(Map::Requests, limits.request_map_size);
+    metrics.set_map_capacity(Map::Rendezvous, limits.request_map_size);
+    metrics.set_map_capacity(Map::Addresses, limits.address_map_size);
+    metrics.set_map_capacity(Map::Relays, limits.relay_sessions);
 
-    let pair_manager = tokio::spawn(pair::manager(pair_rx));
-    let request_manager = tokio::spawn(request::manager(request_rx));
-    let establish_manager = tokio::spawn(establish::manager(establish_rx));
+    let pair_manager = tokio::spawn(pair::manager(
+        pair_rx,
+        limits,
+        metrics.clone(),
+        tables.codes,
+        tables.requestors,
+    ));
+    let request_manager = tokio::spawn(request::manager(
+        request_rx,
+        limits,
+        metrics.clone(),
+        tables.requests,
+        tables.rendezvous,
+    ));
+    let establish_manager = tokio::spawn(establish::manager(
+        establish_rx,
+        limits,
+        metrics.clone(),
+        tables.addresses,
+    ));
+    let limit_manager = tokio::spawn(limit::manager(limit_rx, limits));
+    let relay_manager = tokio::spawn(relay::manager(relay_rx, limits, metrics.clone()));
 
     (
         Channels {
             pair: pair_tx,
             request: request_tx,
             establish: establish_tx,
+            limit: limit_tx,
+            relay: relay_tx,
+            metrics,
         },
         Handles {
             pair: pair_manager,
             request: request_manager,
             establish: establish_manager,
+            limit: limit_manager,
+            relay: relay_manager,
         },
     )
 }
@@ -0,0 +1,248 @@
+use crate::{
+    collections::MaxSizeHashMap,
+    manager::{establish::Pinged, request::Rendezvous},
+    Result,
+};
+
+use std::{fmt::Debug, hash::Hash, marker::PhantomData, path::Path};
+
+use memorage_core::{time::OffsetDateTime, PublicKey};
+use memorage_cs::{response::ConnectionRequest, PairingCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, error, warn};
+
+/// An on-disk store for the state held by the managers.
+///
+/// Without a store, pairing codes, connection requests and peer addresses are
+/// lost when the server restarts.
+#[derive(Clone, Debug)]
+pub struct Store {
+    db: sled::Db,
+}
+
+impl Store {
+    /// Opens the store at the given path, creating it if it doesn't exist.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    pub(crate) fn table<K, V>(&self, name: &str) -> Result<Table<K, V>> {
+        Ok(Table {
+            tree: self.db.open_tree(name)?,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// A typed tree in the [`Store`].
+pub(crate) struct Table<K, V> {
+    tree: sled::Tree,
+    _phantom: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> Debug for Table<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Table")
+            .field("name", &String::from_utf8_lossy(&self.tree.name()))
+            .finish()
+    }
+}
+
+impl<K, V> Table<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn insert(&self, key: &K, value: &V) -> Result<()> {
+        let key = bincode::serialize(key)?;
+        let value = bincode::serialize(value)?;
+        self.tree.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &K) -> Result<()> {
+        let key = bincode::serialize(key)?;
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<(K, V)>> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((bincode::deserialize(&key)?, bincode::deserialize(&value)?))
+            })
+            .collect()
+    }
+}
+
+/// A value that is only valid until its expiry.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Expiring<V> {
+    value: V,
+    #[serde(with = "memorage_core::time::serde::timestamp")]
+    expiry: OffsetDateTime,
+}
+
+impl<V> Expiring<V> {
+    fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.expiry <= now
+    }
+}
+
+/// The tables backing the managers' state.
+#[derive(Debug, Default)]
+pub(crate) struct Tables {
+    pub(crate) codes: Option<Table<PairingCode, Expiring<PublicKey>>>,
+    pub(crate) requestors: Option<Table<PublicKey, Expiring<PublicKey>>>,
+    pub(crate) requests: Option<Table<PublicKey, Expiring<Vec<ConnectionRequest>>>>,
+    pub(crate) rendezvous: Option<Table<Rendezvous, Expiring<()>>>,
+    pub(crate) addresses: Option<Table<(PublicKey, PublicKey), Expiring<Pinged>>>,
+}
+
+impl Tables {
+    pub(crate) fn open(store: &Store) -> Result<Self> {
+        Ok(Self {
+            codes: Some(store.table("codes")?),
+            requestors: Some(store.table("requestors")?),
+            requests: Some(store.table("requests")?),
+            rendezvous: Some(store.table("rendezvous")?),
+            addresses: Some(store.table("addresses")?),
+        })
+    }
+}
+
+/// A [`MaxSizeHashMap`] of expiring entries whose changes are written through
+/// to a [`Table`].
+///
+/// Expired entries are never returned, but they only free up space once
+/// [`sweep`](Self::sweep) is called. Unexpired entries are only evicted when
+/// the map is full.
+///
+/// Errors writing to the table are logged rather than returned, so that the
+/// server keeps functioning from memory if the store fails.
+pub(crate) struct PersistentMap<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    map: MaxSizeHashMap<K, Expiring<V>>,
+    table: Option<Table<K, Expiring<V>>>,
+}
+
+impl<K, V> PersistentMap<K, V>
+where
+    K: Hash + Eq + Clone + Debug + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Creates a map that holds at most `capacity` entries, loading any
+    /// unexpired entries already in the table.
+    pub(crate) fn new(capacity: usize, table: Option<Table<K, Expiring<V>>>) -> Self {
+        let mut result = Self {
+            map: MaxSizeHashMap::new(capacity),
+            table: None,
+        };
+
+        if let Some(ref table) = table {
+            match table.entries() {
+                Ok(entries) => {
+                    let now = OffsetDateTime::now_utc();
+                    for (key, entry) in entries {
+                        if entry.is_expired(now) {
+                            remove_from_table(table, &key);
+                            continue;
+                        }
+                        // The table may contain more entries than the capacity
+                        // if the capacity was reduced since it was written.
+                        if let (_, Some((evicted_key, _))) = result.map.insert_evicting(key, entry)
+                        {
+                            remove_from_table(table, &evicted_key);
+                        }
+                    }
+                }
+                Err(e) => error!(?table, %e, "error loading table"),
+            }
+        }
+
+        result.table = table;
+        result
+    }
+
+    pub(crate) fn insert(&mut self, key: K, value: V, expiry: OffsetDateTime) {
+        let entry = Expiring { value, expiry };
+        if let Some(ref table) = self.table {
+            if let Err(e) = table.insert(&key, &entry) {
+                warn!(?table, %e, "error inserting into table");
+            }
+        }
+
+        let (_, evicted) = self.map.insert_evicting(key.clone(), entry);
+        if let (Some(table), Some((evicted_key, _))) = (&self.table, evicted) {
+            // The evicted key may be the key that was just inserted.
+            if evicted_key != key {
+                remove_from_table(table, &evicted_key);
+            }
+        }
+    }
+
+    /// Returns the number of entries, including expired entries that haven't
+    /// been swept.
+    pub(crate) fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.map
+            .get(key)
+            .is_some_and(|entry| !entry.is_expired(OffsetDateTime::now_utc()))
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<&V> {
+        self.map
+            .get(key)
+            .filter(|entry| !entry.is_expired(OffsetDateTime::now_utc()))
+            .map(|entry| &entry.value)
+    }
+
+    /// Removes the entry for the key, returning its value if it hasn't expired.
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.map.remove(key)?;
+        if let Some(ref table) = self.table {
+            remove_from_table(table, key);
+        }
+
+        if entry.is_expired(OffsetDateTime::now_utc()) {
+            None
+        } else {
+            Some(entry.value)
+        }
+    }
+
+    /// Removes the entries that have expired.
+    pub(crate) fn sweep(&mut self, now: OffsetDateTime) {
+        let expired = self.map.remove_where(|_, entry| entry.is_expired(now));
+        if !expired.is_empty() {
+            debug!(count = expired.len(), "swept expired entries");
+        }
+        if let Some(ref table) = self.table {
+            for key in expired {
+                remove_from_table(table, &key);
+            }
+        }
+    }
+}
+
+fn remove_from_table<K, V>(table: &Table<K, V>, key: &K)
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    if let Err(e) = table.remove(key) {
+        warn!(?table, %e, "error removing from table");
+    }
+}
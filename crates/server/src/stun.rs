@@ -0,0 +1,88 @@
+use crate::Result;
+
+use std::net::SocketAddr;
+
+use futures_util::future::try_join_all;
+use memorage_stun::BindingRequest;
+use tokio::net::UdpSocket;
+use tracing::{debug, trace};
+
+/// The largest STUN request that is answered.
+const MAX_REQUEST_SIZE: usize = 576;
+
+/// Answers STUN Binding requests, so that clients can discover their public
+/// address without a third-party STUN server.
+pub async fn serve_stun(socket: UdpSocket) -> Result<()> {
+    let mut buf = [0; MAX_REQUEST_SIZE];
+    loop {
+        let (len, source) = socket.recv_from(&mut buf).await?;
+        let response = match memorage_stun::binding_response(&buf[..len], source) {
+            Ok(response) => response,
+            Err(e) => {
+                trace!(%source, ?e, "dropping invalid STUN request");
+                continue;
+            }
+        };
+        if let Err(e) = socket.send_to(&response, source).await {
+            debug!(%source, ?e, "error sending STUN response");
+        }
+    }
+}
+
+/// Answers STUN Binding requests on two IP addresses with two ports each,
+/// indexed by IP address and then by port.
+///
+/// Responses are sent from the IP address and port the client asks for, so
+/// that it can discover the behaviour of its NAT.
+pub async fn serve_stun_with_alternate(sockets: [[UdpSocket; 2]; 2]) -> Result<()> {
+    let mut addresses = [[SocketAddr::from(([0, 0, 0, 0], 0)); 2]; 2];
+    for (ip, sockets) in sockets.iter().enumerate() {
+        for (port, socket) in sockets.iter().enumerate() {
+            addresses[ip][port] = socket.local_addr()?;
+        }
+    }
+
+    let responders = (0..4).map(|i| respond(&sockets, addresses, i / 2, i % 2));
+    try_join_all(responders).await?;
+    Ok(())
+}
+
+async fn respond(
+    sockets: &[[UdpSocket; 2]; 2],
+    addresses: [[SocketAddr; 2]; 2],
+    ip: usize,
+    port: usize,
+) -> Result<()> {
+    let mut buf = [0; MAX_REQUEST_SIZE];
+    loop {
+        let (len, source) = sockets[ip][port].recv_from(&mut buf).await?;
+        let request = match BindingRequest::decode(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                trace!(%source, ?e, "dropping invalid STUN request");
+                continue;
+            }
+        };
+
+        let response_ip = ip ^ usize::from(request.change_ip());
+        let response_port = port ^ usize::from(request.change_port());
+        let origin = addresses[response_ip][response_port];
+        // The other address differs from the address the request was received
+        // on in both IP address and port.
+        let other = addresses[1 - ip][1 - port];
+
+        let response = match request.response(source, Some(origin), Some(other)) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!(%source, ?e, "error encoding STUN response");
+                continue;
+            }
+        };
+        if let Err(e) = sockets[response_ip][response_port]
+            .send_to(&response, source)
+            .await
+        {
+            debug!(%source, ?e, "error sending STUN response");
+        }
+    }
+}
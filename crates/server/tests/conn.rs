@@ -1,9 +1,27 @@
 mod util;
 
-use util::{ID_1, ID_2};
+use util::{Identity, ID_1, ID_2};
 
-use memorage_core::time::OffsetDateTime;
-use memorage_cs::{request, response, Error};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use memorage_core::{time::OffsetDateTime, Behaviour, KeyPair, NatType};
+use memorage_cs::{
+    request,
+    response::{self, Strategy},
+    Candidate, CandidateKind, Error,
+};
+use memorage_server::Limits;
+
+/// Returns the candidates of a peer that hasn't published any.
+fn seen(identity: &Identity) -> Vec<Candidate> {
+    vec![Candidate::new(
+        identity.address,
+        CandidateKind::ServerReflexive,
+    )]
+}
 
 #[tokio::test]
 async fn basic() {
@@ -18,33 +36,398 @@ async fn basic() {
     let response = util::request(request, &ID_1, channels.clone()).await;
     assert_eq!(response, Ok(response::RequestConnection));
 
-    let request = request::Ping(ID_2.public_key);
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::CheckConnection(vec![
+            response::ConnectionRequest {
+                initiator: ID_1.public_key,
+                time
+            }
+        ]))
+    );
+
+    let request = request::AcceptConnection(ID_1.public_key);
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Ok(response::AcceptConnection));
+
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::Ping {
+            candidates: seen(&ID_1),
+            strategy: Strategy::Punch,
+        })
+    );
+
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::Ping {
+            candidates: seen(&ID_2),
+            strategy: Strategy::Punch,
+        })
+    );
+}
+
+#[tokio::test]
+async fn unconfirmed_ping() {
+    let (channels, _handles) = memorage_server::setup();
+
+    let stranger = Identity {
+        public_key: KeyPair::from_entropy().public,
+        address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 4, 5, 6)), 3),
+    };
+
+    // Without a rendezvous, pings neither reveal nor store addresses.
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let time = OffsetDateTime::now_utc();
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time,
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Ok(response::RequestConnection));
+
+    // The request alone doesn't confirm the rendezvous.
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert!(response.is_ok());
+
+    // Neither does retrieving it.
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::AcceptConnection(ID_1.public_key);
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Ok(response::AcceptConnection));
+
+    // The pings sent before the rendezvous was confirmed weren't stored.
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    // A third peer can't learn the address of either peer.
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &stranger, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
     let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::Ping {
+            candidates: seen(&ID_2),
+            strategy: Strategy::Punch,
+        })
+    );
+}
+
+#[tokio::test]
+async fn multiple_requests() {
+    let limits = Limits {
+        request_queue_size: 2,
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let ids: Vec<_> = (0..3)
+        .map(|i| Identity {
+            public_key: KeyPair::from_entropy().public,
+            address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(4, 5, 6, i)), 4),
+        })
+        .collect();
+    let time = OffsetDateTime::now_utc();
+    let requests: Vec<_> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| response::ConnectionRequest {
+            initiator: id.public_key,
+            time: time + Duration::from_secs(i as u64),
+        })
+        .collect();
+
+    for (id, r) in ids.iter().zip(&requests) {
+        let request = request::RequestConnection {
+            target: ID_2.public_key,
+            time: r.time,
+        };
+        let response = util::request(request, id, channels.clone()).await;
+        assert_eq!(response, Ok(response::RequestConnection));
+    }
+
+    // The oldest request was dropped to make room.
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::CheckConnection(requests[1..].to_vec()))
+    );
+
+    let request = request::AcceptConnection(ids[0].public_key);
+    let response = util::request(request, &ID_2, channels.clone()).await;
     assert_eq!(response, Err(Error::NoData));
 
+    let request = request::AcceptConnection(ids[2].public_key);
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Ok(response::AcceptConnection));
+
     let request = request::CheckConnection;
     let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Ok(response::CheckConnection(vec![requests[1]])));
+
+    // Only the accepted initiator can exchange addresses with the target.
+    for id in &ids[..2] {
+        let request = request::Ping {
+            target: ID_2.public_key,
+            nat: None,
+            candidates: Vec::new(),
+        };
+        let response = util::request(request, id, channels.clone()).await;
+        assert_eq!(response, Err(Error::NoData));
+    }
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ids[2], channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ids[2].public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::Ping {
+            candidates: seen(&ids[2]),
+            strategy: Strategy::Punch,
+        })
+    );
+}
+
+#[tokio::test]
+async fn nat_strategy() {
+    let (channels, _handles) = memorage_server::setup();
+
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time: OffsetDateTime::now_utc(),
+    };
+    util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+    let request = request::AcceptConnection(ID_1.public_key);
+    util::request(request, &ID_2, channels.clone())
+        .await
+        .unwrap();
+
+    let symmetric = NatType {
+        mapping: Behaviour::AddressAndPortDependent,
+        filtering: Behaviour::AddressAndPortDependent,
+    };
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: Some(symmetric),
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: Some(symmetric),
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &ID_2, channels.clone()).await;
     assert_eq!(
         response,
-        Ok(response::CheckConnection {
-            initiator: ID_1.public_key,
-            time
+        Ok(response::Ping {
+            candidates: seen(&ID_1),
+            strategy: Strategy::Relay,
         })
     );
+}
+
+#[tokio::test]
+async fn published_candidates() {
+    let (channels, _handles) = memorage_server::setup();
 
-    let request = request::Ping(ID_2.public_key);
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time: OffsetDateTime::now_utc(),
+    };
+    util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+    let request = request::AcceptConnection(ID_1.public_key);
+    util::request(request, &ID_2, channels.clone())
+        .await
+        .unwrap();
+
+    let host = Candidate::new("192.168.0.2:1".parse().unwrap(), CandidateKind::Host);
+    let ipv6 = Candidate::new(
+        "[2600::1]:1".parse().unwrap(),
+        CandidateKind::ServerReflexive,
+    );
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        // The address the server sees isn't repeated.
+        candidates: vec![host, ipv6, seen(&ID_1)[0]],
+    };
     let response = util::request(request, &ID_1, channels.clone()).await;
     assert_eq!(response, Err(Error::NoData));
 
-    let request = request::Ping(ID_1.public_key);
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
     let response = util::request(request, &ID_2, channels.clone()).await;
-    assert_eq!(response, Ok(response::Ping(ID_1.address)));
+    assert_eq!(
+        response,
+        Ok(response::Ping {
+            candidates: vec![seen(&ID_1)[0], host, ipv6],
+            strategy: Strategy::Punch,
+        })
+    );
+}
+
+#[tokio::test]
+async fn addresses_per_rendezvous() {
+    let (channels, _handles) = memorage_server::setup();
+
+    let id_3 = Identity {
+        public_key: KeyPair::from_entropy().public,
+        address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 4, 5, 6)), 3),
+    };
+    for id in [&*ID_1, &id_3] {
+        let request = request::RequestConnection {
+            target: ID_2.public_key,
+            time: OffsetDateTime::now_utc(),
+        };
+        util::request(request, id, channels.clone()).await.unwrap();
+        let request = request::AcceptConnection(id.public_key);
+        util::request(request, &ID_2, channels.clone())
+            .await
+            .unwrap();
+    }
 
-    let request = request::Ping(ID_1.public_key);
+    let request = request::Ping {
+        target: ID_1.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
     let response = util::request(request, &ID_2, channels.clone()).await;
     assert_eq!(response, Err(Error::NoData));
 
-    let request = request::Ping(ID_2.public_key);
+    // The addresses published for ID_1 aren't revealed to another peer.
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
+    let response = util::request(request, &id_3, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let request = request::Ping {
+        target: ID_2.public_key,
+        nat: None,
+        candidates: Vec::new(),
+    };
     let response = util::request(request, &ID_1, channels.clone()).await;
-    assert_eq!(response, Ok(response::Ping(ID_2.address)));
+    assert_eq!(
+        response,
+        Ok(response::Ping {
+            candidates: seen(&ID_2),
+            strategy: Strategy::Punch,
+        })
+    );
 }
@@ -0,0 +1,143 @@
+mod util;
+
+use std::time::Duration;
+
+use util::{Identity, ID_1, ID_2};
+
+use memorage_core::{time::OffsetDateTime, KeyPair};
+use memorage_cs::{request, Error};
+use memorage_server::Limits;
+
+#[tokio::test]
+async fn code_expires() {
+    let limits = Limits {
+        code_ttl: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let request = request::Register;
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    let code = response.unwrap().0;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let request = request::GetKey(code);
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+}
+
+#[tokio::test]
+async fn request_expires_after_grace() {
+    let limits = Limits {
+        request_grace: Duration::from_secs(60),
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time: OffsetDateTime::now_utc() - Duration::from_secs(120),
+    };
+    util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    let time = OffsetDateTime::now_utc() - Duration::from_secs(30);
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time,
+    };
+    util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response.map(|r| r.0[0].time), Ok(time));
+}
+
+#[tokio::test]
+async fn swept_requests_free_space() {
+    let limits = Limits {
+        request_grace: Duration::from_millis(100),
+        sweep_interval: Duration::from_millis(50),
+        request_map_size: 2,
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let stranger = |i| Identity {
+        public_key: KeyPair::from_entropy().public,
+        address: ([5, 6, 7, i], 5).into(),
+    };
+
+    let time = OffsetDateTime::now_utc() + Duration::from_secs(60);
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time,
+    };
+    util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+
+    let request = request::RequestConnection {
+        target: stranger(0).public_key,
+        time: OffsetDateTime::now_utc(),
+    };
+    util::request(request, &stranger(1), channels.clone())
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // The expired request was swept, so there is room without evicting the
+    // live request.
+    let request = request::RequestConnection {
+        target: stranger(2).public_key,
+        time: OffsetDateTime::now_utc(),
+    };
+    util::request(request, &stranger(3), channels.clone())
+        .await
+        .unwrap();
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response.map(|r| r.0[0].time), Ok(time));
+}
+
+#[tokio::test]
+async fn zero_sweep_interval() {
+    let limits = Limits {
+        sweep_interval: Duration::ZERO,
+        ..Default::default()
+    };
+    assert!(matches!(
+        memorage_server::setup_with(limits, None),
+        Err(memorage_server::Error::InvalidLimits(_))
+    ));
+}
+
+#[tokio::test]
+async fn request_beyond_horizon() {
+    let limits = Limits {
+        request_horizon: Duration::from_secs(60),
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let request = request::RequestConnection {
+        target: ID_2.public_key,
+        time: OffsetDateTime::now_utc() + Duration::from_secs(120),
+    };
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::InvalidTime));
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+}
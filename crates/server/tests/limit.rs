@@ -0,0 +1,146 @@
+mod util;
+
+use std::{net::SocketAddr, time::Duration};
+
+use util::{Identity, ID_1, ID_2};
+
+use memorage_core::KeyPair;
+use memorage_cs::{request, Error, PairingCode};
+use memorage_server::{Limits, RateLimit};
+
+#[tokio::test]
+async fn key_limit() {
+    let limits = Limits {
+        key_limit: RateLimit {
+            burst: 2,
+            interval: Duration::from_secs(60),
+        },
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    for _ in 0..2 {
+        let response = util::request(request::Register, &ID_1, channels.clone()).await;
+        assert!(response.is_ok());
+    }
+    let response = util::request(request::Register, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::RateLimited));
+
+    // Other keys have their own buckets. ID_2 has a different address.
+    let response = util::request(request::Register, &ID_2, channels.clone()).await;
+    assert!(response.is_ok());
+}
+
+#[tokio::test]
+async fn address_limit_ipv6_prefix() {
+    let limits = Limits {
+        address_limit: RateLimit {
+            burst: 2,
+            interval: Duration::from_secs(60),
+        },
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let identity = |address: &str| Identity {
+        public_key: KeyPair::from_entropy().public,
+        address: address.parse::<SocketAddr>().unwrap(),
+    };
+
+    for address in ["[2001:db8::1]:1", "[2001:db8::2]:1"] {
+        let response = util::request(request::Register, &identity(address), channels.clone()).await;
+        assert!(response.is_ok());
+    }
+    // Addresses in the same /64 share a bucket.
+    let response = util::request(
+        request::Register,
+        &identity("[2001:db8::ffff:3]:1"),
+        channels.clone(),
+    )
+    .await;
+    assert_eq!(response, Err(Error::RateLimited));
+
+    let response = util::request(
+        request::Register,
+        &identity("[2001:db8:0:1::1]:1"),
+        channels.clone(),
+    )
+    .await;
+    assert!(response.is_ok());
+}
+
+#[tokio::test]
+async fn get_key_failure_limit() {
+    let limits = Limits {
+        get_key_failure_limit: RateLimit {
+            burst: 2,
+            interval: Duration::from_secs(60),
+        },
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let response = util::request(request::Register, &ID_1, channels.clone()).await;
+    let code = response.unwrap().0;
+
+    for _ in 0..2 {
+        let request = request::GetKey(PairingCode::new());
+        let response = util::request(request, &ID_2, channels.clone()).await;
+        assert_eq!(response, Err(Error::NoData));
+    }
+
+    // Even a valid code is rejected once too many invalid codes have been tried.
+    let request = request::GetKey(code);
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Err(Error::RateLimited));
+
+    // Other requests are unaffected.
+    let response = util::request(request::Register, &ID_2, channels.clone()).await;
+    assert!(response.is_ok());
+}
+
+#[tokio::test]
+async fn concurrent_get_key_failure_limit() {
+    let limits = Limits {
+        get_key_failure_limit: RateLimit {
+            burst: 2,
+            interval: Duration::from_secs(60),
+        },
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    // The requests are all admitted before any of their codes are looked up.
+    let responses = futures_util::future::join_all((0..10).map(|_| {
+        let request = request::GetKey(PairingCode::new());
+        util::request(request, &ID_2, channels.clone())
+    }))
+    .await;
+    let failures = responses
+        .iter()
+        .filter(|response| **response == Err(Error::NoData))
+        .count();
+    assert_eq!(failures, 2);
+    assert!(responses
+        .iter()
+        .all(|response| *response == Err(Error::NoData) || *response == Err(Error::RateLimited)));
+}
+
+#[tokio::test]
+async fn get_key_success_refunds_failure_token() {
+    let limits = Limits {
+        get_key_failure_limit: RateLimit {
+            burst: 1,
+            interval: Duration::from_secs(60),
+        },
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    for _ in 0..2 {
+        let response = util::request(request::Register, &ID_1, channels.clone()).await;
+        let code = response.unwrap().0;
+        let response = util::request(request::GetKey(code), &ID_2, channels.clone()).await;
+        assert_eq!(response.map(|r| r.0), Ok(ID_1.public_key));
+    }
+}
@@ -0,0 +1,52 @@
+mod util;
+
+use util::ID_1;
+
+use memorage_cs::request;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+async fn get(address: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn serve_metrics() {
+    let (channels, _handles) = memorage_server::setup();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(memorage_server::serve_metrics(listener, channels.clone()));
+
+    util::request(request::Register, &ID_1, channels.clone())
+        .await
+        .unwrap();
+    let response = util::request(request::CheckConnection, &ID_1, channels.clone()).await;
+    assert!(response.is_err());
+
+    let response = get(address, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    for line in [
+        r#"memorage_requests_total{type="register",result="ok"} 1"#,
+        r#"memorage_requests_total{type="check_connection",result="no_data"} 1"#,
+        r#"memorage_requests_total{type="ping",result="ok"} 0"#,
+        r#"memorage_request_duration_seconds_count{type="register"} 1"#,
+        r#"memorage_map_entries{map="codes"} 1"#,
+        r#"memorage_map_capacity{map="codes"} 256"#,
+        r#"memorage_queue_depth{manager="pair"} 0"#,
+    ] {
+        assert!(response.contains(line), "missing {line}");
+    }
+
+    let response = get(address, "/").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
@@ -0,0 +1,133 @@
+mod util;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use util::{Identity, ID_1, ID_2};
+
+use memorage_core::{time::OffsetDateTime, KeyPair};
+use memorage_cs::{request, Error};
+use memorage_server::{setup::Channels, Limits};
+use tokio::net::UdpSocket;
+
+async fn rendezvous(initiator: &Identity, target: &Identity, channels: &Channels) {
+    let request = request::RequestConnection {
+        target: target.public_key,
+        time: OffsetDateTime::now_utc(),
+    };
+    util::request(request, initiator, channels.clone())
+        .await
+        .unwrap();
+
+    let request = request::AcceptConnection(initiator.public_key);
+    util::request(request, target, channels.clone())
+        .await
+        .unwrap();
+}
+
+async fn recv(socket: &UdpSocket) -> Option<Vec<u8>> {
+    let mut buf = [0; 64];
+    let len = tokio::time::timeout(Duration::from_millis(500), socket.recv(&mut buf))
+        .await
+        .ok()?
+        .unwrap();
+    Some(buf[..len].to_vec())
+}
+
+#[tokio::test]
+async fn relay() {
+    let limits = Limits {
+        relay_sessions: 1,
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    let request = request::Relay(ID_2.public_key);
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::NoData));
+
+    rendezvous(&ID_1, &ID_2, &channels).await;
+
+    let request = request::Relay(ID_2.public_key);
+    let relay_1 = util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+    let request = request::Relay(ID_1.public_key);
+    let relay_2 = util::request(request, &ID_2, channels.clone())
+        .await
+        .unwrap();
+    assert_eq!(relay_1.port, relay_2.port);
+    assert_ne!(relay_1.token, relay_2.token);
+
+    let relay_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), relay_1.port);
+    let socket_1 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let socket_2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for socket in [&socket_1, &socket_2, &stranger] {
+        socket.connect(relay_address).await.unwrap();
+    }
+
+    socket_1.send(&relay_1.token).await.unwrap();
+    socket_2.send(&relay_2.token).await.unwrap();
+    // The tokens aren't relayed.
+    assert_eq!(recv(&socket_1).await, None);
+    assert_eq!(recv(&socket_2).await, None);
+
+    socket_1.send(b"hello").await.unwrap();
+    assert_eq!(recv(&socket_2).await.as_deref(), Some(&b"hello"[..]));
+    socket_2.send(b"world").await.unwrap();
+    assert_eq!(recv(&socket_1).await.as_deref(), Some(&b"world"[..]));
+
+    stranger.send(b"hello").await.unwrap();
+    assert_eq!(recv(&socket_1).await, None);
+    assert_eq!(recv(&socket_2).await, None);
+
+    // The only session is in use.
+    let other = Identity {
+        public_key: KeyPair::from_entropy().public,
+        address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(3, 4, 5, 6)), 3),
+    };
+    rendezvous(&other, &ID_2, &channels).await;
+    let request = request::Relay(ID_2.public_key);
+    let response = util::request(request, &other, channels.clone()).await;
+    assert_eq!(response, Err(Error::RelayUnavailable));
+}
+
+#[tokio::test]
+async fn relay_disabled() {
+    let (channels, _handles) = memorage_server::setup();
+
+    rendezvous(&ID_1, &ID_2, &channels).await;
+
+    let request = request::Relay(ID_2.public_key);
+    let response = util::request(request, &ID_1, channels.clone()).await;
+    assert_eq!(response, Err(Error::RelayUnavailable));
+}
+
+#[tokio::test]
+async fn relay_idle_timeout() {
+    let limits = Limits {
+        relay_sessions: 1,
+        relay_idle_timeout: Duration::from_millis(100),
+        sweep_interval: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let (channels, _handles) = memorage_server::setup_with(limits, None).unwrap();
+
+    rendezvous(&ID_1, &ID_2, &channels).await;
+    let request = request::Relay(ID_2.public_key);
+    let first = util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // The idle session was freed, so a new session is allocated.
+    let request = request::Relay(ID_2.public_key);
+    let second = util::request(request, &ID_1, channels.clone())
+        .await
+        .unwrap();
+    assert_ne!(first.token, second.token);
+}
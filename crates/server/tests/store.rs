@@ -0,0 +1,66 @@
+mod util;
+
+use util::{ID_1, ID_2};
+
+use memorage_core::time::OffsetDateTime;
+use memorage_cs::{request, response};
+use memorage_server::Store;
+
+#[tokio::test]
+async fn survives_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store");
+
+    let time = OffsetDateTime::now_utc();
+    let code = {
+        let store = Store::open(&path).unwrap();
+        let (channels, handles) =
+            memorage_server::setup_with(Default::default(), Some(&store)).unwrap();
+
+        let request = request::Register;
+        let response = util::request(request, &ID_1, channels.clone()).await;
+        let code = response.unwrap().0;
+
+        let request = request::RequestConnection {
+            target: ID_2.public_key,
+            time,
+        };
+        let response = util::request(request, &ID_1, channels.clone()).await;
+        assert_eq!(response, Ok(response::RequestConnection));
+
+        // The managers exit once all channels are dropped, releasing the store.
+        drop(channels);
+        handles.join().await.unwrap();
+        code
+    };
+
+    // Sled's background flusher may briefly hold the lock after the store is
+    // dropped.
+    let mut tries = 0;
+    let store = loop {
+        match Store::open(&path) {
+            Ok(store) => break store,
+            Err(_) if tries < 50 => tries += 1,
+            Err(e) => panic!("failed to reopen store: {e:?}"),
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    let (channels, _handles) =
+        memorage_server::setup_with(Default::default(), Some(&store)).unwrap();
+
+    let request = request::GetKey(code);
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(response, Ok(response::GetKey(ID_1.public_key)));
+
+    let request = request::CheckConnection;
+    let response = util::request(request, &ID_2, channels.clone()).await;
+    assert_eq!(
+        response,
+        Ok(response::CheckConnection(vec![
+            response::ConnectionRequest {
+                initiator: ID_1.public_key,
+                time,
+            }
+        ]))
+    );
+}
@@ -0,0 +1,52 @@
+use memorage_core::{Behaviour, NatType};
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn binding_request() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_address = server.local_addr().unwrap();
+    tokio::spawn(memorage_server::serve_stun(server));
+
+    let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = memorage_stun::public_address(&mut socket, server_address)
+        .await
+        .unwrap();
+    assert_eq!(address, socket.local_addr().unwrap());
+
+    // Invalid requests are ignored rather than ending the responder.
+    socket.send_to(b"hello", server_address).await.unwrap();
+    let address = memorage_stun::public_address(&mut socket, server_address)
+        .await
+        .unwrap();
+    assert_eq!(address, socket.local_addr().unwrap());
+}
+
+#[tokio::test]
+async fn behaviour_discovery() {
+    // The same pair of ports is used on both IP addresses.
+    let primary = [
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+    ];
+    let ports = [0, 1].map(|i| primary[i].local_addr().unwrap().port());
+    let alternate = [
+        UdpSocket::bind(("127.0.0.2", ports[0])).await.unwrap(),
+        UdpSocket::bind(("127.0.0.2", ports[1])).await.unwrap(),
+    ];
+    let server_address = primary[0].local_addr().unwrap();
+    tokio::spawn(memorage_server::serve_stun_with_alternate([
+        primary, alternate,
+    ]));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let nat = memorage_stun::nat_type(&socket, server_address)
+        .await
+        .unwrap();
+    assert_eq!(
+        nat,
+        NatType {
+            mapping: Behaviour::EndpointIndependent,
+            filtering: Behaviour::EndpointIndependent,
+        }
+    );
+}
@@ -1,6 +1,6 @@
 use crate::{StunError, MAGIC_COOKIE};
 
-use std::{convert::TryFrom, net};
+use std::{convert::TryFrom, net, time::Duration};
 
 type Result<T> = std::result::Result<T, StunError>;
 
@@ -10,14 +10,55 @@ type Result<T> = std::result::Result<T, StunError>;
 pub(crate) enum Attribute {
     Software(Software),
     XorMappedAddress(XorMappedAddress),
+    Username(Username),
+    MessageIntegrity(MessageIntegrity),
+    ErrorCode(ErrorCode),
+    Realm(Realm),
+    Nonce(Nonce),
+    ChannelNumber(ChannelNumber),
+    Lifetime(Lifetime),
+    XorPeerAddress(XorPeerAddress),
+    Data(Data),
+    XorRelayedAddress(XorRelayedAddress),
+    RequestedTransport(RequestedTransport),
+    MappedAddress(MappedAddress),
+    ChangeRequest(ChangeRequest),
+    ResponseOrigin(ResponseOrigin),
+    OtherAddress(OtherAddress),
+    MessageIntegritySha256(MessageIntegritySha256),
+    Fingerprint(Fingerprint),
+}
+
+/// Calls the given method on the attribute contained in each variant.
+macro_rules! dispatch {
+    ($attribute:expr, $a:ident => $call:expr) => {
+        match $attribute {
+            Attribute::Software($a) => $call,
+            Attribute::XorMappedAddress($a) => $call,
+            Attribute::Username($a) => $call,
+            Attribute::MessageIntegrity($a) => $call,
+            Attribute::ErrorCode($a) => $call,
+            Attribute::Realm($a) => $call,
+            Attribute::Nonce($a) => $call,
+            Attribute::ChannelNumber($a) => $call,
+            Attribute::Lifetime($a) => $call,
+            Attribute::XorPeerAddress($a) => $call,
+            Attribute::Data($a) => $call,
+            Attribute::XorRelayedAddress($a) => $call,
+            Attribute::RequestedTransport($a) => $call,
+            Attribute::MappedAddress($a) => $call,
+            Attribute::ChangeRequest($a) => $call,
+            Attribute::ResponseOrigin($a) => $call,
+            Attribute::OtherAddress($a) => $call,
+            Attribute::MessageIntegritySha256($a) => $call,
+            Attribute::Fingerprint($a) => $call,
+        }
+    };
 }
 
 impl Attribute {
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        match self {
-            Attribute::Software(a) => a.to_bytes(),
-            Attribute::XorMappedAddress(a) => a.to_bytes(),
-        }
+        dispatch!(self, a => a.to_bytes())
     }
 
     /// Decodes bytes into an [`Attribute`]. The function requires the
@@ -25,35 +66,79 @@ impl Attribute {
     /// to successfully interpret the bytes. If given multiple attributes,
     /// it will only decode the top most one and return the decoded attribute
     /// and how many bytes were read while decoding it.
+    ///
+    /// Unknown comprehension-optional attributes are skipped, returning
+    /// [`None`] and their length, while unknown comprehension-required
+    /// attributes are an error.
     #[allow(clippy::missing_panics_doc)]
-    pub(crate) fn from_bytes(data: Vec<u8>, tid: [u8; 12]) -> Result<(Self, usize)> {
+    pub(crate) fn from_bytes(data: Vec<u8>, tid: [u8; 12]) -> Result<(Option<Self>, usize)> {
         // Ensure future indexing won't panic.
         if data.len() < 2 {
             return Err(StunError::InvalidAttributeType);
         }
 
-        match u16::from_be_bytes(<[u8; 2]>::try_from(&data[0..2]).unwrap()) {
-            Software::TYPE => {
-                let result = Software::from_bytes(data, tid)?;
-                Ok((Attribute::Software(result.0), result.1))
-            }
-            XorMappedAddress::TYPE => {
-                let result = XorMappedAddress::from_bytes(data, tid)?;
-                Ok((Attribute::XorMappedAddress(result.0), result.1))
-            }
-            _ => Err(StunError::InvalidAttributeType),
+        // Decodes the attribute of the given type, wrapping it in the variant of
+        // the same name.
+        macro_rules! decode {
+            ($($t:ident),*$(,)?) => {
+                match u16::from_be_bytes(<[u8; 2]>::try_from(&data[0..2]).unwrap()) {
+                    $(
+                        $t::TYPE => {
+                            let result = $t::from_bytes(data, tid)?;
+                            Ok((Some(Attribute::$t(result.0)), result.1))
+                        }
+                    )*
+                    ty if ty >= COMPREHENSION_OPTIONAL => Ok((None, skipped_len(&data)?)),
+                    _ => Err(StunError::InvalidAttributeType),
+                }
+            };
         }
+
+        decode![
+            Software,
+            XorMappedAddress,
+            Username,
+            MessageIntegrity,
+            ErrorCode,
+            Realm,
+            Nonce,
+            ChannelNumber,
+            Lifetime,
+            XorPeerAddress,
+            Data,
+            XorRelayedAddress,
+            RequestedTransport,
+            MappedAddress,
+            ChangeRequest,
+            ResponseOrigin,
+            OtherAddress,
+            MessageIntegritySha256,
+            Fingerprint,
+        ]
     }
 
     #[allow(clippy::len_without_is_empty)]
     pub(crate) fn len(&self) -> usize {
-        match self {
-            Attribute::Software(a) => a.len(),
-            Attribute::XorMappedAddress(a) => a.len(),
-        }
+        dispatch!(self, a => a.len())
     }
 }
 
+/// Attribute types from this value upwards are comprehension-optional, and can
+/// be ignored by agents that don't understand them.
+const COMPREHENSION_OPTIONAL: u16 = 0x8000;
+
+/// Returns the length of the attribute, including its header and padding.
+fn skipped_len(data: &[u8]) -> Result<usize> {
+    if data.len() < 4 {
+        return Err(StunError::IncorrectAttributeLength);
+    }
+    let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    if 4 + len > data.len() {
+        return Err(StunError::IncorrectAttributeLength);
+    }
+    Ok(4 + len + (4 - len % 4) % 4)
+}
+
 /// The trait implemented by all STUN attributes.
 #[allow(clippy::len_without_is_empty)]
 pub(crate) trait AttributeExt: Sized {
@@ -127,6 +212,9 @@ pub(crate) trait AttributeExt: Sized {
         }
 
         // Ensure that indexing further down won't panic.
+        if data.len() < 4 {
+            return Err(StunError::IncorrectAttributeLength);
+        }
         let expected_len = u16::from_be_bytes(<[u8; 2]>::try_from(&data[2..4]).unwrap());
         if expected_len as usize > data.len() - 4 {
             return Err(StunError::IncorrectAttributeLength);
@@ -348,6 +436,608 @@ impl AttributeExt for XorMappedAddress {
     }
 }
 
+/// Defines an attribute containing a UTF-8 string of fewer than `$max` bytes.
+macro_rules! string_attribute {
+    ($(#[$meta:meta])* $name:ident, $ty:literal, $max:literal) => {
+        $(#[$meta])*
+        #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+        pub(crate) struct $name(String);
+
+        impl $name {
+            #[allow(dead_code)]
+            pub(crate) fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl std::convert::TryFrom<&str> for $name {
+            type Error = StunError;
+
+            fn try_from(value: &str) -> Result<Self> {
+                if value.as_bytes().len() >= $max {
+                    Err(StunError::AttributeTooLarge(stringify!($name)))
+                } else {
+                    Ok(Self(value.to_owned()))
+                }
+            }
+        }
+
+        impl AttributeExt for $name {
+            const TYPE: u16 = $ty;
+
+            fn encode(&self) -> Vec<u8> {
+                self.0.as_bytes().to_owned()
+            }
+
+            fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+                Ok(Self(String::from_utf8(data)?))
+            }
+
+            fn value_len(&self) -> usize {
+                self.0.as_bytes().len()
+            }
+        }
+    };
+}
+
+string_attribute!(
+    /// The user name used for message integrity.
+    ///
+    /// # Reference
+    /// [RFC 8489]
+    ///
+    /// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.3
+    Username,
+    0x0006,
+    513
+);
+
+string_attribute!(
+    /// The realm of the long-term credentials, which is sent by a server in
+    /// error responses asking the client to authenticate.
+    ///
+    /// # Reference
+    /// [RFC 8489]
+    ///
+    /// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.9
+    Realm,
+    0x0014,
+    763
+);
+
+string_attribute!(
+    /// A value chosen by the server, which the client must include in
+    /// authenticated requests.
+    ///
+    /// # Reference
+    /// [RFC 8489]
+    ///
+    /// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.10
+    Nonce,
+    0x0015,
+    763
+);
+
+/// An HMAC-SHA1 of the message, keyed with the credentials.
+///
+/// The HMAC is computed by [`Message`](crate::Message) when it is encoded, as
+/// it covers all of the preceding attributes.
+///
+/// # Reference
+/// [RFC 8489]
+///
+/// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.5
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub(crate) struct MessageIntegrity(pub(crate) [u8; 20]);
+
+impl AttributeExt for MessageIntegrity {
+    const TYPE: u16 = 0x0008;
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+        <[u8; 20]>::try_from(data)
+            .map(Self)
+            .map_err(|_| StunError::IncorrectAttributeLength)
+    }
+
+    fn value_len(&self) -> usize {
+        20
+    }
+}
+
+/// An HMAC-SHA256 of the message, keyed with the credentials.
+///
+/// The HMAC may be truncated to between 16 and 32 bytes, in multiples of 4.
+/// Like [`MessageIntegrity`], it is computed by [`Message`](crate::Message)
+/// when it is encoded.
+///
+/// # Reference
+/// [RFC 8489]
+///
+/// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.6
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub(crate) struct MessageIntegritySha256(pub(crate) Vec<u8>);
+
+impl AttributeExt for MessageIntegritySha256 {
+    const TYPE: u16 = 0x001C;
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+        if data.len() < 16 || data.len() > 32 || !data.len().is_multiple_of(4) {
+            return Err(StunError::IncorrectAttributeLength);
+        }
+        Ok(Self(data))
+    }
+
+    fn value_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// A CRC-32 of the message, which distinguishes STUN messages from other
+/// protocols sharing the port.
+///
+/// The CRC is computed by [`Message`](crate::Message) when it is encoded, and
+/// the attribute must be the last in the message.
+///
+/// # Reference
+/// [RFC 8489]
+///
+/// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.7
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub(crate) struct Fingerprint(pub(crate) u32);
+
+impl Fingerprint {
+    /// The value XORed with the CRC-32, so that the fingerprint differs from
+    /// the CRC of other protocols.
+    pub(crate) const XOR: u32 = 0x5354554e;
+}
+
+impl AttributeExt for Fingerprint {
+    const TYPE: u16 = 0x8028;
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+        <[u8; 4]>::try_from(data)
+            .map(|bytes| Self(u32::from_be_bytes(bytes)))
+            .map_err(|_| StunError::IncorrectAttributeLength)
+    }
+
+    fn value_len(&self) -> usize {
+        4
+    }
+}
+
+/// The error code and reason phrase of an error response.
+///
+/// # Reference
+/// [RFC 8489]
+///
+/// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.8
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub(crate) struct ErrorCode {
+    code: u16,
+    reason: String,
+}
+
+impl ErrorCode {
+    /// Try alternate server.
+    #[allow(dead_code)]
+    pub(crate) const TRY_ALTERNATE: u16 = 300;
+    /// The request was malformed.
+    #[allow(dead_code)]
+    pub(crate) const BAD_REQUEST: u16 = 400;
+    /// The request didn't contain the correct credentials.
+    pub(crate) const UNAUTHENTICATED: u16 = 401;
+    /// The nonce is no longer valid.
+    pub(crate) const STALE_NONCE: u16 = 438;
+
+    /// Creates an error code, which must be between 300 and 699.
+    #[allow(dead_code)]
+    pub(crate) fn new(code: u16, reason: &str) -> Self {
+        Self {
+            code,
+            reason: reason.to_owned(),
+        }
+    }
+
+    pub(crate) fn code(&self) -> u16 {
+        self.code
+    }
+
+    pub(crate) fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl AttributeExt for ErrorCode {
+    const TYPE: u16 = 0x0009;
+
+    fn encode(&self) -> Vec<u8> {
+        // The class is the hundreds digit of the code, and the number is the
+        // code modulo 100.
+        let mut result = vec![0, 0, (self.code / 100) as u8, (self.code % 100) as u8];
+        result.extend_from_slice(self.reason.as_bytes());
+        result
+    }
+
+    fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(StunError::IncorrectAttributeLength);
+        }
+
+        let code = (data[2] & 0x7) as u16 * 100 + data[3] as u16;
+        let reason = String::from_utf8(data[4..].to_vec())?;
+        Ok(Self { code, reason })
+    }
+
+    fn value_len(&self) -> usize {
+        4 + self.reason.len()
+    }
+}
+
+/// Fixed-size attributes containing a 32-bit value.
+macro_rules! u32_attribute {
+    ($(#[$meta:meta])* $name:ident, $ty:literal) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+        pub(crate) struct $name(u32);
+
+        impl AttributeExt for $name {
+            const TYPE: u16 = $ty;
+
+            fn encode(&self) -> Vec<u8> {
+                self.0.to_be_bytes().to_vec()
+            }
+
+            fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+                <[u8; 4]>::try_from(data)
+                    .map(|bytes| Self(u32::from_be_bytes(bytes)))
+                    .map_err(|_| StunError::IncorrectAttributeLength)
+            }
+
+            fn value_len(&self) -> usize {
+                4
+            }
+        }
+    };
+}
+
+u32_attribute!(
+    /// The channel number of a channel binding, followed by 16 reserved bits.
+    ///
+    /// # Reference
+    /// [RFC 8656]
+    ///
+    /// [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656#section-18.1
+    ChannelNumber,
+    0x000C
+);
+
+impl ChannelNumber {
+    pub(crate) fn new(number: u16) -> Self {
+        Self((number as u32) << 16)
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn number(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+}
+
+u32_attribute!(
+    /// The number of seconds the server will maintain an allocation for
+    /// without a refresh.
+    ///
+    /// # Reference
+    /// [RFC 8656]
+    ///
+    /// [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656#section-18.2
+    Lifetime,
+    0x000D
+);
+
+impl Lifetime {
+    pub(crate) fn new(lifetime: Duration) -> Self {
+        Self(u32::try_from(lifetime.as_secs()).unwrap_or(u32::MAX))
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_secs(self.0 as u64)
+    }
+}
+
+u32_attribute!(
+    /// The transport protocol of the relayed address, followed by 24 reserved
+    /// bits.
+    ///
+    /// # Reference
+    /// [RFC 8656]
+    ///
+    /// [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656#section-18.6
+    RequestedTransport,
+    0x0019
+);
+
+impl RequestedTransport {
+    /// The IANA protocol number of UDP.
+    pub(crate) const UDP: Self = Self(17 << 24);
+
+    #[allow(dead_code)]
+    pub(crate) fn protocol(&self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+}
+
+/// The application data of a Send or Data indication.
+///
+/// # Reference
+/// [RFC 8656]
+///
+/// [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656#section-18.4
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub(crate) struct Data(pub(crate) Vec<u8>);
+
+impl AttributeExt for Data {
+    const TYPE: u16 = 0x0013;
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+        Ok(Self(data))
+    }
+
+    fn value_len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Defines an attribute encoded in the same way as a [`XorMappedAddress`].
+macro_rules! xor_address_attribute {
+    ($(#[$meta:meta])* $name:ident, $ty:literal) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+        pub(crate) struct $name(XorMappedAddress);
+
+        impl $name {
+            #[allow(dead_code)]
+            pub(crate) fn from(ip: net::IpAddr, port: u16) -> Self {
+                Self(XorMappedAddress::from(ip, port))
+            }
+
+            /// This function must only be called by the [`Message`](crate::Message)
+            /// that carries the attribute.
+            pub(crate) fn set_tid(&mut self, tid: [u8; 12]) {
+                self.0.set_tid(tid);
+            }
+        }
+
+        impl std::convert::From<$name> for std::net::SocketAddr {
+            fn from(a: $name) -> Self {
+                a.0.into()
+            }
+        }
+
+        impl AttributeExt for $name {
+            const TYPE: u16 = $ty;
+
+            fn encode(&self) -> Vec<u8> {
+                self.0.encode()
+            }
+
+            fn decode(data: Vec<u8>, tid: [u8; 12]) -> Result<Self> {
+                XorMappedAddress::decode(data, tid).map(Self)
+            }
+
+            fn value_len(&self) -> usize {
+                self.0.value_len()
+            }
+        }
+    };
+}
+
+xor_address_attribute!(
+    /// The address of a peer as seen from the TURN server.
+    ///
+    /// # Reference
+    /// [RFC 8656]
+    ///
+    /// [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656#section-18.3
+    XorPeerAddress,
+    0x0012
+);
+
+xor_address_attribute!(
+    /// The address the TURN server allocated for the client.
+    ///
+    /// # Reference
+    /// [RFC 8656]
+    ///
+    /// [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656#section-18.5
+    XorRelayedAddress,
+    0x0016
+);
+
+u32_attribute!(
+    /// Asks the server to send the response from a different IP address and/or
+    /// port.
+    ///
+    /// # Reference
+    /// [RFC 5780]
+    ///
+    /// [RFC 5780]: https://datatracker.ietf.org/doc/html/rfc5780#section-7.2
+    ChangeRequest,
+    0x0003
+);
+
+impl ChangeRequest {
+    const CHANGE_IP: u32 = 0x4;
+    const CHANGE_PORT: u32 = 0x2;
+
+    pub(crate) fn new(change_ip: bool, change_port: bool) -> Self {
+        let mut flags = 0;
+        if change_ip {
+            flags |= Self::CHANGE_IP;
+        }
+        if change_port {
+            flags |= Self::CHANGE_PORT;
+        }
+        Self(flags)
+    }
+
+    pub(crate) fn change_ip(&self) -> bool {
+        self.0 & Self::CHANGE_IP != 0
+    }
+
+    pub(crate) fn change_port(&self) -> bool {
+        self.0 & Self::CHANGE_PORT != 0
+    }
+}
+
+/// The address of the client as seen by the server, without the obfuscation
+/// of a [`XorMappedAddress`].
+///
+/// Servers only send it for backwards compatibility with [RFC 3489] clients.
+///
+/// # Reference
+/// [RFC 8489]
+///
+/// [RFC 3489]: https://datatracker.ietf.org/doc/html/rfc3489
+/// [RFC 8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.1
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub(crate) struct MappedAddress(net::SocketAddr);
+
+impl MappedAddress {
+    pub(crate) fn new(address: net::SocketAddr) -> Self {
+        Self(address)
+    }
+}
+
+impl std::convert::From<MappedAddress> for std::net::SocketAddr {
+    fn from(a: MappedAddress) -> Self {
+        a.0
+    }
+}
+
+impl AttributeExt for MappedAddress {
+    const TYPE: u16 = 0x0001;
+
+    fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.value_len());
+        match self.0.ip() {
+            net::IpAddr::V4(a) => {
+                result.extend_from_slice(&1u16.to_be_bytes());
+                result.extend_from_slice(&self.0.port().to_be_bytes());
+                result.extend_from_slice(&a.octets());
+            }
+            net::IpAddr::V6(a) => {
+                result.extend_from_slice(&2u16.to_be_bytes());
+                result.extend_from_slice(&self.0.port().to_be_bytes());
+                result.extend_from_slice(&a.octets());
+            }
+        }
+        result
+    }
+
+    fn decode(data: Vec<u8>, _: [u8; 12]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(StunError::IncorrectAttributeLength);
+        }
+
+        let port = u16::from_be_bytes(<[u8; 2]>::try_from(&data[2..4]).unwrap());
+        let ip = match u16::from_be_bytes(<[u8; 2]>::try_from(&data[0..2]).unwrap()) {
+            1 => <[u8; 4]>::try_from(&data[4..])
+                .map(net::IpAddr::from)
+                .map_err(|_| StunError::InvalidAddress)?,
+            2 => <[u8; 16]>::try_from(&data[4..])
+                .map(net::IpAddr::from)
+                .map_err(|_| StunError::InvalidAddress)?,
+            _ => return Err(StunError::InvalidAddressFamily),
+        };
+        Ok(Self(net::SocketAddr::new(ip, port)))
+    }
+
+    fn value_len(&self) -> usize {
+        match self.0 {
+            // 2 (family) + 2 (port) + 4 (address)
+            net::SocketAddr::V4(_) => 8,
+            // 2 (family) + 2 (port) + 16 (address)
+            net::SocketAddr::V6(_) => 20,
+        }
+    }
+}
+
+/// Defines an attribute encoded in the same way as a [`MappedAddress`].
+macro_rules! address_attribute {
+    ($(#[$meta:meta])* $name:ident, $ty:literal) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+        pub(crate) struct $name(MappedAddress);
+
+        impl $name {
+            pub(crate) fn new(address: net::SocketAddr) -> Self {
+                Self(MappedAddress::new(address))
+            }
+        }
+
+        impl std::convert::From<$name> for std::net::SocketAddr {
+            fn from(a: $name) -> Self {
+                a.0.into()
+            }
+        }
+
+        impl AttributeExt for $name {
+            const TYPE: u16 = $ty;
+
+            fn encode(&self) -> Vec<u8> {
+                self.0.encode()
+            }
+
+            fn decode(data: Vec<u8>, tid: [u8; 12]) -> Result<Self> {
+                MappedAddress::decode(data, tid).map(Self)
+            }
+
+            fn value_len(&self) -> usize {
+                self.0.value_len()
+            }
+        }
+    };
+}
+
+address_attribute!(
+    /// The address from which the server sent the response.
+    ///
+    /// # Reference
+    /// [RFC 5780]
+    ///
+    /// [RFC 5780]: https://datatracker.ietf.org/doc/html/rfc5780#section-7.3
+    ResponseOrigin,
+    0x802B
+);
+
+address_attribute!(
+    /// The alternate address of the server, which differs from the address the
+    /// request was received on in both IP address and port.
+    ///
+    /// # Reference
+    /// [RFC 5780]
+    ///
+    /// [RFC 5780]: https://datatracker.ietf.org/doc/html/rfc5780#section-7.4
+    OtherAddress,
+    0x802C
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -546,7 +1236,7 @@ mod tests {
         );
         assert_eq!(
             Attribute::from_bytes(packet, [0; 12]).unwrap(),
-            (Attribute::XorMappedAddress(expected), 12),
+            (Some(Attribute::XorMappedAddress(expected)), 12),
         )
     }
 
@@ -584,7 +1274,118 @@ mod tests {
         );
         assert_eq!(
             Attribute::from_bytes(packet, tid).unwrap(),
-            (Attribute::XorMappedAddress(expected), 24),
+            (Some(Attribute::XorMappedAddress(expected)), 24),
+        );
+    }
+
+    #[test]
+    fn test_turn_attributes_round_trip() {
+        let tid = [7; 12];
+        let mut peer_address = XorPeerAddress::from(IpAddr::V6(net::Ipv6Addr::LOCALHOST), 3478);
+        peer_address.set_tid(tid);
+
+        let attributes = [
+            Attribute::Username(Username::try_from("user").unwrap()),
+            Attribute::ErrorCode(ErrorCode::new(ErrorCode::STALE_NONCE, "Stale Nonce")),
+            Attribute::Realm(Realm::try_from("memorage.org").unwrap()),
+            Attribute::Nonce(Nonce::try_from("f//499k954d6OL34oL9FSTvy64sA").unwrap()),
+            Attribute::ChannelNumber(ChannelNumber::new(0x4001)),
+            Attribute::Lifetime(Lifetime::new(Duration::from_secs(600))),
+            Attribute::XorPeerAddress(peer_address),
+            Attribute::Data(Data(vec![1, 2, 3, 4, 5])),
+            Attribute::XorRelayedAddress(XorRelayedAddress::from(
+                IpAddr::V4(net::Ipv4Addr::new(1, 2, 3, 4)),
+                50000,
+            )),
+            Attribute::RequestedTransport(RequestedTransport::UDP),
+            Attribute::MessageIntegrity(MessageIntegrity([9; 20])),
+            Attribute::MappedAddress(MappedAddress::new("1.2.3.4:5".parse().unwrap())),
+            Attribute::ChangeRequest(ChangeRequest::new(true, false)),
+            Attribute::ResponseOrigin(ResponseOrigin::new("[2001:db8::1]:3478".parse().unwrap())),
+            Attribute::OtherAddress(OtherAddress::new("5.6.7.8:3479".parse().unwrap())),
+            Attribute::MessageIntegritySha256(MessageIntegritySha256(vec![3; 32])),
+            Attribute::Fingerprint(Fingerprint(0xe57a3bcf)),
+        ];
+
+        for attribute in attributes {
+            let bytes = attribute.to_bytes();
+            assert_eq!(bytes.len(), attribute.len());
+            assert_eq!(bytes.len() % 4, 0);
+            assert_eq!(
+                Attribute::from_bytes(bytes, tid).unwrap(),
+                (Some(attribute.clone()), attribute.len())
+            );
+        }
+    }
+
+    #[test]
+    fn test_change_request() {
+        let change = ChangeRequest::new(true, true);
+        assert_eq!(&change.to_bytes()[4..], &[0, 0, 0, 6]);
+        assert!(change.change_ip());
+        assert!(change.change_port());
+
+        let change = ChangeRequest::new(false, true);
+        assert!(!change.change_ip());
+        assert!(change.change_port());
+    }
+
+    #[test]
+    fn test_mapped_address_encode() {
+        let address = MappedAddress::new("192.0.2.1:32853".parse().unwrap());
+        assert_eq!(
+            address.to_bytes(),
+            [0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 192, 0, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_error_code_encode() {
+        let error = ErrorCode::new(ErrorCode::UNAUTHENTICATED, "Unauthenticated");
+        let bytes = error.to_bytes();
+
+        assert_eq!(&bytes[0..4], &[0x00, 0x09, 0x00, 19]);
+        // The class is 4 and the number is 1.
+        assert_eq!(&bytes[4..8], &[0, 0, 4, 1]);
+        assert_eq!(&bytes[8..23], b"Unauthenticated");
+        assert_eq!(error.code(), 401);
+        assert_eq!(error.reason(), "Unauthenticated");
+    }
+
+    #[test]
+    fn test_turn_attribute_accessors() {
+        assert_eq!(ChannelNumber::new(0x4fff).number(), 0x4fff);
+        assert_eq!(RequestedTransport::UDP.protocol(), 17);
+        assert_eq!(
+            Lifetime::new(Duration::from_secs(3600)).duration(),
+            Duration::from_secs(3600)
+        );
+        assert!(matches!(
+            Realm::try_from(&"a".repeat(763)[..]),
+            Err(StunError::AttributeTooLarge("Realm"))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_attributes() {
+        // ALTERNATE-DOMAIN is comprehension-optional.
+        let optional = [
+            0x80, 0x03, 0x00, 0x05, b'a', b'.', b'o', b'r', b'g', 0, 0, 0,
+        ];
+        assert_eq!(
+            Attribute::from_bytes(optional.to_vec(), [0; 12]).unwrap(),
+            (None, 12)
         );
+        assert!(matches!(
+            Attribute::from_bytes(optional[..8].to_vec(), [0; 12]),
+            Err(StunError::IncorrectAttributeLength)
+        ));
+
+        // USE-CANDIDATE is comprehension-required.
+        let required = [0x00, 0x25, 0x00, 0x00];
+        assert!(matches!(
+            Attribute::from_bytes(required.to_vec(), [0; 12]),
+            Err(StunError::InvalidAttributeType)
+        ));
     }
 }
@@ -34,4 +34,22 @@ pub enum StunError {
     InvalidAddress,
     #[error("no xor-mapped address in message")]
     NoAddress,
+    #[error("missing {0} attribute")]
+    MissingAttribute(&'static str),
+    #[error("invalid message integrity")]
+    InvalidIntegrity,
+    #[error("invalid fingerprint")]
+    InvalidFingerprint,
+    #[error("error response {code}: {reason}")]
+    ErrorResponse { code: u16, reason: String },
+    #[error("unsupported request")]
+    UnsupportedRequest,
+    #[error("unexpected response")]
+    UnexpectedResponse,
+    #[error("server didn't respond")]
+    Timeout,
+    #[error("STUN servers didn't agree on an address")]
+    NoConsensus,
+    #[error("no channel numbers available")]
+    NoChannels,
 }
@@ -16,40 +16,164 @@
 mod attribute;
 mod error;
 mod message;
+mod nat;
+mod server;
+mod transaction;
+mod turn;
 
 pub use error::{Error, Result, StunError};
 pub(crate) use message::*;
+pub use nat::nat_type;
+pub use server::{binding_response, BindingRequest};
+pub use transaction::Retransmission;
+pub use turn::{Allocation, Credentials};
 
-use std::net::SocketAddr;
-use tokio::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{ToSocketAddrs, UdpSocket};
 
 pub const DEFAULT_STUN_SERVER: &str = "172.253.59.127:19302";
 
+/// The port on which STUN servers listen by default.
+pub const DEFAULT_STUN_PORT: u16 = 3478;
+
+pub(crate) fn software() -> Result<attribute::Software> {
+    Ok(attribute::Software::try_from(concat!(
+        "memorage v",
+        env!("CARGO_PKG_VERSION")
+    ))?)
+}
+
+/// Queries the STUN server for the public address of the socket, with the
+/// default [`Retransmission`].
 #[inline]
-pub async fn public_address(socket: &mut UdpSocket, addr: &str) -> crate::Result<SocketAddr> {
+pub async fn public_address<A>(socket: &mut UdpSocket, addr: A) -> crate::Result<SocketAddr>
+where
+    A: ToSocketAddrs,
+{
+    let server = tokio::net::lookup_host(addr).await?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        )
+    })?;
+    public_address_with(socket, server, &Retransmission::default()).await
+}
+
+/// Queries the STUN server for the public address of the socket.
+///
+/// Datagrams that aren't the response to the request are ignored: the
+/// response must come from the server, carry the transaction ID of the
+/// request, and have a valid fingerprint if it has one.
+pub async fn public_address_with(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    retransmission: &Retransmission,
+) -> Result<SocketAddr> {
     let mut message = Message::new(Type {
         class: Class::Request,
         method: Method::Binding,
     });
-    message.push(attribute::Attribute::Software(
-        attribute::Software::try_from(concat!("memorage v", env!("CARGO_PKG_VERSION")))?,
-    ));
+    message.push(attribute::Attribute::Software(software()?));
+    let tid = message.tid();
+    let request = message.encode_with_fingerprint();
 
-    socket.send_to(&<Vec<u8>>::from(message)[..], addr).await?;
+    let received =
+        transaction::transaction(socket, server, &request, retransmission, |data, source| {
+            Ok(response(data, tid).filter(|_| source == server))
+        })
+        .await?
+        .ok_or(StunError::Timeout)?;
 
-    let mut buf = [0u8; 32];
-    socket.recv_from(&mut buf).await?;
+    if received.ty().class != Class::Success {
+        return Err(Error::Stun(StunError::UnexpectedResponse));
+    }
+    mapped_address(&received)
+}
 
-    let received = Message::try_from(&buf[..])?;
+/// The public address agreed on by a set of STUN servers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Consensus {
+    /// The public address reported by the first of the agreeing servers.
+    pub address: SocketAddr,
+    /// The servers that reported the IP address, in the order they were
+    /// queried.
+    pub servers: Vec<SocketAddr>,
+    /// The servers that reported a different IP address, and the address each
+    /// reported.
+    pub dissenting: Vec<(SocketAddr, SocketAddr)>,
+}
 
-    for attr in received.attrs() {
-        // TODO add non xor mapped address
-        if let attribute::Attribute::XorMappedAddress(a) = attr {
-            return Ok(a.into());
+/// Queries each STUN server in turn for the public address of the socket.
+///
+/// A strict majority of the servers that respond must report the same IP
+/// address, so a single misbehaving server can't choose the address. Ports
+/// aren't compared, as NATs with address-dependent mapping use a different
+/// port for each server.
+///
+/// If no server responds, the error from the last server is returned.
+pub async fn public_address_consensus(
+    socket: &UdpSocket,
+    servers: &[SocketAddr],
+    retransmission: &Retransmission,
+) -> Result<Consensus> {
+    let mut reports = Vec::new();
+    let mut last_error = None;
+    for &server in servers {
+        match public_address_with(socket, server, retransmission).await {
+            Ok(address) => reports.push((server, address)),
+            Err(e) => last_error = Some(e),
         }
     }
+    if reports.is_empty() {
+        return Err(last_error.unwrap_or(Error::Stun(StunError::NoConsensus)));
+    }
 
-    Err(Error::Stun(StunError::NoAddress))
+    let count = |ip: IpAddr| reports.iter().filter(|(_, a)| a.ip() == ip).count();
+    let majority = reports
+        .iter()
+        .map(|(_, address)| address.ip())
+        .find(|&ip| count(ip) * 2 > reports.len())
+        .ok_or(StunError::NoConsensus)?;
+
+    let (agreeing, dissenting): (Vec<_>, Vec<_>) = reports
+        .into_iter()
+        .partition(|(_, address)| address.ip() == majority);
+    Ok(Consensus {
+        address: agreeing[0].1,
+        servers: agreeing.into_iter().map(|(server, _)| server).collect(),
+        dissenting,
+    })
+}
+
+/// Decodes the response to the transaction, returning [`None`] if the datagram
+/// isn't a response with the transaction ID and a valid fingerprint.
+pub(crate) fn response(data: &[u8], tid: [u8; 12]) -> Option<Message> {
+    let message = Message::try_from(data).ok()?;
+    let is_response = matches!(message.ty().class, Class::Success | Class::Error);
+    if is_response && message.tid() == tid && Message::check_fingerprint(data).is_ok() {
+        Some(message)
+    } else {
+        None
+    }
+}
+
+/// Returns the address of the client in a Binding response, preferring the
+/// XOR-MAPPED-ADDRESS attribute.
+pub(crate) fn mapped_address(message: &Message) -> Result<SocketAddr> {
+    let attrs = message.attrs();
+    let xor_mapped = attrs.iter().find_map(|attr| match attr {
+        attribute::Attribute::XorMappedAddress(a) => Some(SocketAddr::from(*a)),
+        _ => None,
+    });
+    let mapped = || {
+        attrs.iter().find_map(|attr| match attr {
+            attribute::Attribute::MappedAddress(a) => Some(SocketAddr::from(*a)),
+            _ => None,
+        })
+    };
+    xor_mapped
+        .or_else(mapped)
+        .ok_or(Error::Stun(StunError::NoAddress))
 }
 
 #[cfg(test)]
@@ -67,4 +191,127 @@ mod tests {
             .unwrap();
         assert_eq!(addr_1, addr_2);
     }
+
+    #[tokio::test]
+    async fn local_binding_response() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; transaction::MAX_DATAGRAM_SIZE];
+            let (len, source) = server.recv_from(&mut buf).await.unwrap();
+            let response = binding_response(&buf[..len], source).unwrap();
+            server.send_to(&response, source).await.unwrap();
+        });
+
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = public_address(&mut socket, server_address).await.unwrap();
+        assert_eq!(address, socket.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn ignore_spoofed_responses() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let fake_address = "1.2.3.4:5".parse().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; transaction::MAX_DATAGRAM_SIZE];
+            let (len, source) = server.recv_from(&mut buf).await.unwrap();
+            let request = BindingRequest::decode(&buf[..len]).unwrap();
+
+            // A response from another address.
+            let spoofed = request.response(fake_address, None, None).unwrap();
+            spoofer.send_to(&spoofed, source).await.unwrap();
+            // A response to another transaction.
+            let other = Message::new(Type {
+                class: Class::Request,
+                method: Method::Binding,
+            });
+            let other = BindingRequest::decode(&<Vec<u8>>::from(other)).unwrap();
+            let spoofed = other.response(fake_address, None, None).unwrap();
+            server.send_to(&spoofed, source).await.unwrap();
+            // A response with a corrupted fingerprint.
+            let mut spoofed = request.response(fake_address, None, None).unwrap();
+            let last = spoofed.len() - 1;
+            spoofed[last] ^= 1;
+            server.send_to(&spoofed, source).await.unwrap();
+
+            let response = request.response(source, None, None).unwrap();
+            server.send_to(&response, source).await.unwrap();
+        });
+
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = public_address(&mut socket, server_address).await.unwrap();
+        assert_eq!(address, socket.local_addr().unwrap());
+    }
+
+    /// Runs a STUN server that reports the given IP address instead of the
+    /// client's, or doesn't respond at all.
+    async fn lying_server(reported: Option<IpAddr>) -> SocketAddr {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; transaction::MAX_DATAGRAM_SIZE];
+            loop {
+                let (len, source) = server.recv_from(&mut buf).await.unwrap();
+                if let Some(ip) = reported {
+                    let request = BindingRequest::decode(&buf[..len]).unwrap();
+                    let address = SocketAddr::new(ip, source.port());
+                    let response = request.response(address, None, None).unwrap();
+                    server.send_to(&response, source).await.unwrap();
+                }
+            }
+        });
+        server_address
+    }
+
+    #[tokio::test]
+    async fn consensus() {
+        let retransmission = Retransmission {
+            timeout: std::time::Duration::from_millis(200),
+            ..Default::default()
+        };
+        let localhost = "127.0.0.1".parse().unwrap();
+        let liar = "1.2.3.4".parse().unwrap();
+        let honest_1 = lying_server(Some(localhost)).await;
+        let silent = lying_server(None).await;
+        let lying = lying_server(Some(liar)).await;
+        let honest_2 = lying_server(Some(localhost)).await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_address = socket.local_addr().unwrap();
+        let servers = [silent, honest_1, lying, honest_2];
+        let consensus = public_address_consensus(&socket, &servers, &retransmission)
+            .await
+            .unwrap();
+        assert_eq!(
+            consensus,
+            Consensus {
+                address: local_address,
+                servers: vec![honest_1, honest_2],
+                dissenting: vec![(lying, SocketAddr::new(liar, local_address.port()))],
+            }
+        );
+
+        let result = public_address_consensus(&socket, &[honest_1, lying], &retransmission).await;
+        assert!(matches!(result, Err(Error::Stun(StunError::NoConsensus))));
+
+        let result = public_address_consensus(&socket, &[silent], &retransmission).await;
+        assert!(matches!(result, Err(Error::Stun(StunError::Timeout))));
+    }
+
+    #[tokio::test]
+    async fn dual_stack_socket() {
+        let socket = match UdpSocket::bind("[::]:0").await {
+            Ok(socket) => socket,
+            // IPv6 is unavailable.
+            Err(_) => return,
+        };
+        let server = lying_server(Some("127.0.0.1".parse().unwrap())).await;
+        let address = public_address_with(&socket, server, &Retransmission::default())
+            .await
+            .unwrap();
+        assert_eq!(address.ip(), server.ip());
+        assert_eq!(address.port(), socket.local_addr().unwrap().port());
+    }
 }
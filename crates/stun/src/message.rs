@@ -1,6 +1,12 @@
-use crate::{attribute::Attribute, StunError};
+use crate::{
+    attribute::{Attribute, AttributeExt, Fingerprint, MessageIntegrity, MessageIntegritySha256},
+    StunError,
+};
 
+use hmac::{Hmac, Mac};
 use memorage_core::rand::{thread_rng, RngCore};
+use sha1::Sha1;
+use sha2::Sha256;
 
 type Result<T> = std::result::Result<T, StunError>;
 
@@ -147,6 +153,24 @@ impl Message {
         }
     }
 
+    /// Creates a message with the given transaction ID, e.g. a response to a
+    /// request.
+    pub(crate) fn with_tid(ty: Type, tid: [u8; 12]) -> Self {
+        Self {
+            tid,
+            ty,
+            attrs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn tid(&self) -> [u8; 12] {
+        self.tid
+    }
+
+    pub(crate) fn ty(&self) -> Type {
+        self.ty
+    }
+
     /// The attributes of the message.
     pub(crate) fn attrs(&self) -> Vec<Attribute> {
         self.attrs.clone()
@@ -169,15 +193,167 @@ impl Message {
     /// the first occurrence needs to be processed by a receiver, and any
     /// duplicates may be ignored by a receiver.
     pub(crate) fn push(&mut self, mut attr: Attribute) {
-        #[allow(clippy::single_match)]
-        // Certain attributes require information about the message in order to be correctly
-        // encoded.
+        // Certain attributes require information about the message in order to be
+        // correctly encoded.
         match attr {
             Attribute::XorMappedAddress(ref mut a) => a.set_tid(self.tid),
+            Attribute::XorPeerAddress(ref mut a) => a.set_tid(self.tid),
+            Attribute::XorRelayedAddress(ref mut a) => a.set_tid(self.tid),
             _ => {}
         }
         self.attrs.push(attr);
     }
+
+    /// Encodes the message followed by a [`MessageIntegrity`] attribute keyed
+    /// with the given key.
+    ///
+    /// For long-term credentials, the key is the MD5 hash of
+    /// `username:realm:password`.
+    pub(crate) fn encode_with_integrity(self, key: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = self.into();
+        set_len(&mut result, MESSAGE_INTEGRITY_LEN);
+        let integrity = MessageIntegrity(hmac_sha1(key, &result));
+        result.extend(integrity.to_bytes());
+        result
+    }
+
+    /// Encodes the message followed by a [`MessageIntegritySha256`] attribute
+    /// keyed with the given key.
+    #[allow(dead_code)]
+    pub(crate) fn encode_with_integrity_sha256(self, key: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = self.into();
+        set_len(&mut result, MESSAGE_INTEGRITY_SHA256_LEN);
+        let integrity = MessageIntegritySha256(hmac_sha256(key, &result).to_vec());
+        result.extend(integrity.to_bytes());
+        result
+    }
+
+    /// Encodes the message followed by a [`Fingerprint`] attribute.
+    pub(crate) fn encode_with_fingerprint(self) -> Vec<u8> {
+        let mut result = self.into();
+        Self::append_fingerprint(&mut result);
+        result
+    }
+
+    /// Appends a [`Fingerprint`] attribute to an encoded message, e.g. after
+    /// its message integrity.
+    pub(crate) fn append_fingerprint(data: &mut Vec<u8>) {
+        set_len(data, FINGERPRINT_LEN);
+        let fingerprint = Fingerprint(crc32fast::hash(data) ^ Fingerprint::XOR);
+        data.extend(fingerprint.to_bytes());
+    }
+
+    /// Checks the message integrity of an encoded message against the given
+    /// key.
+    ///
+    /// The [`MessageIntegritySha256`] attribute is checked if the message has
+    /// one, and the [`MessageIntegrity`] attribute otherwise.
+    pub(crate) fn check_integrity(data: &[u8], key: &[u8]) -> Result<()> {
+        let (offset, sha256) = match attribute_offset(data, MessageIntegritySha256::TYPE) {
+            Some(offset) => (offset, true),
+            None => (
+                attribute_offset(data, MessageIntegrity::TYPE)
+                    .ok_or(StunError::MissingAttribute("MESSAGE-INTEGRITY"))?,
+                false,
+            ),
+        };
+        let len = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let valid_len = if sha256 {
+            (16..=32).contains(&len) && len.is_multiple_of(4)
+        } else {
+            len == 20
+        };
+        let integrity = data
+            .get(offset + 4..offset + 4 + len)
+            .filter(|_| valid_len)
+            .ok_or(StunError::IncorrectAttributeLength)?;
+
+        // Attributes following the message integrity attribute are excluded from
+        // the length in the header.
+        let mut header = [0; 20];
+        header.copy_from_slice(&data[..20]);
+        header[2..4].copy_from_slice(&((offset - 20 + 4 + len) as u16).to_be_bytes());
+
+        let result = if sha256 {
+            let mut mac =
+                <Hmac<Sha256>>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(&header);
+            mac.update(&data[20..offset]);
+            mac.verify_truncated_left(integrity)
+        } else {
+            let mut mac =
+                <Hmac<Sha1>>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(&header);
+            mac.update(&data[20..offset]);
+            mac.verify_slice(integrity)
+        };
+        result.map_err(|_| StunError::InvalidIntegrity)
+    }
+
+    /// Checks the [`Fingerprint`] attribute of an encoded message, if it has
+    /// one.
+    pub(crate) fn check_fingerprint(data: &[u8]) -> Result<()> {
+        let offset = match attribute_offset(data, Fingerprint::TYPE) {
+            Some(offset) => offset,
+            None => return Ok(()),
+        };
+        // The fingerprint must be the last attribute.
+        if offset + FINGERPRINT_LEN != data.len() {
+            return Err(StunError::InvalidFingerprint);
+        }
+
+        let fingerprint = u32::from_be_bytes(<[u8; 4]>::try_from(&data[offset + 4..]).unwrap());
+        if crc32fast::hash(&data[..offset]) ^ Fingerprint::XOR == fingerprint {
+            Ok(())
+        } else {
+            Err(StunError::InvalidFingerprint)
+        }
+    }
+}
+
+/// The length of an encoded [`MessageIntegrity`] attribute, including its
+/// header.
+const MESSAGE_INTEGRITY_LEN: usize = 24;
+
+/// The length of an encoded [`MessageIntegritySha256`] attribute, including
+/// its header.
+const MESSAGE_INTEGRITY_SHA256_LEN: usize = 36;
+
+/// The length of an encoded [`Fingerprint`] attribute, including its header.
+const FINGERPRINT_LEN: usize = 8;
+
+/// Sets the length in the header of an encoded message to include an
+/// attribute of the given length that is about to be appended.
+fn set_len(data: &mut [u8], attribute_len: usize) {
+    let len = (data.len() - 20 + attribute_len) as u16;
+    data[2..4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    let mut mac = <Hmac<Sha1>>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256>>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Returns the offset of the first attribute of the given type in an encoded
+/// message.
+fn attribute_offset(data: &[u8], ty: u16) -> Option<usize> {
+    let mut offset = 20;
+    while offset + 4 <= data.len() {
+        let attr_ty = u16::from_be_bytes([data[offset], data[offset + 1]]);
+        if attr_ty == ty {
+            return Some(offset);
+        }
+        let len = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        offset += 4 + len + (4 - len % 4) % 4;
+    }
+    None
 }
 
 impl std::convert::From<Message> for Vec<u8> {
@@ -233,9 +409,9 @@ impl std::convert::TryFrom<&[u8]> for Message {
 
         while attr_start_index < value.len() {
             let data_remainder = &value[(attr_start_index)..(value.len())];
-            let attr_result = Attribute::from_bytes(data_remainder.to_vec(), tid)?;
-            attrs.push(attr_result.0);
-            attr_start_index += attr_result.1;
+            let (attr, len) = Attribute::from_bytes(data_remainder.to_vec(), tid)?;
+            attrs.extend(attr);
+            attr_start_index += len;
         }
 
         Ok(Self { ty, tid, attrs })
@@ -430,4 +606,164 @@ mod tests {
         // Should result in an error as the first 2 bits aren't 0.
         assert!(matches!(Type::try_from(0xFF), Err(StunError::InvalidType)));
     }
+
+    /// The sample request with long-term authentication from [RFC 5769].
+    ///
+    /// [RFC 5769]: https://datatracker.ietf.org/doc/html/rfc5769#section-2.4
+    const LONG_TERM_REQUEST: [u8; 116] = [
+        0x00, 0x01, 0x00, 0x60, 0x21, 0x12, 0xa4, 0x42, 0x78, 0xad, 0x34, 0x33, 0xc6, 0xad, 0x72,
+        0xc0, 0x29, 0xda, 0x41, 0x2e, 0x00, 0x06, 0x00, 0x12, 0xe3, 0x83, 0x9e, 0xe3, 0x83, 0x88,
+        0xe3, 0x83, 0xaa, 0xe3, 0x83, 0x83, 0xe3, 0x82, 0xaf, 0xe3, 0x82, 0xb9, 0x00, 0x00, 0x00,
+        0x15, 0x00, 0x1c, 0x66, 0x2f, 0x2f, 0x34, 0x39, 0x39, 0x6b, 0x39, 0x35, 0x34, 0x64, 0x36,
+        0x4f, 0x4c, 0x33, 0x34, 0x6f, 0x4c, 0x39, 0x46, 0x53, 0x54, 0x76, 0x79, 0x36, 0x34, 0x73,
+        0x41, 0x00, 0x14, 0x00, 0x0b, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x6f, 0x72,
+        0x67, 0x00, 0x00, 0x08, 0x00, 0x14, 0xf6, 0x70, 0x24, 0x65, 0x6d, 0xd6, 0x4a, 0x3e, 0x02,
+        0xb8, 0xe0, 0x71, 0x2e, 0x85, 0xc9, 0xa2, 0x8c, 0xa8, 0x96, 0x66,
+    ];
+
+    #[test]
+    fn test_long_term_integrity() {
+        use crate::{
+            attribute::{Nonce, Realm, Username},
+            turn::long_term_key,
+        };
+
+        let username = "\u{30DE}\u{30C8}\u{30EA}\u{30C3}\u{30AF}\u{30B9}";
+        // The password after being processed with the OpaqueString profile.
+        let key = long_term_key(username, "example.org", "TheMatrIX");
+
+        assert!(Message::check_integrity(&LONG_TERM_REQUEST, &key).is_ok());
+        assert!(matches!(
+            Message::check_integrity(&LONG_TERM_REQUEST, &[0; 16]),
+            Err(StunError::InvalidIntegrity)
+        ));
+
+        let decoded = Message::try_from(&LONG_TERM_REQUEST[..]).unwrap();
+        let mut message = Message::with_tid(decoded.ty(), decoded.tid());
+        message.push(Attribute::Username(Username::try_from(username).unwrap()));
+        message.push(Attribute::Nonce(
+            Nonce::try_from("f//499k954d6OL34oL9FSTvy64sA").unwrap(),
+        ));
+        message.push(Attribute::Realm(Realm::try_from("example.org").unwrap()));
+        assert_eq!(message.encode_with_integrity(&key), LONG_TERM_REQUEST);
+    }
+
+    #[test]
+    fn test_integrity_ignores_later_attributes() {
+        let mut message = Message::new(Type {
+            class: Class::Request,
+            method: Method::Allocate,
+        });
+        message.push(Attribute::Software(Software::try_from("memorage").unwrap()));
+        let mut bytes = message.encode_with_integrity(b"key");
+
+        // Attributes following the message integrity attribute aren't covered
+        // by it.
+        bytes.extend(Attribute::Software(Software::try_from("later").unwrap()).to_bytes());
+        let len = (bytes.len() - 20) as u16;
+        bytes[2..4].copy_from_slice(&len.to_be_bytes());
+
+        assert!(Message::check_integrity(&bytes, b"key").is_ok());
+        bytes[24] ^= 1;
+        assert!(matches!(
+            Message::check_integrity(&bytes, b"key"),
+            Err(StunError::InvalidIntegrity)
+        ));
+    }
+
+    /// The sample IPv4 response from [RFC 5769], which uses short-term
+    /// credentials with the password `VOkJxbRl1RmTxUk/WvJxBt`.
+    ///
+    /// [RFC 5769]: https://datatracker.ietf.org/doc/html/rfc5769#section-2.2
+    const IPV4_RESPONSE: [u8; 80] = [
+        0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6,
+        0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76,
+        0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1,
+        0x12, 0xa6, 0x43, 0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99, 0xfd, 0x9e, 0x90, 0xc3,
+        0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b, 0xe7, 0xd7, 0x80, 0x28, 0x00,
+        0x04, 0xc0, 0x7d, 0x4c, 0x96,
+    ];
+
+    #[test]
+    fn test_fingerprint() {
+        let key = b"VOkJxbRl1RmTxUk/WvJxBt";
+        assert!(Message::check_fingerprint(&IPV4_RESPONSE).is_ok());
+        assert!(Message::check_integrity(&IPV4_RESPONSE, key).is_ok());
+
+        let mut corrupted = IPV4_RESPONSE;
+        corrupted[30] ^= 1;
+        assert!(matches!(
+            Message::check_fingerprint(&corrupted),
+            Err(StunError::InvalidFingerprint)
+        ));
+
+        let response = Message::try_from(&IPV4_RESPONSE[..]).unwrap();
+        assert_eq!(
+            crate::mapped_address(&response).unwrap(),
+            "192.0.2.1:32853".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_with_fingerprint() {
+        let message = Message::new(Type {
+            class: Class::Request,
+            method: Method::Binding,
+        });
+        let mut bytes = message.encode_with_fingerprint();
+        assert!(Message::check_fingerprint(&bytes).is_ok());
+        assert!(Message::try_from(&bytes[..]).is_ok());
+
+        bytes[8] ^= 1;
+        assert!(matches!(
+            Message::check_fingerprint(&bytes),
+            Err(StunError::InvalidFingerprint)
+        ));
+    }
+
+    #[test]
+    fn test_skip_unknown_optional_attributes() {
+        let software = Attribute::Software(Software::try_from("memorage").unwrap());
+        let mut message = Message::new(Type {
+            class: Class::Success,
+            method: Method::Binding,
+        });
+        message.push(software.clone());
+        let mut bytes = <Vec<u8>>::from(message);
+
+        // An unknown comprehension-optional attribute.
+        let unknown = [0x80, 0xff, 0x00, 0x02, 1, 2, 0, 0];
+        set_len(&mut bytes, unknown.len());
+        bytes.extend_from_slice(&unknown);
+        let message = Message::try_from(&bytes[..]).unwrap();
+        assert_eq!(message.attrs(), vec![software]);
+
+        // An unknown comprehension-required attribute.
+        let unknown = [0x7f, 0xff, 0x00, 0x00];
+        set_len(&mut bytes, unknown.len());
+        bytes.extend_from_slice(&unknown);
+        assert!(matches!(
+            Message::try_from(&bytes[..]),
+            Err(StunError::InvalidAttributeType)
+        ));
+    }
+
+    #[test]
+    fn test_integrity_sha256() {
+        let mut message = Message::new(Type {
+            class: Class::Request,
+            method: Method::Binding,
+        });
+        message.push(Attribute::Software(Software::try_from("memorage").unwrap()));
+        let mut bytes = message.encode_with_integrity_sha256(b"key");
+        Message::append_fingerprint(&mut bytes);
+
+        assert!(Message::try_from(&bytes[..]).is_ok());
+        assert!(Message::check_fingerprint(&bytes).is_ok());
+        assert!(Message::check_integrity(&bytes, b"key").is_ok());
+        assert!(matches!(
+            Message::check_integrity(&bytes, b"other key"),
+            Err(StunError::InvalidIntegrity)
+        ));
+    }
 }
@@ -0,0 +1,239 @@
+use crate::{
+    attribute::{Attribute, ChangeRequest},
+    transaction::{transaction, Retransmission},
+    Class, Error, Message, Method, Result, StunError, Type,
+};
+
+use std::{net::SocketAddr, time::Duration};
+
+use memorage_core::{Behaviour, NatType};
+use tokio::net::UdpSocket;
+
+/// How test requests are retransmitted before concluding that no response
+/// will arrive.
+///
+/// This gives up far sooner than the default, as some tests are expected to
+/// go unanswered.
+const TEST_RETRANSMISSION: Retransmission = Retransmission {
+    rto: Duration::from_millis(250),
+    transmissions: 3,
+    last_wait: 2,
+    timeout: Duration::from_millis(1250),
+};
+
+/// Discovers the behaviour of the NAT in front of the socket, as described in
+/// [RFC 5780].
+///
+/// The server must support behaviour discovery, which requires it to have two
+/// IP addresses.
+///
+/// [RFC 5780]: https://datatracker.ietf.org/doc/html/rfc5780#section-4
+pub async fn nat_type(socket: &UdpSocket, server: SocketAddr) -> Result<NatType> {
+    let (response, _) = binding(socket, server, None)
+        .await?
+        .ok_or(StunError::Timeout)?;
+    let mapped_1 = crate::mapped_address(&response)?;
+    let other = response
+        .attrs()
+        .into_iter()
+        .find_map(|attr| match attr {
+            Attribute::OtherAddress(a) => Some(SocketAddr::from(a)),
+            _ => None,
+        })
+        .ok_or(StunError::MissingAttribute("OTHER-ADDRESS"))?;
+    if other.ip() == server.ip() || other.port() == server.port() {
+        return Err(Error::Stun(StunError::UnexpectedResponse));
+    }
+
+    // The filtering tests run first, as sending to the alternate address in
+    // the mapping tests would open the NAT to it. Tests II and III ask for
+    // responses from the alternate IP address and port, and then from the
+    // alternate port only.
+    let filtering = if binding(socket, server, Some(ChangeRequest::new(true, true)))
+        .await?
+        .is_some()
+    {
+        Behaviour::EndpointIndependent
+    } else if binding(socket, server, Some(ChangeRequest::new(false, true)))
+        .await?
+        .is_some()
+    {
+        Behaviour::AddressDependent
+    } else {
+        Behaviour::AddressAndPortDependent
+    };
+
+    // Mapping tests II and III send to the alternate IP address, first with
+    // the primary port and then with the alternate port.
+    let mapped_2 = mapped(socket, SocketAddr::new(other.ip(), server.port())).await?;
+    let mapping = if mapped_2 == mapped_1 {
+        Behaviour::EndpointIndependent
+    } else if mapped(socket, other).await? == mapped_2 {
+        Behaviour::AddressDependent
+    } else {
+        Behaviour::AddressAndPortDependent
+    };
+
+    Ok(NatType { mapping, filtering })
+}
+
+/// Returns the mapped address of the socket when sending to `destination`.
+async fn mapped(socket: &UdpSocket, destination: SocketAddr) -> Result<SocketAddr> {
+    let (response, _) = binding(socket, destination, None)
+        .await?
+        .ok_or(StunError::Timeout)?;
+    crate::mapped_address(&response)
+}
+
+/// Sends a Binding request, returning the success response and its source, or
+/// [`None`] if no response arrives.
+async fn binding(
+    socket: &UdpSocket,
+    destination: SocketAddr,
+    change: Option<ChangeRequest>,
+) -> Result<Option<(Message, SocketAddr)>> {
+    let mut request = Message::new(Type {
+        class: Class::Request,
+        method: Method::Binding,
+    });
+    if let Some(change) = change {
+        request.push(Attribute::ChangeRequest(change));
+    }
+    let tid = request.tid();
+    let request = request.encode_with_fingerprint();
+    let change_ip = change.is_some_and(|c| c.change_ip());
+    let change_port = change.is_some_and(|c| c.change_port());
+
+    transaction(
+        socket,
+        destination,
+        &request,
+        &TEST_RETRANSMISSION,
+        |data, source| {
+            // The response must come from the address the request asked for,
+            // which also rejects servers that ignore CHANGE-REQUEST, as they
+            // would make the filtering look endpoint-independent.
+            let expected_source = (source.ip() != destination.ip()) == change_ip
+                && (source.port() != destination.port()) == change_port;
+            if !expected_source {
+                return Ok(None);
+            }
+            // Responses to earlier tests may still arrive.
+            match crate::response(data, tid) {
+                Some(response) if response.ty().class != Class::Success => {
+                    Err(Error::Stun(StunError::UnexpectedResponse))
+                }
+                Some(response) => Ok(Some((response, source))),
+                None => Ok(None),
+            }
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BindingRequest;
+
+    /// Runs a behaviour discovery server on two loopback addresses, each with
+    /// two ports, returning the primary address.
+    ///
+    /// The server emulates a NAT in front of the client with the given
+    /// behaviour, by changing the reported address and dropping responses.
+    async fn server(nat: NatType) -> SocketAddr {
+        // The same pair of ports is used on both IP addresses.
+        let primary = [
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        ];
+        let ports = [0, 1].map(|i| primary[i].local_addr().unwrap().port());
+        let alternate = [
+            UdpSocket::bind(("127.0.0.2", ports[0])).await.unwrap(),
+            UdpSocket::bind(("127.0.0.2", ports[1])).await.unwrap(),
+        ];
+        let sockets = vec![primary, alternate];
+        let sockets = std::sync::Arc::new(sockets);
+        let primary = sockets[0][0].local_addr().unwrap();
+
+        for ip in 0..2 {
+            for port in 0..2 {
+                let sockets = sockets.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0; crate::transaction::MAX_DATAGRAM_SIZE];
+                    loop {
+                        let (len, client) = sockets[ip][port].recv_from(&mut buf).await.unwrap();
+                        let mut source = client;
+                        source.set_port(match nat.mapping {
+                            Behaviour::EndpointIndependent => source.port(),
+                            Behaviour::AddressDependent => source.port() + ip as u16,
+                            Behaviour::AddressAndPortDependent => {
+                                source.port() + 2 * ip as u16 + port as u16
+                            }
+                        });
+
+                        let request = BindingRequest::decode(&buf[..len]).unwrap();
+                        let (response_ip, response_port) = (
+                            ip ^ request.change_ip() as usize,
+                            port ^ request.change_port() as usize,
+                        );
+                        let allowed = match nat.filtering {
+                            Behaviour::EndpointIndependent => true,
+                            Behaviour::AddressDependent => response_ip == ip,
+                            Behaviour::AddressAndPortDependent => {
+                                (response_ip, response_port) == (ip, port)
+                            }
+                        };
+                        if !allowed {
+                            continue;
+                        }
+
+                        let socket = &sockets[response_ip][response_port];
+                        let origin = socket.local_addr().unwrap();
+                        let other = sockets[1 - ip][1 - port].local_addr().unwrap();
+                        let response = request.response(source, Some(origin), Some(other)).unwrap();
+                        socket.send_to(&response, client).await.unwrap();
+                    }
+                });
+            }
+        }
+
+        primary
+    }
+
+    #[tokio::test]
+    async fn classify() {
+        let behaviours = [
+            Behaviour::EndpointIndependent,
+            Behaviour::AddressDependent,
+            Behaviour::AddressAndPortDependent,
+        ];
+        for (mapping, filtering) in [(0, 0), (1, 2), (2, 1), (2, 2)] {
+            let nat = NatType {
+                mapping: behaviours[mapping],
+                filtering: behaviours[filtering],
+            };
+            let server = server(nat).await;
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            assert_eq!(nat_type(&socket, server).await.unwrap(), nat);
+        }
+    }
+
+    #[tokio::test]
+    async fn no_other_address() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; crate::transaction::MAX_DATAGRAM_SIZE];
+            let (len, source) = server.recv_from(&mut buf).await.unwrap();
+            let response = crate::binding_response(&buf[..len], source).unwrap();
+            server.send_to(&response, source).await.unwrap();
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        assert!(matches!(
+            nat_type(&socket, server_address).await,
+            Err(Error::Stun(StunError::MissingAttribute("OTHER-ADDRESS")))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{
+    attribute::{Attribute, OtherAddress, ResponseOrigin, XorMappedAddress},
+    Class, Error, Message, Method, Result, StunError, Type,
+};
+
+use std::net::SocketAddr;
+
+/// A Binding request received by a STUN server.
+#[derive(Copy, Clone, Debug)]
+pub struct BindingRequest {
+    tid: [u8; 12],
+    change_ip: bool,
+    change_port: bool,
+}
+
+impl BindingRequest {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let request = Message::try_from(data)?;
+        Message::check_fingerprint(data)?;
+        let binding_request = Type {
+            class: Class::Request,
+            method: Method::Binding,
+        };
+        if request.ty() != binding_request {
+            return Err(Error::Stun(StunError::UnsupportedRequest));
+        }
+
+        let change = request.attrs().into_iter().find_map(|attr| match attr {
+            Attribute::ChangeRequest(change) => Some(change),
+            _ => None,
+        });
+        Ok(Self {
+            tid: request.tid(),
+            change_ip: change.is_some_and(|change| change.change_ip()),
+            change_port: change.is_some_and(|change| change.change_port()),
+        })
+    }
+
+    /// Whether the client asked for the response to be sent from the other IP
+    /// address of the server.
+    pub fn change_ip(&self) -> bool {
+        self.change_ip
+    }
+
+    /// Whether the client asked for the response to be sent from the other
+    /// port of the server.
+    pub fn change_port(&self) -> bool {
+        self.change_port
+    }
+
+    /// Encodes the success response to the request, which was received from
+    /// `source`.
+    ///
+    /// Servers supporting behaviour discovery ([RFC 5780]) pass the address
+    /// the response is sent from as `origin`, and their alternate address as
+    /// `other`.
+    ///
+    /// [RFC 5780]: https://datatracker.ietf.org/doc/html/rfc5780
+    pub fn response(
+        &self,
+        source: SocketAddr,
+        origin: Option<SocketAddr>,
+        other: Option<SocketAddr>,
+    ) -> Result<Vec<u8>> {
+        let mut response = Message::with_tid(
+            Type {
+                class: Class::Success,
+                method: Method::Binding,
+            },
+            self.tid,
+        );
+        response.push(Attribute::XorMappedAddress(XorMappedAddress::from(
+            source.ip(),
+            source.port(),
+        )));
+        if let Some(origin) = origin {
+            response.push(Attribute::ResponseOrigin(ResponseOrigin::new(origin)));
+        }
+        if let Some(other) = other {
+            response.push(Attribute::OtherAddress(OtherAddress::new(other)));
+        }
+        response.push(Attribute::Software(crate::software()?));
+        Ok(response.encode_with_fingerprint())
+    }
+}
+
+/// Answers a STUN Binding request received from `source`, returning the
+/// encoded success response.
+///
+/// The response tells the client the address from which its request was
+/// received. Requests asking for the response to be sent from another address
+/// are rejected, as the server only has one.
+pub fn binding_response(request: &[u8], source: SocketAddr) -> Result<Vec<u8>> {
+    let request = BindingRequest::decode(request)?;
+    if request.change_ip() || request.change_port() {
+        return Err(Error::Stun(StunError::UnsupportedRequest));
+    }
+    request.response(source, None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::ChangeRequest;
+
+    fn binding_request(change: Option<ChangeRequest>) -> Vec<u8> {
+        let mut request = Message::with_tid(
+            Type {
+                class: Class::Request,
+                method: Method::Binding,
+            },
+            [7; 12],
+        );
+        if let Some(change) = change {
+            request.push(Attribute::ChangeRequest(change));
+        }
+        request.into()
+    }
+
+    #[test]
+    fn binding_response_ipv6() {
+        let source = "[2001:db8::1]:4000".parse().unwrap();
+        let response = binding_response(&binding_request(None), source).unwrap();
+
+        let response = Message::try_from(&response[..]).unwrap();
+        assert_eq!(response.tid(), [7; 12]);
+        assert_eq!(response.ty().class, Class::Success);
+        assert_eq!(crate::mapped_address(&response).unwrap(), source);
+    }
+
+    #[test]
+    fn binding_response_rejects_responses() {
+        let message = Message::new(Type {
+            class: Class::Success,
+            method: Method::Binding,
+        });
+        let source = "1.2.3.4:5".parse().unwrap();
+        assert!(matches!(
+            binding_response(&<Vec<u8>>::from(message), source),
+            Err(Error::Stun(StunError::UnsupportedRequest))
+        ));
+    }
+
+    #[test]
+    fn binding_response_fingerprint() {
+        let source = "1.2.3.4:5".parse().unwrap();
+        let response = binding_response(&binding_request(None), source).unwrap();
+        assert!(crate::response(&response, [7; 12]).is_some());
+
+        let mut request = Message::new(Type {
+            class: Class::Request,
+            method: Method::Binding,
+        })
+        .encode_with_fingerprint();
+        assert!(binding_response(&request, source).is_ok());
+        let last = request.len() - 1;
+        request[last] ^= 1;
+        assert!(matches!(
+            binding_response(&request, source),
+            Err(Error::Stun(StunError::InvalidFingerprint))
+        ));
+    }
+
+    #[test]
+    fn binding_response_rejects_change_requests() {
+        let request = binding_request(Some(ChangeRequest::new(false, true)));
+        let source = "1.2.3.4:5".parse().unwrap();
+        assert!(matches!(
+            binding_response(&request, source),
+            Err(Error::Stun(StunError::UnsupportedRequest))
+        ));
+    }
+
+    #[test]
+    fn behaviour_discovery_response() {
+        let request =
+            BindingRequest::decode(&binding_request(Some(ChangeRequest::new(true, false))))
+                .unwrap();
+        assert!(request.change_ip());
+        assert!(!request.change_port());
+
+        let source: SocketAddr = "1.2.3.4:5".parse().unwrap();
+        let origin: SocketAddr = "192.0.2.2:3478".parse().unwrap();
+        let other: SocketAddr = "192.0.2.2:3479".parse().unwrap();
+        let response = request.response(source, Some(origin), Some(other)).unwrap();
+
+        let response = Message::try_from(&response[..]).unwrap();
+        let attrs = response.attrs();
+        assert!(attrs.contains(&Attribute::ResponseOrigin(ResponseOrigin::new(origin))));
+        assert!(attrs.contains(&Attribute::OtherAddress(OtherAddress::new(other))));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::Result;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time::Instant};
+
+/// The size of the buffer that responses are received into, which fits any
+/// UDP datagram.
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// How a request is retransmitted while waiting for its response, as
+/// described in [RFC 8489 section 6.2.1].
+///
+/// [RFC 8489 section 6.2.1]: https://datatracker.ietf.org/doc/html/rfc8489#section-6.2.1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retransmission {
+    /// The initial retransmission timeout, which doubles after every
+    /// transmission.
+    pub rto: Duration,
+    /// The number of times the request is sent.
+    pub transmissions: u32,
+    /// How many multiples of the initial retransmission timeout to wait for a
+    /// response after the last transmission.
+    pub last_wait: u32,
+    /// How long to wait for a response overall, even if transmissions remain.
+    pub timeout: Duration,
+}
+
+impl Default for Retransmission {
+    /// The values recommended by RFC 8489, which give up after 39.5 seconds.
+    fn default() -> Self {
+        Self {
+            rto: Duration::from_millis(500),
+            transmissions: 7,
+            last_wait: 16,
+            timeout: Duration::from_millis(39_500),
+        }
+    }
+}
+
+/// Sends the request to the destination until `accept` returns a value for a
+/// received datagram, returning [`None`] if no datagram is accepted before the
+/// retransmissions run out.
+///
+/// `accept` is given every datagram received on the socket, along with its
+/// source. Dual-stack sockets are supported: IPv4 sources are never given as
+/// IPv4-mapped IPv6 addresses.
+pub(crate) async fn transaction<T, F>(
+    socket: &UdpSocket,
+    destination: SocketAddr,
+    request: &[u8],
+    retransmission: &Retransmission,
+    mut accept: F,
+) -> Result<Option<T>>
+where
+    F: FnMut(&[u8], SocketAddr) -> Result<Option<T>>,
+{
+    let timeout = Instant::now() + retransmission.timeout;
+    let destination = socket_address(socket, destination)?;
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let mut rto = retransmission.rto;
+
+    for transmission in 1..=retransmission.transmissions {
+        socket.send_to(request, destination).await?;
+        let wait = if transmission == retransmission.transmissions {
+            retransmission.rto.saturating_mul(retransmission.last_wait)
+        } else {
+            rto
+        };
+        rto = rto.saturating_mul(2);
+        let deadline = (Instant::now() + wait).min(timeout);
+
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await
+        {
+            let (len, source) = received?;
+            let source = SocketAddr::new(source.ip().to_canonical(), source.port());
+            if let Some(value) = accept(&buf[..len], source)? {
+                return Ok(Some(value));
+            }
+        }
+        if deadline == timeout {
+            break;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Converts an IPv4 address to an IPv4-mapped IPv6 address if the socket is an
+/// IPv6 socket, as not every platform accepts IPv4 destinations on dual-stack
+/// sockets.
+pub(crate) fn socket_address(socket: &UdpSocket, address: SocketAddr) -> Result<SocketAddr> {
+    Ok(match address.ip() {
+        IpAddr::V4(ip) if socket.local_addr()?.is_ipv6() => {
+            SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), address.port())
+        }
+        _ => address,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RETRANSMISSION: Retransmission = Retransmission {
+        rto: Duration::from_millis(50),
+        transmissions: 4,
+        last_wait: 2,
+        timeout: Duration::from_secs(5),
+    };
+
+    #[tokio::test]
+    async fn retransmit_lost_requests() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 16];
+            // The first three requests are lost.
+            for _ in 0..3 {
+                server.recv_from(&mut buf).await.unwrap();
+            }
+            let (len, source) = server.recv_from(&mut buf).await.unwrap();
+            server.send_to(&buf[..len], source).await.unwrap();
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let result = transaction(
+            &socket,
+            server_address,
+            b"ping",
+            &RETRANSMISSION,
+            |data, _| Ok((data == b"ping").then_some(())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, Some(()));
+    }
+
+    #[tokio::test]
+    async fn give_up() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let start = Instant::now();
+        let result: Option<()> =
+            transaction(&socket, server_address, b"ping", &RETRANSMISSION, |_, _| {
+                Ok(None)
+            })
+            .await
+            .unwrap();
+        assert_eq!(result, None);
+        // 50 + 100 + 200 + 100 milliseconds.
+        assert!(start.elapsed() >= Duration::from_millis(450));
+
+        let mut buf = [0; 16];
+        for _ in 0..RETRANSMISSION.transmissions {
+            let (len, _) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"ping");
+        }
+
+        let timeout = Retransmission {
+            timeout: Duration::from_millis(100),
+            ..RETRANSMISSION
+        };
+        let start = Instant::now();
+        let result: Option<()> =
+            transaction(&socket, server_address, b"ping", &timeout, |_, _| Ok(None))
+                .await
+                .unwrap();
+        assert_eq!(result, None);
+        assert!(start.elapsed() < Duration::from_millis(450));
+    }
+}
@@ -0,0 +1,694 @@
+//! A TURN client, as specified in [RFC 8656].
+//!
+//! Only UDP allocations authenticated with long-term credentials are
+//! supported.
+//!
+//! [RFC 8656]: https://datatracker.ietf.org/doc/html/rfc8656
+
+use crate::{
+    attribute::{
+        Attribute, ChannelNumber, Data, ErrorCode, Lifetime, Nonce, Realm, RequestedTransport,
+        Username, XorPeerAddress,
+    },
+    transaction::{socket_address, transaction, Retransmission, MAX_DATAGRAM_SIZE},
+    Class, Message, Method, Result, StunError, Type,
+};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    time::Duration,
+};
+
+use md5::{Digest, Md5};
+use tokio::net::UdpSocket;
+
+/// The channel numbers a client can bind.
+const CHANNEL_NUMBERS: RangeInclusive<u16> = 0x4000..=0x4FFF;
+
+/// Long-term credentials for a TURN server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Computes the key used for message integrity with long-term credentials.
+///
+/// The password should already have been processed with the OpaqueString
+/// profile, which is a no-op for ASCII passwords.
+pub(crate) fn long_term_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+    let mut hasher = Md5::new();
+    hasher.update(format!("{username}:{realm}:{password}"));
+    hasher.finalize().into()
+}
+
+/// An allocation on a TURN server, through which the client can exchange
+/// datagrams with peers.
+///
+/// The server only relays datagrams from peers for which a permission has been
+/// created, either with [`create_permission`](Self::create_permission) or
+/// [`bind_channel`](Self::bind_channel). Allocations, permissions and channels
+/// expire unless they are refreshed.
+#[derive(Debug)]
+pub struct Allocation {
+    socket: UdpSocket,
+    server: SocketAddr,
+    username: Username,
+    realm: Realm,
+    nonce: Nonce,
+    key: [u8; 16],
+    relayed_address: SocketAddr,
+    mapped_address: Option<SocketAddr>,
+    lifetime: Duration,
+    channels: HashMap<SocketAddr, u16>,
+    /// Datagrams from peers received while waiting for a response.
+    received: VecDeque<(Vec<u8>, SocketAddr)>,
+}
+
+impl Allocation {
+    /// Allocates a relayed address on the server.
+    pub async fn new(
+        socket: UdpSocket,
+        server: SocketAddr,
+        credentials: &Credentials,
+    ) -> Result<Self> {
+        let mut message = allocate_request();
+        let tid = message.tid();
+        message.push(Attribute::Software(crate::software()?));
+        let request = message.encode_with_fingerprint();
+
+        // The server challenges the unauthenticated request with its realm and
+        // a nonce.
+        let mut received = VecDeque::new();
+        let (response, _) = exchange(
+            &socket,
+            server,
+            &request,
+            tid,
+            &HashMap::new(),
+            &mut received,
+        )
+        .await?;
+        let error = error_code(&response)?;
+        if error.code() != ErrorCode::UNAUTHENTICATED {
+            return Err(error_response(error).into());
+        }
+        let realm = find(&response, |a| match a {
+            Attribute::Realm(r) => Some(r),
+            _ => None,
+        })
+        .ok_or(StunError::MissingAttribute("REALM"))?;
+        let nonce = find(&response, |a| match a {
+            Attribute::Nonce(n) => Some(n),
+            _ => None,
+        })
+        .ok_or(StunError::MissingAttribute("NONCE"))?;
+
+        let key = long_term_key(&credentials.username, realm.as_str(), &credentials.password);
+        let mut allocation = Self {
+            socket,
+            server,
+            username: Username::try_from(&credentials.username[..])?,
+            realm,
+            nonce,
+            key,
+            relayed_address: server,
+            mapped_address: None,
+            lifetime: Duration::ZERO,
+            channels: HashMap::new(),
+            received,
+        };
+
+        let response = allocation.request(allocate_request).await?;
+        allocation.relayed_address = find(&response, |a| match a {
+            Attribute::XorRelayedAddress(a) => Some(a.into()),
+            _ => None,
+        })
+        .ok_or(StunError::MissingAttribute("XOR-RELAYED-ADDRESS"))?;
+        allocation.mapped_address = find(&response, |a| match a {
+            Attribute::XorMappedAddress(a) => Some(a.into()),
+            _ => None,
+        });
+        allocation.lifetime = lifetime(&response)?;
+
+        Ok(allocation)
+    }
+
+    /// The address on the server that peers send datagrams to.
+    pub fn relayed_address(&self) -> SocketAddr {
+        self.relayed_address
+    }
+
+    /// The client's address as seen by the server, if the server included it.
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        self.mapped_address
+    }
+
+    /// How long the allocation lasts unless it is refreshed.
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Refreshes the allocation, requesting the given lifetime.
+    ///
+    /// The server may grant a different lifetime.
+    pub async fn refresh(&mut self, requested: Duration) -> Result<()> {
+        let response = self
+            .request(|| {
+                let mut message = Message::new(Type {
+                    class: Class::Request,
+                    method: Method::Refresh,
+                });
+                message.push(Attribute::Lifetime(Lifetime::new(requested)));
+                message
+            })
+            .await?;
+        self.lifetime = lifetime(&response)?;
+        Ok(())
+    }
+
+    /// Deletes the allocation.
+    pub async fn close(mut self) -> Result<()> {
+        self.refresh(Duration::ZERO).await
+    }
+
+    /// Permits the peer to send datagrams to the relayed address.
+    ///
+    /// Permissions last for five minutes, and are refreshed by creating them
+    /// again.
+    pub async fn create_permission(&mut self, peer: IpAddr) -> Result<()> {
+        self.request(|| {
+            let mut message = Message::new(Type {
+                class: Class::Request,
+                method: Method::CreatePermission,
+            });
+            message.push(Attribute::XorPeerAddress(XorPeerAddress::from(peer, 0)));
+            message
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Binds a channel to the peer, which reduces the overhead of exchanging
+    /// datagrams with it, and permits it to send datagrams to the relayed
+    /// address.
+    ///
+    /// Channel bindings last for ten minutes, and are refreshed by binding them
+    /// again.
+    pub async fn bind_channel(&mut self, peer: SocketAddr) -> Result<()> {
+        let number = match self.channels.get(&peer) {
+            Some(number) => *number,
+            None => CHANNEL_NUMBERS
+                .clone()
+                .nth(self.channels.len())
+                .ok_or(StunError::NoChannels)?,
+        };
+
+        self.request(|| {
+            let mut message = Message::new(Type {
+                class: Class::Request,
+                method: Method::ChannelBind,
+            });
+            message.push(Attribute::ChannelNumber(ChannelNumber::new(number)));
+            message.push(Attribute::XorPeerAddress(XorPeerAddress::from(
+                peer.ip(),
+                peer.port(),
+            )));
+            message
+        })
+        .await?;
+
+        self.channels.insert(peer, number);
+        Ok(())
+    }
+
+    /// Sends a datagram to the peer through the relay.
+    ///
+    /// The datagram is sent over the channel bound to the peer, if there is
+    /// one.
+    pub async fn send_to(&self, data: &[u8], peer: SocketAddr) -> Result<()> {
+        let datagram = match self.channels.get(&peer) {
+            Some(number) => {
+                let mut datagram = Vec::with_capacity(4 + data.len());
+                datagram.extend_from_slice(&number.to_be_bytes());
+                datagram.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                datagram.extend_from_slice(data);
+                datagram
+            }
+            None => {
+                let mut message = Message::new(Type {
+                    class: Class::Indication,
+                    method: Method::Send,
+                });
+                message.push(Attribute::XorPeerAddress(XorPeerAddress::from(
+                    peer.ip(),
+                    peer.port(),
+                )));
+                message.push(Attribute::Data(Data(data.to_vec())));
+                message.into()
+            }
+        };
+        let server = socket_address(&self.socket, self.server)?;
+        self.socket.send_to(&datagram, server).await?;
+        Ok(())
+    }
+
+    /// Receives a datagram from a peer through the relay.
+    ///
+    /// If the datagram is too long to fit in the buffer, the excess is
+    /// discarded.
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        let (data, peer) = match self.received.pop_front() {
+            Some(received) => received,
+            None => loop {
+                let mut datagram = vec![0; MAX_DATAGRAM_SIZE];
+                let (len, source) = self.socket.recv_from(&mut datagram).await?;
+                if SocketAddr::new(source.ip().to_canonical(), source.port()) != self.server {
+                    continue;
+                }
+                if let Some(received) = peer_data(&datagram[..len], &self.channels) {
+                    break received;
+                }
+            },
+        };
+
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, peer))
+    }
+
+    /// Sends an authenticated request and waits for its success response.
+    ///
+    /// If the server responds that the nonce is stale, the request is retried
+    /// once with the new nonce.
+    async fn request<F>(&mut self, build: F) -> Result<Message>
+    where
+        F: Fn() -> Message,
+    {
+        let mut retried = false;
+        loop {
+            let mut message = build();
+            let tid = message.tid();
+            message.push(Attribute::Username(self.username.clone()));
+            message.push(Attribute::Realm(self.realm.clone()));
+            message.push(Attribute::Nonce(self.nonce.clone()));
+            let mut request = message.encode_with_integrity(&self.key);
+            Message::append_fingerprint(&mut request);
+
+            let (response, data) = exchange(
+                &self.socket,
+                self.server,
+                &request,
+                tid,
+                &self.channels,
+                &mut self.received,
+            )
+            .await?;
+            match response.ty().class {
+                Class::Success => {
+                    Message::check_integrity(&data, &self.key)?;
+                    return Ok(response);
+                }
+                Class::Error => {
+                    let error = error_code(&response)?;
+                    let nonce = find(&response, |a| match a {
+                        Attribute::Nonce(n) => Some(n),
+                        _ => None,
+                    });
+                    match nonce {
+                        Some(nonce) if error.code() == ErrorCode::STALE_NONCE && !retried => {
+                            self.nonce = nonce;
+                            retried = true;
+                        }
+                        _ => return Err(error_response(error).into()),
+                    }
+                }
+                _ => return Err(StunError::UnexpectedResponse.into()),
+            }
+        }
+    }
+}
+
+fn allocate_request() -> Message {
+    let mut message = Message::new(Type {
+        class: Class::Request,
+        method: Method::Allocate,
+    });
+    message.push(Attribute::RequestedTransport(RequestedTransport::UDP));
+    message
+}
+
+/// Sends the request until its response arrives, returning the decoded and
+/// encoded response.
+///
+/// Datagrams from peers received in the meantime are queued.
+async fn exchange(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    request: &[u8],
+    tid: [u8; 12],
+    channels: &HashMap<SocketAddr, u16>,
+    received: &mut VecDeque<(Vec<u8>, SocketAddr)>,
+) -> Result<(Message, Vec<u8>)> {
+    let accept = |data: &[u8], source| {
+        if source != server {
+            return Ok(None);
+        }
+        if let Some(message) = crate::response(data, tid) {
+            return Ok(Some((message, data.to_vec())));
+        }
+        if let Some(peer_data) = peer_data(data, channels) {
+            received.push_back(peer_data);
+        }
+        Ok(None)
+    };
+    transaction(socket, server, request, &Retransmission::default(), accept)
+        .await?
+        .ok_or_else(|| StunError::Timeout.into())
+}
+
+/// Extracts the data and peer address from a Data indication or a ChannelData
+/// message.
+fn peer_data(
+    datagram: &[u8],
+    channels: &HashMap<SocketAddr, u16>,
+) -> Option<(Vec<u8>, SocketAddr)> {
+    // The first two bits of a ChannelData message are 01, whereas they are 00
+    // for STUN messages.
+    if datagram.len() >= 4 && datagram[0] >> 6 == 1 {
+        let number = u16::from_be_bytes([datagram[0], datagram[1]]);
+        let len = u16::from_be_bytes([datagram[2], datagram[3]]) as usize;
+        let data = datagram.get(4..4 + len)?;
+        let peer = channels
+            .iter()
+            .find(|(_, n)| **n == number)
+            .map(|(peer, _)| *peer)?;
+        return Some((data.to_vec(), peer));
+    }
+
+    let message = Message::try_from(datagram).ok()?;
+    if message.ty()
+        != (Type {
+            class: Class::Indication,
+            method: Method::Data,
+        })
+    {
+        return None;
+    }
+    let peer = find(&message, |a| match a {
+        Attribute::XorPeerAddress(a) => Some(a.into()),
+        _ => None,
+    })?;
+    let data = find(&message, |a| match a {
+        Attribute::Data(d) => Some(d.0),
+        _ => None,
+    })?;
+    Some((data, peer))
+}
+
+/// Returns the first attribute for which the function returns a value.
+fn find<T, F>(message: &Message, f: F) -> Option<T>
+where
+    F: FnMut(Attribute) -> Option<T>,
+{
+    message.attrs().into_iter().find_map(f)
+}
+
+fn error_code(message: &Message) -> Result<ErrorCode> {
+    if message.ty().class != Class::Error {
+        return Err(StunError::UnexpectedResponse.into());
+    }
+    Ok(find(message, |a| match a {
+        Attribute::ErrorCode(e) => Some(e),
+        _ => None,
+    })
+    .ok_or(StunError::MissingAttribute("ERROR-CODE"))?)
+}
+
+fn error_response(error: ErrorCode) -> StunError {
+    StunError::ErrorResponse {
+        code: error.code(),
+        reason: error.reason().to_owned(),
+    }
+}
+
+fn lifetime(message: &Message) -> Result<Duration> {
+    Ok(find(message, |a| match a {
+        Attribute::Lifetime(l) => Some(l.duration()),
+        _ => None,
+    })
+    .ok_or(StunError::MissingAttribute("LIFETIME"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::{XorMappedAddress, XorRelayedAddress};
+
+    const REALM: &str = "memorage.org";
+    const PASSWORD: &str = "hunter2";
+
+    fn credentials() -> Credentials {
+        Credentials {
+            username: "memorage".to_owned(),
+            password: PASSWORD.to_owned(),
+        }
+    }
+
+    fn reply(request: &Message, class: Class) -> Message {
+        Message::with_tid(
+            Type {
+                class,
+                method: request.ty().method,
+            },
+            request.tid(),
+        )
+    }
+
+    fn error(request: &Message, code: u16, nonce: &str) -> Vec<u8> {
+        let mut message = reply(request, Class::Error);
+        message.push(Attribute::ErrorCode(ErrorCode::new(code, "error")));
+        message.push(Attribute::Realm(Realm::try_from(REALM).unwrap()));
+        message.push(Attribute::Nonce(Nonce::try_from(nonce).unwrap()));
+        message.into()
+    }
+
+    /// A minimal TURN server, which relays datagrams between a single client
+    /// and its peers. The first refresh is rejected with a stale nonce.
+    async fn server(socket: UdpSocket) {
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relayed_address = relay.local_addr().unwrap();
+        let key = long_term_key("memorage", REALM, PASSWORD);
+        let mut nonce = "nonce-1".to_owned();
+        let mut client = None;
+        let mut permissions = Vec::new();
+        let mut channels: HashMap<u16, SocketAddr> = HashMap::new();
+
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let mut relay_buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                result = socket.recv_from(&mut buf) => {
+                    let (len, source) = result.unwrap();
+                    let data = &buf[..len];
+                    client = Some(source);
+
+                    if data[0] >> 6 == 1 {
+                        let number = u16::from_be_bytes([data[0], data[1]]);
+                        relay.send_to(&data[4..], channels[&number]).await.unwrap();
+                        continue;
+                    }
+
+                    let request = Message::try_from(data).unwrap();
+                    if request.ty().class == Class::Indication {
+                        let peer = find(&request, |a| match a {
+                            Attribute::XorPeerAddress(a) => Some(SocketAddr::from(a)),
+                            _ => None,
+                        }).unwrap();
+                        let data = find(&request, |a| match a {
+                            Attribute::Data(d) => Some(d.0),
+                            _ => None,
+                        }).unwrap();
+                        relay.send_to(&data, peer).await.unwrap();
+                        continue;
+                    }
+
+                    let request_nonce = find(&request, |a| match a {
+                        Attribute::Nonce(n) => Some(n),
+                        _ => None,
+                    });
+                    let response = match request_nonce {
+                        None => error(&request, ErrorCode::UNAUTHENTICATED, &nonce),
+                        Some(_) if Message::check_integrity(data, &key).is_err() => {
+                            error(&request, ErrorCode::UNAUTHENTICATED, &nonce)
+                        }
+                        Some(n) if n.as_str() != nonce => {
+                            error(&request, ErrorCode::STALE_NONCE, &nonce)
+                        }
+                        Some(_) if request.ty().method == Method::Refresh && nonce == "nonce-1" => {
+                            nonce = "nonce-2".to_owned();
+                            error(&request, ErrorCode::STALE_NONCE, &nonce)
+                        }
+                        Some(_) => {
+                            let mut response = reply(&request, Class::Success);
+                            match request.ty().method {
+                                Method::Allocate => {
+                                    response.push(Attribute::XorRelayedAddress(
+                                        XorRelayedAddress::from(
+                                            relayed_address.ip(),
+                                            relayed_address.port(),
+                                        ),
+                                    ));
+                                    response.push(Attribute::XorMappedAddress(
+                                        XorMappedAddress::from(source.ip(), source.port()),
+                                    ));
+                                    response.push(Attribute::Lifetime(Lifetime::new(
+                                        Duration::from_secs(600),
+                                    )));
+                                }
+                                Method::Refresh => {
+                                    let lifetime = find(&request, |a| match a {
+                                        Attribute::Lifetime(l) => Some(l),
+                                        _ => None,
+                                    }).unwrap();
+                                    response.push(Attribute::Lifetime(lifetime));
+                                }
+                                Method::CreatePermission => {
+                                    let peer = find(&request, |a| match a {
+                                        Attribute::XorPeerAddress(a) => Some(SocketAddr::from(a)),
+                                        _ => None,
+                                    }).unwrap();
+                                    permissions.push(peer.ip());
+                                }
+                                Method::ChannelBind => {
+                                    let peer = find(&request, |a| match a {
+                                        Attribute::XorPeerAddress(a) => Some(SocketAddr::from(a)),
+                                        _ => None,
+                                    }).unwrap();
+                                    let number = find(&request, |a| match a {
+                                        Attribute::ChannelNumber(n) => Some(n.number()),
+                                        _ => None,
+                                    }).unwrap();
+                                    permissions.push(peer.ip());
+                                    channels.insert(number, peer);
+                                }
+                                _ => unreachable!(),
+                            }
+                            response.encode_with_integrity(&key)
+                        }
+                    };
+                    socket.send_to(&response, source).await.unwrap();
+                }
+                result = relay.recv_from(&mut relay_buf) => {
+                    let (len, peer) = result.unwrap();
+                    if !permissions.contains(&peer.ip()) {
+                        continue;
+                    }
+                    let data = &relay_buf[..len];
+                    let datagram = match channels.iter().find(|(_, p)| **p == peer) {
+                        Some((number, _)) => {
+                            let mut datagram = number.to_be_bytes().to_vec();
+                            datagram.extend_from_slice(&(len as u16).to_be_bytes());
+                            datagram.extend_from_slice(data);
+                            datagram
+                        }
+                        None => {
+                            let mut message = Message::new(Type {
+                                class: Class::Indication,
+                                method: Method::Data,
+                            });
+                            message.push(Attribute::XorPeerAddress(XorPeerAddress::from(
+                                peer.ip(),
+                                peer.port(),
+                            )));
+                            message.push(Attribute::Data(Data(data.to_vec())));
+                            message.into()
+                        }
+                    };
+                    socket.send_to(&datagram, client.unwrap()).await.unwrap();
+                }
+            }
+        }
+    }
+
+    async fn recv(allocation: &mut Allocation) -> (Vec<u8>, SocketAddr) {
+        let mut buf = [0; 64];
+        let (len, peer) = allocation.recv_from(&mut buf).await.unwrap();
+        (buf[..len].to_vec(), peer)
+    }
+
+    #[tokio::test]
+    async fn relay_through_allocation() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        tokio::spawn(server(server_socket));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_address = socket.local_addr().unwrap();
+        let mut allocation = Allocation::new(socket, server_address, &credentials())
+            .await
+            .unwrap();
+        assert_eq!(allocation.mapped_address(), Some(local_address));
+        assert_eq!(allocation.lifetime(), Duration::from_secs(600));
+        let relayed_address = allocation.relayed_address();
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_address = peer.local_addr().unwrap();
+        let mut buf = [0; 64];
+
+        // Send and Data indications.
+        allocation
+            .create_permission(peer_address.ip())
+            .await
+            .unwrap();
+        allocation.send_to(b"hello", peer_address).await.unwrap();
+        let (len, source) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!((&buf[..len], source), (&b"hello"[..], relayed_address));
+
+        peer.send_to(b"world", relayed_address).await.unwrap();
+        assert_eq!(
+            recv(&mut allocation).await,
+            (b"world".to_vec(), peer_address)
+        );
+
+        // ChannelData messages.
+        allocation.bind_channel(peer_address).await.unwrap();
+        allocation.send_to(b"channel", peer_address).await.unwrap();
+        let (len, source) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!((&buf[..len], source), (&b"channel"[..], relayed_address));
+
+        peer.send_to(b"data", relayed_address).await.unwrap();
+        assert_eq!(
+            recv(&mut allocation).await,
+            (b"data".to_vec(), peer_address)
+        );
+
+        // The server rejects the first refresh with a stale nonce.
+        allocation.refresh(Duration::from_secs(300)).await.unwrap();
+        assert_eq!(allocation.lifetime(), Duration::from_secs(300));
+        allocation.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_password() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server_socket.local_addr().unwrap();
+        tokio::spawn(server(server_socket));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let credentials = Credentials {
+            password: "hunter3".to_owned(),
+            ..credentials()
+        };
+        let result = Allocation::new(socket, server_address, &credentials).await;
+        assert!(matches!(
+            result,
+            Err(crate::Error::Stun(StunError::ErrorResponse {
+                code: 401,
+                ..
+            }))
+        ));
+    }
+}